@@ -0,0 +1,58 @@
+use crate::builder::TEMPLATE_EXTENSION;
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::state::{read_journal, unix_time, write_journal, JournalEntry};
+use crate::Config;
+use std::path::{absolute, Path, PathBuf};
+use tokio::fs::{copy, create_dir_all, remove_file, rename, symlink_metadata};
+
+/// Move the existing file at `target` into the template tree, at the path which is linked back to
+/// it, and as a template if `template` is set.
+///
+/// Returns the path of the file in the tree, which is left for the caller to build and link.
+pub async fn add_target(cfg: &Config, target: &Path, template: bool) -> Result<PathBuf, Error> {
+    let target = absolute(target).with_location(target)?;
+
+    let meta = symlink_metadata(&target).await.with_location(&target)?;
+    if !meta.is_file() {
+        return Err(InnerError::NotRegularFile.with_location(&target));
+    }
+
+    let Ok(relative) = target.strip_prefix(&cfg.link_dir) else {
+        return Err(InnerError::OutsideLinkDir.with_location(&target));
+    };
+
+    let mut source = relative.to_path_buf();
+    if template {
+        source
+            .as_mut_os_string()
+            .push(format!(".{TEMPLATE_EXTENSION}"));
+    }
+
+    let source_path = cfg.template_dir.join(&source);
+    if source_path.exists() {
+        return Err(InnerError::AlreadyExists.with_location(&source_path));
+    }
+
+    if let Some(parent) = source_path.parent() {
+        create_dir_all(parent).await.with_location(parent)?;
+    }
+
+    info!("moving {target:?} to {source_path:?}");
+    // the template dir may be on another file system
+    if rename(&target, &source_path).await.is_err() {
+        copy(&target, &source_path).await.with_location(&target)?;
+        remove_file(&target).await.with_location(&target)?;
+    }
+
+    let mut journal = read_journal(cfg).await?;
+    journal.entries.push(JournalEntry {
+        at: unix_time(),
+        action: "add".to_string(),
+        target,
+        source: source.to_string_lossy().into_owned(),
+        backup: None,
+    });
+    write_journal(cfg, &journal).await?;
+
+    Ok(source)
+}
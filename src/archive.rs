@@ -0,0 +1,61 @@
+use crate::error::{Error, ErrorLocation};
+use crate::private::{is_decrypted, PRIVATE_DIR};
+use crate::state::read_sources;
+use crate::Config;
+use std::io;
+use std::path::Path;
+use tokio::fs::create_dir_all;
+use tokio::process::Command;
+
+/// Write a gzipped tarball of the build tree, except for the private directory and other
+/// decrypted files, to stdout.
+pub async fn write_archive(cfg: &Config) -> Result<(), Error> {
+    let mut cmd = Command::new("tar");
+    cmd.arg("-C")
+        .arg(&cfg.build_dir)
+        .arg("--no-wildcards")
+        .arg(format!("--exclude=./{PRIVATE_DIR}"));
+
+    let sources = read_sources(cfg).await?;
+    for output in sources.keys() {
+        if is_decrypted(&sources, Path::new(output)) {
+            cmd.arg(format!("--exclude=./{output}"));
+        }
+    }
+
+    let status = cmd
+        .args(["-czf", "-", "."])
+        .status()
+        .await
+        .with_location(&cfg.build_dir)?;
+
+    if !status.success() {
+        let e = io::Error::other(format!("tar exited with {status}"));
+        return Err(e.with_location(&cfg.build_dir));
+    }
+
+    Ok(())
+}
+
+/// Unpack a tarball created by [write_archive] into the build tree.
+pub async fn extract_archive(cfg: &Config, archive: &Path) -> Result<(), Error> {
+    create_dir_all(&cfg.build_dir)
+        .await
+        .with_location(&cfg.build_dir)?;
+
+    let status = Command::new("tar")
+        .arg("-C")
+        .arg(&cfg.build_dir)
+        .arg("-xzf")
+        .arg(archive)
+        .status()
+        .await
+        .with_location(archive)?;
+
+    if !status.success() {
+        let e = io::Error::other(format!("tar exited with {status}"));
+        return Err(e.with_location(archive));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::state::unix_time;
+use crate::Config;
+use clap::Subcommand;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs::{copy, create_dir, create_dir_all, read_dir, read_to_string, remove_file, write};
+use tokio::sync::Mutex;
+
+/// Name of the directory in the state dir where replaced and removed files are kept, in a
+/// directory per run named after when it started, or the first free second after it.
+pub const BACKUP_DIR: &str = "backups";
+
+/// Name of the directory in a backup directory with the backed up files.
+const FILES_DIR: &str = "files";
+
+/// Name of the file in a backup directory which lists where each of the files in it was.
+const ORIGINS_FILE: &str = "origins.toml";
+
+/// Where each file in a backup was, by its path in the files directory.
+type Origins = BTreeMap<String, PathBuf>;
+
+/// Held while the origins file of a backup is updated, as files are linked concurrently.
+static ORIGINS: Mutex<()> = Mutex::const_new(());
+
+#[derive(Subcommand)]
+pub enum BackupAction {
+    /// List the backups and the files in them.
+    List,
+
+    /// Copy the files in a backup back to where they were.
+    Restore {
+        /// The backup to restore from, as shown by list.
+        id: u64,
+
+        /// Only restore this file.
+        path: Option<PathBuf>,
+    },
+}
+
+/// Make a new directory for the backups made by this run.
+pub async fn new_backup_dir(cfg: &Config) -> Result<PathBuf, Error> {
+    let parent = cfg.state_dir.join(BACKUP_DIR);
+    create_dir_all(&parent).await.with_location(&parent)?;
+
+    // another run may have started in the same second
+    for id in unix_time().. {
+        let dir = parent.join(id.to_string());
+        match create_dir(&dir).await {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.with_location(&dir)),
+        }
+    }
+
+    unreachable!("ran out of backup directory names")
+}
+
+/// Where the file at `link_path` is kept in the backup directory `backup_dir`.
+pub fn backup_path(cfg: &Config, backup_dir: &Path, link_path: &Path) -> PathBuf {
+    let relative = link_path
+        .strip_prefix(&cfg.link_dir)
+        .or_else(|_| link_path.strip_prefix("/"))
+        .unwrap_or(link_path);
+
+    backup_dir.join(FILES_DIR).join(relative)
+}
+
+/// Remember that the file at `backup_path` in the backup directory `backup_dir` was at
+/// `link_path`, so that it's restored there rather than into the link dir.
+pub async fn record_backup(
+    backup_dir: &Path,
+    backup_path: &Path,
+    link_path: &Path,
+) -> Result<(), Error> {
+    let relative = backup_path
+        .strip_prefix(backup_dir.join(FILES_DIR))
+        .unwrap_or(backup_path);
+
+    let _guard = ORIGINS.lock().await;
+    let mut origins = read_origins(backup_dir).await?;
+    origins.insert(relative.to_string_lossy().into_owned(), link_path.into());
+
+    let path = backup_dir.join(ORIGINS_FILE);
+    let s = toml::to_string(&origins).with_location(&path)?;
+    write(&path, s).await.with_location(&path)
+}
+
+/// Where each file in the backup directory `dir` was.
+async fn read_origins(dir: &Path) -> Result<Origins, Error> {
+    let path = dir.join(ORIGINS_FILE);
+
+    debug!("trying to read {:?}", path);
+    match read_to_string(&path).await {
+        Ok(s) => toml::de::from_str(&s).with_location(&path),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Origins::new()),
+        Err(e) => Err(e.with_location(&path)),
+    }
+}
+
+/// The files in the backup directory `dir`, along with where each of them was.
+async fn backed_up_files(cfg: &Config, dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>, Errors> {
+    // the directory is made up front, so a run might not have backed up anything into it
+    let files_dir = dir.join(FILES_DIR);
+    if !files_dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let origins = read_origins(dir).await?;
+    let files = list_files(&files_dir).await?;
+    Ok(files
+        .into_iter()
+        .map(|relative| {
+            let origin = match origins.get(&*relative.to_string_lossy()) {
+                Some(origin) => origin.clone(),
+                None => cfg.link_dir.join(&relative),
+            };
+            (files_dir.join(relative), origin)
+        })
+        .collect())
+}
+
+pub async fn list_backups(cfg: &Config) -> Result<(), Errors> {
+    let dir = cfg.state_dir.join(BACKUP_DIR);
+    let mut entries = match read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.with_location(&dir).into()),
+    };
+
+    let mut ids = vec![];
+    while let Some(entry) = entries.next_entry().await.with_location(&dir)? {
+        if let Ok(id) = entry.file_name().to_string_lossy().parse::<u64>() {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+
+    for id in ids {
+        let files = backed_up_files(cfg, &dir.join(id.to_string())).await?;
+        if files.is_empty() {
+            continue;
+        }
+
+        println!("{id}:");
+        for (_, origin) in files {
+            println!("  {}", origin.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the files in the backup `id`, or only the one which was at `only`, back to where they
+/// were.
+pub async fn restore_backup(cfg: &Config, id: u64, only: Option<&Path>) -> Result<(), Errors> {
+    let dir = cfg.state_dir.join(BACKUP_DIR).join(id.to_string());
+    if !dir.is_dir() {
+        return Err(InnerError::UnknownBackup(id).with_location(&dir).into());
+    }
+
+    for (backup, link_path) in backed_up_files(cfg, &dir).await? {
+        let relative = link_path.strip_prefix(&cfg.link_dir).unwrap_or(&link_path);
+        if only.is_some_and(|only| only != link_path && only != relative) {
+            continue;
+        }
+
+        // whatever replaced the file, usually a link into the build tree
+        match remove_file(&link_path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.with_location(&link_path).into()),
+        }
+
+        if let Some(parent) = link_path.parent() {
+            create_dir_all(parent).await.with_location(parent)?;
+        }
+
+        info!("restoring {link_path:?}");
+        copy(&backup, &link_path).await.with_location(&backup)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use crate::target::{LocalFs, Target};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, DirBuilderExt};
+use std::path::{Path, PathBuf};
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
+
+/// How many filesystem operations [BatchedFs] runs at once.
+///
+/// The blocking pool allows hundreds of threads, which only contend with each other on the
+/// filesystem.
+static PERMITS: Semaphore = Semaphore::const_new(32);
+
+/// The local filesystem, where each operation of the linker on a file, such as backing it up or
+/// replacing it with a link, runs as a single blocking task instead of as a chain of awaits on
+/// single syscalls.
+pub struct BatchedFs;
+
+/// Run `f` on the blocking pool, once there is a free permit.
+async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    let _permit = PERMITS.acquire().await.map_err(io::Error::other)?;
+    spawn_blocking(f).await.map_err(io::Error::other)?
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+impl Target for BatchedFs {
+    fn create_dir<'a>(
+        &'a self,
+        path: &'a Path,
+        mode: Option<u32>,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        let path = path.to_path_buf();
+        blocking(move || {
+            let mut builder = fs::DirBuilder::new();
+            if let Some(mode) = mode {
+                builder.mode(mode);
+            }
+            builder.create(path)
+        })
+        .boxed()
+    }
+
+    fn is_symlink<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        let path = path.to_path_buf();
+        blocking(move || match fs::symlink_metadata(path) {
+            Ok(meta) => Ok(meta.file_type().is_symlink()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        })
+        .boxed()
+    }
+
+    fn read_link<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<PathBuf>>> {
+        let path = path.to_path_buf();
+        blocking(move || match fs::read_link(path) {
+            Ok(original) => Ok(Some(original)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => Ok(None),
+            Err(e) => Err(e),
+        })
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        let path = path.to_path_buf();
+        blocking(move || fs::remove_file(path)).boxed()
+    }
+
+    fn symlink<'a>(&'a self, original: &'a Path, link: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        let (original, link) = (original.to_path_buf(), link.to_path_buf());
+        blocking(move || symlink(original, link)).boxed()
+    }
+
+    fn copy_file<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        blocking(move || fs::copy(from, to).map(|_| ())).boxed()
+    }
+
+    fn replace_with_symlink<'a>(
+        &'a self,
+        original: &'a Path,
+        link: &'a Path,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        let (original, link) = (original.to_path_buf(), link.to_path_buf());
+        blocking(move || {
+            remove_if_exists(&link)?;
+            symlink(original, link)
+        })
+        .boxed()
+    }
+
+    fn replace_with_copy<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        blocking(move || {
+            remove_if_exists(&to)?;
+            fs::copy(from, to).map(|_| ())
+        })
+        .boxed()
+    }
+
+    fn back_up<'a>(&'a self, path: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        let (path, to) = (path.to_path_buf(), to.to_path_buf());
+        blocking(move || {
+            match fs::symlink_metadata(&path) {
+                Ok(meta) if meta.is_file() => {}
+                Ok(_) => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e),
+            }
+
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // the backup dir may be on another file system
+            if fs::rename(&path, &to).is_err() {
+                fs::copy(&path, &to)?;
+                fs::remove_file(&path)?;
+            }
+
+            Ok(true)
+        })
+        .boxed()
+    }
+
+    fn get_acl<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<String>>> {
+        LocalFs.get_acl(path)
+    }
+
+    fn set_acl<'a>(&'a self, path: &'a Path, acl: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        LocalFs.set_acl(path, acl)
+    }
+
+    fn restore_context<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        LocalFs.restore_context(path)
+    }
+}
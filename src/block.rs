@@ -0,0 +1,28 @@
+/// Whether `content` contains the block of lines managed by this tool named `name`.
+pub fn has_block(content: &str, name: &str) -> bool {
+    content.contains(&format!("# BEGIN dotfiles managed {name}"))
+}
+
+/// Replace the block of lines managed by this tool named `name` in `content` with `block`, or
+/// append it if there is none. All lines outside of the block are left alone.
+pub fn replace_block(content: &str, name: &str, block: &str) -> String {
+    let begin = format!("# BEGIN dotfiles managed {name}");
+    let end = format!("# END dotfiles managed {name}");
+    let managed = format!("{begin}\n{block}{end}\n");
+
+    match (content.find(&begin), content.find(&end)) {
+        (Some(start), Some(stop)) if start < stop => {
+            let rest = &content[stop + end.len()..];
+            let rest = rest.strip_prefix('\n').unwrap_or(rest);
+            format!("{}{managed}{rest}", &content[..start])
+        }
+        _ => {
+            let mut content = content.to_string();
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            content.push_str(&managed);
+            content
+        }
+    }
+}
@@ -1,102 +1,893 @@
-use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::condition::{condition_variables, evaluate};
+use crate::dirsettings::{read_dir_settings, DIR_SETTINGS_FILE};
+use crate::error::{Error, ErrorLocation, ErrorOperation, Errors, InnerError, Operation};
+use crate::frontmatter::{split_front_matter, FrontMatter};
+use crate::ignore::read_ignores;
+use crate::lock::{read_lock, read_lock_key, settings_hash, Changes};
+use crate::peeker::read_docs;
+use crate::permissions::parse_mode;
+use crate::private::{
+    decrypt, decrypt_as, is_encrypted, is_private, sniff_encryption, ENCRYPTED_EXTENSION,
+    GPG_EXTENSION, PRIVATE_DIR_MODE, PRIVATE_FILE_MODE,
+};
+use crate::profile::{generate_profiles, PROFILE_DIR};
+use crate::progress::Progress;
+use crate::redact::{mark_sensitive, redact};
+use crate::secrets::{read_secrets, Secrets};
+use crate::ssh::generate_ssh_config;
+use crate::state::{
+    read_link_targets, read_outputs, read_sources, write_link_targets, write_outputs,
+    write_sources, LinkTargets, Outputs, Sources,
+};
+use crate::tplcache::{parsed_template, template_variables};
+use crate::warnings::warning;
 use crate::Config;
 use async_recursion::async_recursion;
-use blueprint::{parse_template, Env, Value};
+use blueprint::{Env, Value};
 use futures::future::join_all;
-use futures::TryFutureExt;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
 use std::env;
 use std::ffi::OsStr;
-use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::str::from_utf8;
-use tokio::fs::{copy, create_dir, read_dir, read_to_string, File};
+use std::fs::Permissions;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs::{
+    canonicalize, copy, create_dir_all, metadata, read, read_dir, read_to_string, remove_file,
+    set_permissions, symlink_metadata, DirBuilder, File, OpenOptions,
+};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::join;
-use tokio::process::Command;
+use unicode_normalization::UnicodeNormalization;
 
 pub const TEMPLATE_EXTENSION: &str = "tpl";
 
+/// Name of the file next to the variables file with variables that shouldn't be stored in plain
+/// text, usually encrypted with age or gpg.
+const SECRET_VARIABLES_FILE: &str = "variables.secret.toml";
+
+/// Name of the directory next to the variables file with variables for specific machines, see
+/// [read_variables].
+const VARIABLES_OVERRIDES_DIR: &str = "variables.d";
+
+/// Prefix of the environment variables which variables fall back to with `--env-vars`.
+const ENV_VAR_PREFIX: &str = "DOTFILES_VAR_";
+
+/// Key in variables files with the other variables files they include.
+const INCLUDE_KEY: &str = "include";
+
+/// Table in the variables file with sets of variables which `--profile` selects from.
+const PROFILES_TABLE: &str = "profile";
+
+/// Name of the directory in the state dir with the state and the build tree of the shared tree.
+const SHARED_STATE_DIR: &str = "shared";
+
+/// Name of the directory in the shared state dir which the shared tree is built into.
+const SHARED_BUILD_DIR: &str = "build";
+
+/// Variables that are always detected from the current machine.
+pub const FACTS: &[&str] = &[
+    "hostname",
+    "fqdn",
+    "username",
+    "os",
+    "wsl",
+    "windows_user",
+    "windows_home",
+    "termux",
+];
+
+/// Everything templates are rendered with.
+struct Context {
+    env: Env,
+
+    /// List variables, which can only be used to render a template once per item.
+    lists: HashMap<String, Vec<String>>,
+
+    /// Outputs of multi-output templates in the previous build.
+    previous_outputs: Outputs,
+
+    /// Outputs of multi-output templates in this build.
+    outputs: Mutex<Outputs>,
+
+    /// What each file in the build tree was made from.
+    sources: Mutex<Sources>,
+
+    /// Where files which set a target in their front matter are linked.
+    link_targets: Mutex<LinkTargets>,
+
+    progress: Progress,
+
+    /// What changed since the last sync, if there was one.
+    changes: Option<Changes>,
+
+    /// Variables used by the templates built so far.
+    used: Mutex<BTreeSet<String>>,
+
+    /// Secrets, which aren't in `env` until a template uses them.
+    secrets: Secrets,
+}
+
+impl Context {
+    /// Remember what the file at `output_path` in the build tree was made from.
+    fn record(&self, cfg: &Config, output_path: &Path, source: impl Into<String>) {
+        let output = output_path
+            .strip_prefix(&cfg.build_dir)
+            .unwrap_or(output_path);
+
+        self.sources
+            .lock()
+            .unwrap()
+            .insert(output.to_string_lossy().into_owned(), source.into());
+    }
+
+    /// Remember that the template `body` uses its variables, and `condition` its variables.
+    fn use_variables(&self, path: &Path, body: &str, condition: Option<&str>) -> Result<(), Error> {
+        let mut used = template_variables(body).with_location(path)?;
+        if let Some(condition) = condition {
+            used.extend(condition_variables(condition).with_location(path)?);
+        }
+
+        self.used.lock().unwrap().extend(used);
+        Ok(())
+    }
+
+    /// The env to render the template `body` with, which includes the secrets it uses.
+    async fn env_for(&self, cfg: &Config, path: &Path, body: &str) -> Result<Cow<'_, Env>, Error> {
+        let variables = template_variables(body).with_location(path)?;
+        if !self.secrets.any(&variables) {
+            return Ok(Cow::Borrowed(&self.env));
+        }
+
+        let mut env = self.env.clone();
+        self.secrets.resolve(cfg, &variables, &mut env).await?;
+        Ok(Cow::Owned(env))
+    }
+
+    /// Remember where the file at `output_path` in the build tree is linked, if it's not where it
+    /// usually would be.
+    fn record_target(&self, cfg: &Config, output_path: &Path, target: Option<&Path>) {
+        let output = output_path
+            .strip_prefix(&cfg.build_dir)
+            .unwrap_or(output_path)
+            .to_string_lossy()
+            .into_owned();
+
+        let mut link_targets = self.link_targets.lock().unwrap();
+        match target {
+            Some(target) => link_targets.insert(output, cfg.link_dir.join(target)),
+            None => link_targets.remove(&output),
+        };
+    }
+}
+
+/// Build the tree, on top of the shared tree if there is one.
+///
+/// The shared tree is built first, with its own state and into its own build dir, and the files
+/// it built which the template dir doesn't replace are copied into the build dir after the
+/// template dir is built, so that files in the template dir take precedence over the ones at the
+/// same path in the shared tree. Each user still has their own build and link dirs.
 pub async fn build_tree(cfg: &Config) -> Result<(), Errors> {
+    let Some(shared_dir) = &cfg.shared_dir else {
+        let used = build_layer(cfg).await?;
+        warn_unused_flags(cfg, &used);
+        return Ok(());
+    };
+
+    let shared_state_dir = cfg.state_dir.join(SHARED_STATE_DIR);
+    let shared = Config {
+        template_dir: shared_dir.clone(),
+        shared_dir: None,
+        build_dir: shared_state_dir.join(SHARED_BUILD_DIR),
+        state_dir: shared_state_dir,
+        ignore: read_ignores(shared_dir).await?,
+        ..cfg.clone()
+    };
+
+    info!("building shared tree {:?}", shared_dir);
+    create_dir_all(&shared.state_dir)
+        .await
+        .with_location(&shared.state_dir)?;
+    let shared_result = build_layer(&shared).await;
+    let result = build_layer(cfg).await;
+
+    // files from the shared tree which the template dir doesn't replace are still ours to track
+    let mut sources = read_sources(cfg).await?;
+    let mut link_targets = read_link_targets(cfg).await?;
+    let shared_link_targets = read_link_targets(&shared).await?;
+    for (output, source) in read_sources(&shared).await? {
+        // the sources of the template dir are relative, the ones of the shared tree aren't
+        let replaced = sources
+            .get(&output)
+            .is_some_and(|source| Path::new(source).is_relative());
+        if replaced {
+            continue;
+        }
+
+        overlay(&shared, cfg, Path::new(&output)).await?;
+
+        match shared_link_targets.get(&output) {
+            Some(target) => link_targets.insert(output.clone(), target.clone()),
+            None => link_targets.remove(&output),
+        };
+        let source = shared_dir.join(source).to_string_lossy().into_owned();
+        sources.insert(output, source);
+    }
+    write_sources(cfg, &sources).await?;
+    write_link_targets(cfg, &link_targets).await?;
+
+    match (shared_result, result) {
+        (Ok(mut used), Ok(more_used)) => {
+            used.extend(more_used);
+            warn_unused_flags(cfg, &used);
+            Ok(())
+        }
+        (Err(errors), Ok(_)) | (Ok(_), Err(errors)) => Err(errors),
+        (Err(mut errors), Err(more_errors)) => {
+            errors.join(more_errors);
+            Err(errors)
+        }
+    }
+}
+
+/// Copy the file at `relative` in the build dir of the `shared` tree into the build dir of `cfg`.
+async fn overlay(shared: &Config, cfg: &Config, relative: &Path) -> Result<(), Error> {
+    let from = shared.build_dir.join(relative);
+    let to = cfg.build_dir.join(relative);
+
+    if let Some(parent) = to.parent() {
+        let mut dir_builder = DirBuilder::new();
+        dir_builder.recursive(true);
+        if is_private(relative) {
+            dir_builder.mode(PRIVATE_DIR_MODE);
+        }
+        dir_builder.create(parent).await.with_location(parent)?;
+    }
+
+    trace!("copying {from:?} -> {to:?}");
+    copy(&from, &to)
+        .await
+        .with_location(&from)
+        .during(Operation::Copy)?;
+    Ok(())
+}
+
+/// Warn about the flags which none of the `used` variables are, after a full build.
+fn warn_unused_flags(cfg: &Config, used: &BTreeSet<String>) {
+    // only a full build uses every variable that will be used
+    if cfg.include.is_some() {
+        return;
+    }
+
+    let names = cfg.flags.iter().map(|flag| parse_flag(flag).0);
+    for flag in names.filter(|name| !used.contains(*name)) {
+        warning(
+            &cfg.template_dir,
+            format!("flag {flag:?} isn't used by any template"),
+        );
+    }
+}
+
+/// Build a single tree into the build dir, returning the variables its templates use.
+async fn build_layer(cfg: &Config) -> Result<BTreeSet<String>, Errors> {
+    // a partial build only replaces the sources of the files it builds
+    let (sources, link_targets) = match cfg.include {
+        Some(_) => (read_sources(cfg).await?, read_link_targets(cfg).await?),
+        None => (Sources::new(), LinkTargets::new()),
+    };
+
+    let env = build_env(cfg).await?;
+    let key = read_lock_key(cfg).await?;
+    let settings = settings_hash(cfg).await?;
+    let changes = read_lock(cfg)
+        .await
+        .ok()
+        .map(|lock| Changes::since(lock, &env, &key, &settings));
+
+    let ctx = Context {
+        env,
+        lists: read_lists(cfg).await?,
+        previous_outputs: read_outputs(cfg).await?,
+        outputs: Mutex::new(Outputs::new()),
+        sources: Mutex::new(sources),
+        link_targets: Mutex::new(link_targets),
+        progress: Progress::new("built"),
+        changes,
+        used: Mutex::new(BTreeSet::new()),
+        secrets: read_secrets(cfg).await?,
+    };
+
+    dir(cfg, &ctx, PathBuf::new()).await?;
+
+    if let Some(output) = generate_ssh_config(cfg).await? {
+        ctx.record(cfg, &output, "[ssh] in the variables file");
+    }
+
+    for output in generate_profiles(cfg).await? {
+        ctx.record(cfg, &output, PROFILE_DIR);
+    }
+
+    // remove the outputs of multi-output templates that no longer exist
+    let outputs = ctx.outputs.into_inner().unwrap();
+    for (template, previous) in &ctx.previous_outputs {
+        if !outputs.contains_key(template) {
+            for output in previous {
+                prune(cfg, &cfg.build_dir.join(output)).await?;
+            }
+        }
+    }
+
+    write_outputs(cfg, &outputs).await?;
+    write_sources(cfg, &ctx.sources.into_inner().unwrap()).await?;
+    write_link_targets(cfg, &ctx.link_targets.into_inner().unwrap()).await?;
+    ctx.progress.finish();
+
+    Ok(ctx.used.into_inner().unwrap())
+}
+
+/// Collect the facts, variables and flags that templates are rendered with.
+pub async fn build_env(cfg: &Config) -> Result<Env, Errors> {
+    if cfg.frozen {
+        info!("using the env of the last sync");
+        let lock = read_lock(cfg).await?;
+        let mut env = lock.to_env().with_location(&cfg.state_dir)?;
+
+        // only hashes of sensitive variables are kept, so their values are read again
+        if lock.has_secrets() {
+            let current = detect_env(cfg).await?;
+            let key = read_lock_key(cfg).await?;
+            lock.resolve_secrets(&key, &current, &mut env)
+                .with_location(&cfg.variables_path)?;
+        }
+
+        set_overrides(cfg, &mut env);
+        return Ok(env);
+    }
+
+    detect_env(cfg).await
+}
+
+/// Collect the facts, variables and flags of the current machine or host.
+async fn detect_env(cfg: &Config) -> Result<Env, Errors> {
+    let (fqdn, hostname, os) = identity(cfg);
+
     let mut env = Env::new();
-    env.insert("hostname".into(), Value::Str(get_hostname().await));
+    env.insert("hostname".into(), Value::Str(hostname));
+    env.insert("fqdn".into(), Value::Str(fqdn));
     env.insert("username".into(), Value::Str(get_username()));
-    env.insert("os".into(), Value::Str(get_operating_system().await));
-
-    debug!("trying to read {:?}", cfg.variables_path);
-    if let Ok(s) = read_to_string(&cfg.variables_path).await {
-        debug!("parsing {:?}", cfg.variables_path);
-        let variables: HashMap<String, toml::Value> =
-            toml::de::from_str(&s).with_location(&cfg.variables_path)?;
-
-        for (key, toml_value) in variables {
-            let value = match toml_value {
-                toml::Value::String(s) => Value::Str(s),
-                toml::Value::Boolean(b) => Value::Bool(b),
-                _ => return Err(InnerError::Type.with_location(&cfg.variables_path).into()),
-            };
+    env.insert("os".into(), Value::Str(os));
+
+    // a host from the inventory is never the WSL machine we are running on
+    let wsl = cfg.wsl.as_ref().filter(|_| cfg.host.is_none());
+    env.insert("wsl".into(), Value::Bool(wsl.is_some()));
+    if let Some(wsl) = wsl {
+        env.insert("windows_user".into(), Value::Str(wsl.user.clone()));
+        let home = wsl.home.to_string_lossy().into_owned();
+        env.insert("windows_home".into(), Value::Str(home));
+    }
+
+    let termux = cfg.termux && cfg.host.is_none();
+    env.insert("termux".into(), Value::Bool(termux));
+
+    let docs = read_docs(cfg).await?;
+    let is_sensitive = |key: &str| docs.get(key).is_some_and(|doc| doc.is_sensitive());
 
-            env.insert(key, value);
+    for (key, toml_value) in read_variables(cfg).await? {
+        if is_sensitive(&key) {
+            mark_strings_sensitive(&toml_value);
+        }
+
+        flatten_value(&mut env, key, toml_value);
+    }
+
+    if let Some(host) = &cfg.host {
+        for (key, toml_value) in &host.variables {
+            if is_sensitive(key) {
+                mark_strings_sensitive(toml_value);
+            }
+
+            flatten_value(&mut env, key.clone(), toml_value.clone());
+        }
+
+        for flag in &host.flags {
+            let (name, value) = parse_flag(flag);
+            env.insert(name.to_string(), value);
         }
-    } else {
-        debug!("failed to read {:?}", cfg.variables_path);
+    }
+
+    if cfg.env_vars {
+        set_env_vars(&mut env);
     }
 
     for flag in &cfg.flags {
-        env.insert(flag.to_string(), Value::Bool(true));
+        let (name, value) = parse_flag(flag);
+        env.insert(name.to_string(), value);
     }
 
+    set_overrides(cfg, &mut env);
+
     info!("env:");
     for (k, v) in &env {
-        info!("  {}: {:?}", k, v);
+        info!("  {}: {}", k, redact(&format!("{v:?}")));
     }
 
-    dir(cfg, &env, PathBuf::new()).await
+    Ok(env)
 }
 
+/// Set the variables from `--set`.
+fn set_overrides(cfg: &Config, env: &mut Env) {
+    for (name, value) in &cfg.overrides {
+        env.insert(name.clone(), parse_value(value));
+    }
+}
+
+/// Set the variables which aren't set yet from `DOTFILES_VAR_<NAME>` environment variables, such
+/// that `DOTFILES_VAR_FONT_SIZE=12` sets `font_size`.
+fn set_env_vars(env: &mut Env) {
+    for (name, value) in env::vars() {
+        let Some(name) = name.strip_prefix(ENV_VAR_PREFIX) else {
+            continue;
+        };
+
+        let name = name.to_lowercase();
+        if env.get(&name).is_none() {
+            env.insert(name, parse_value(&value));
+        }
+    }
+}
+
+/// The name and value of a flag, which is either a name which is set to true, or `name=value`
+/// like a variable from `--set`, e.g. `monitor=DP-1`, or `battery=false` to turn off a flag the
+/// variables file turned on.
+pub fn parse_flag(flag: &str) -> (&str, Value) {
+    match flag.split_once('=') {
+        Some((name, value)) => (name, parse_value(value)),
+        None => (flag, Value::Bool(true)),
+    }
+}
+
+/// A variable from the command line or the environment, where `true` and `false` are booleans
+/// and anything else is a string.
+fn parse_value(value: &str) -> Value {
+    match value {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::Str(value.to_string()),
+    }
+}
+
+/// The fully qualified name, hostname and os of the machine the tree is built for.
+fn identity(cfg: &Config) -> (String, String, String) {
+    let fqdn = match (&cfg.host, &cfg.facts.hostname) {
+        (Some(host), _) => host.hostname(),
+        (None, Some(hostname)) => hostname.clone(),
+        (None, None) => cfg.detected.hostname.clone(),
+    };
+
+    // /etc/hostname contains a fully qualified name on some machines and not on others
+    let fqdn = fqdn.trim().trim_end_matches('.').to_lowercase();
+    let fqdn = match (&cfg.detected.fqdn, &cfg.host, &cfg.facts.hostname) {
+        (Some(detected), None, None) if !fqdn.contains('.') => detected.clone(),
+        _ => fqdn,
+    };
+    let hostname = fqdn.split('.').next().unwrap_or_default().to_string();
+
+    let os = match cfg.host.as_ref().and_then(|host| host.os.clone()) {
+        Some(os) => os,
+        None => match &cfg.facts.os {
+            Some(os) => os.clone(),
+            None => cfg.detected.os.clone(),
+        },
+    };
+
+    (fqdn, hostname, os)
+}
+
+pub fn to_value(toml_value: toml::Value) -> Result<Value, InnerError> {
+    match toml_value {
+        toml::Value::String(s) => Ok(Value::Str(s)),
+        toml::Value::Boolean(b) => Ok(Value::Bool(b)),
+        toml::Value::Integer(i) => Ok(Value::Str(i.to_string())),
+        toml::Value::Float(f) => Ok(Value::Str(f.to_string())),
+        toml::Value::Datetime(d) => Ok(Value::Str(d.to_string())),
+        _ => Err(InnerError::Type),
+    }
+}
+
+/// Insert a variable into the env, with the values in tables and arrays under dotted keys.
+///
+/// Numbers are rendered as they are written, `[git] email = "..."` becomes `git.email`, and
+/// `editors = ["vim", "code"]` becomes `editors.0` and `editors.1`, along with `editors.len`.
+/// Arrays are also list variables, which templates can be rendered once per item of, see
+/// [read_lists].
+fn flatten_value(env: &mut Env, key: String, toml_value: toml::Value) {
+    match toml_value {
+        toml::Value::Table(table) => {
+            for (name, value) in table {
+                flatten_value(env, format!("{key}.{name}"), value);
+            }
+        }
+        toml::Value::Array(items) => {
+            env.insert(format!("{key}.len"), Value::Str(items.len().to_string()));
+            for (i, item) in items.into_iter().enumerate() {
+                flatten_value(env, format!("{key}.{i}"), item);
+            }
+        }
+        scalar => {
+            if let Ok(value) = to_value(scalar) {
+                env.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Collect the list variables, which templates can be rendered once per item of.
+async fn read_lists(cfg: &Config) -> Result<HashMap<String, Vec<String>>, Error> {
+    fn to_list(toml_value: toml::Value) -> Result<Vec<String>, InnerError> {
+        match toml_value {
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(|item| match to_value(item)? {
+                    Value::Str(s) => Ok(s),
+                    Value::Bool(b) => Ok(b.to_string()),
+                    _ => Err(InnerError::Type),
+                })
+                .collect(),
+            _ => Err(InnerError::Type),
+        }
+    }
+
+    let mut lists = HashMap::new();
+
+    for (key, toml_value) in read_variables(cfg).await? {
+        if toml_value.is_array() {
+            let list = to_list(toml_value).with_location(&cfg.variables_path)?;
+            lists.insert(key, list);
+        }
+    }
+
+    if let Some(host) = &cfg.host {
+        for (key, toml_value) in &host.variables {
+            if toml_value.is_array() {
+                let list = to_list(toml_value.clone()).with_location(&cfg.inventory_path)?;
+                lists.insert(key.clone(), list);
+            }
+        }
+    }
+
+    Ok(lists)
+}
+
+/// Read the variables file, the secret variables file and the override files next to it, if
+/// there are any.
+///
+/// Later files are merged on top of earlier ones, in this order:
+///
+/// 1. `variables.toml`
+/// 2. `variables.secret.toml`
+/// 3. `variables.d/os-<os>.toml`
+/// 4. `variables.d/hostname-<hostname>.toml`
+/// 5. the `[profile.<name>]` table selected by `--profile`
+///
+/// Tables are merged key by key, other values are replaced. Any of the files may be encrypted
+/// with age or gpg, in which case it's decrypted in memory and the strings in it are never shown
+/// in logs, diffs or errors.
+///
+/// ```toml
+/// email = "me@example.com"
+///
+/// [profile.work]
+/// email = "me@work.example.com"
+/// ```
+pub async fn read_variables(cfg: &Config) -> Result<HashMap<String, toml::Value>, Error> {
+    let (_, hostname, os) = identity(cfg);
+    let overrides = cfg.variables_path.with_file_name(VARIABLES_OVERRIDES_DIR);
+
+    let paths = [
+        cfg.variables_path.clone(),
+        cfg.variables_path.with_file_name(SECRET_VARIABLES_FILE),
+        overrides.join(format!("os-{os}.toml")),
+        overrides.join(format!("hostname-{hostname}.toml")),
+    ];
+
+    let mut variables = HashMap::new();
+    for path in &paths {
+        merge_variables(&mut variables, read_variables_file(cfg, path, &[]).await?);
+    }
+
+    let mut profiles = match variables.remove(PROFILES_TABLE) {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => return Err(InnerError::Type.with_location(&cfg.variables_path)),
+        None => toml::Table::new(),
+    };
+
+    if let Some(name) = &cfg.profile {
+        let profile = match profiles.remove(name) {
+            Some(toml::Value::Table(profile)) => profile,
+            Some(_) => return Err(InnerError::Type.with_location(&cfg.variables_path)),
+            None => {
+                let error = InnerError::UnknownProfile(name.clone());
+                return Err(error.with_location(&cfg.variables_path));
+            }
+        };
+        merge_variables(&mut variables, profile.into_iter().collect());
+    }
+
+    Ok(variables)
+}
+
+/// Merge `more` on top of `variables`.
+fn merge_variables(
+    variables: &mut HashMap<String, toml::Value>,
+    more: HashMap<String, toml::Value>,
+) {
+    for (key, value) in more {
+        match variables.get_mut(&key) {
+            Some(base) => merge_value(base, value),
+            None => {
+                variables.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Merge `value` on top of `base`, key by key if both are tables.
+fn merge_value(base: &mut toml::Value, value: toml::Value) {
+    match (base, value) {
+        (toml::Value::Table(base), toml::Value::Table(table)) => {
+            for (key, value) in table {
+                match base.get_mut(&key) {
+                    Some(base) => merge_value(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// Read a variables file, if it exists, along with the files it includes.
+///
+/// ```toml
+/// include = ["common.toml", "laptop.toml"]
+/// ```
+///
+/// Included files are relative to the including file, and are merged in order, so that later
+/// files override earlier ones, and the including file overrides all of them.
 #[async_recursion]
-async fn dir(cfg: &Config, env: &Env, relative: PathBuf) -> Result<(), Errors> {
+async fn read_variables_file(
+    cfg: &Config,
+    path: &Path,
+    included_from: &[PathBuf],
+) -> Result<HashMap<String, toml::Value>, Error> {
+    debug!("trying to read {:?}", path);
+    let content = match read(path).await {
+        Ok(content) => content,
+        Err(e) if included_from.is_empty() => {
+            debug!("failed to read {:?}: {e}", path);
+            return Ok(HashMap::new());
+        }
+        Err(e) => return Err(e.with_location(path)),
+    };
+
+    let encryption = sniff_encryption(&content);
+    let content = match encryption {
+        Some(extension) => {
+            debug!("decrypting {:?}", path);
+            decrypt_as(cfg, path, extension).await?
+        }
+        None => content,
+    };
+    let s = String::from_utf8(content)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+        .with_location(path)?;
+
+    debug!("parsing {:?}", path);
+    let mut own = parse_variables(path, &s)?;
+
+    if encryption.is_some() {
+        own.values().for_each(mark_strings_sensitive);
+    }
+
+    let includes: Vec<String> = match own.remove(INCLUDE_KEY) {
+        Some(includes) => includes.try_into().with_location(path)?,
+        None => return Ok(own),
+    };
+
+    let included_from = [included_from, &[path.to_owned()]].concat();
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut variables = HashMap::new();
+    for include in includes {
+        let include = dir.join(include);
+        if included_from.contains(&include) {
+            return Err(InnerError::IncludeCycle.with_location(&include));
+        }
+
+        let more = read_variables_file(cfg, &include, &included_from).await?;
+        merge_variables(&mut variables, more);
+    }
+    merge_variables(&mut variables, own);
+
+    Ok(variables)
+}
+
+/// The format of a variables file, by its extension.
+#[derive(Clone, Copy)]
+pub enum VariablesFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl VariablesFormat {
+    /// The format of the variables file at `path`, which may be encrypted.
+    pub fn of(path: &Path) -> Self {
+        let mut name = PathBuf::from(path.file_name().unwrap_or_default());
+        if is_encrypted(&name) {
+            name.set_extension("");
+        }
+
+        match name.extension().and_then(OsStr::to_str) {
+            Some("json") => VariablesFormat::Json,
+            Some("yaml" | "yml") => VariablesFormat::Yaml,
+            _ => VariablesFormat::Toml,
+        }
+    }
+
+    pub fn parse(self, s: &str) -> Result<HashMap<String, toml::Value>, InnerError> {
+        Ok(match self {
+            VariablesFormat::Toml => toml::de::from_str(s)?,
+            VariablesFormat::Json => serde_json::from_str(s)?,
+            VariablesFormat::Yaml => serde_yaml::from_str(s)?,
+        })
+    }
+
+    pub fn to_string(self, variables: &toml::Table) -> Result<String, InnerError> {
+        Ok(match self {
+            VariablesFormat::Toml => toml::to_string(variables)?,
+            VariablesFormat::Json => serde_json::to_string_pretty(variables)? + "\n",
+            VariablesFormat::Yaml => serde_yaml::to_string(variables)?,
+        })
+    }
+}
+
+/// Parse a variables file as json, yaml or toml, depending on its extension, ignoring the
+/// extension of the encryption it may have.
+fn parse_variables(path: &Path, s: &str) -> Result<HashMap<String, toml::Value>, Error> {
+    VariablesFormat::of(path).parse(s).with_location(path)
+}
+
+fn mark_strings_sensitive(value: &toml::Value) {
+    match value {
+        toml::Value::String(s) => mark_sensitive(s),
+        toml::Value::Array(items) => items.iter().for_each(mark_strings_sensitive),
+        toml::Value::Table(table) => table.values().for_each(mark_strings_sensitive),
+        _ => {}
+    }
+}
+
+#[async_recursion]
+async fn dir(cfg: &Config, ctx: &Context, relative: PathBuf) -> Result<(), Errors> {
     let template_path = cfg.template_dir.join(&relative);
     let build_path = cfg.build_dir.join(&relative);
 
-    info!("traversing {:?}", template_path);
+    trace!("traversing {:?}", template_path);
 
-    match create_dir(&build_path).await {
+    let settings = read_dir_settings(&template_path).await?;
+    if let Some(output) = &settings.assemble {
+        return Ok(assemble(cfg, ctx, &relative, output).await?);
+    }
+
+    // the private directory is only accessible by the owner, regardless of configuration
+    let private = is_private(&relative);
+    let dir_mode = if private {
+        Some(PRIVATE_DIR_MODE)
+    } else {
+        cfg.dir_mode
+    };
+
+    let mut dir_builder = DirBuilder::new();
+    if let Some(mode) = dir_mode {
+        dir_builder.mode(mode);
+    }
+
+    match dir_builder.create(&build_path).await {
         Ok(_) => {}
         Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
-        Err(e) => return Err(e.with_location(&build_path).into()),
+        Err(e) => return Err(e.with_location(&build_path).during(Operation::Mkdir).into()),
+    }
+
+    let required_mode = if private {
+        Some(PRIVATE_DIR_MODE)
+    } else {
+        cfg.permissions.dir_mode(&relative)
+    };
+
+    if let Some(mode) = required_mode {
+        trace!("setting mode of {build_path:?} to {mode:04o}");
+        set_permissions(&build_path, Permissions::from_mode(mode))
+            .await
+            .with_location(&build_path)?;
     }
 
     let mut walker = read_dir(&template_path)
         .await
         .with_location(&template_path)?;
 
-    let mut dir_tasks = vec![];
-    let mut file_tasks = vec![];
+    let mut entries = vec![];
+
+    // names which only differ in case or unicode normalization overwrite each other on some file
+    // systems
+    let mut names: HashMap<String, PathBuf> = HashMap::new();
+    let mut collisions = vec![];
 
     while let Some(entry) = walker.next_entry().await.with_location(&template_path)? {
         let meta = entry.metadata().await.with_location(&entry.path())?;
         let new_relative = relative.join(entry.file_name());
 
-        if meta.is_dir() {
-            dir_tasks.push(dir(cfg, env, new_relative));
-        } else if meta.is_file() {
-            file_tasks.push(file(cfg, env, new_relative));
+        if cfg.skip(&new_relative) {
+            trace!("skipping {:?}", entry.path());
+            continue;
+        }
+
+        let folded: String = output_name(&new_relative).nfc().collect();
+        let folded = folded.to_lowercase();
+        if let Some(other) = names.insert(folded, new_relative.clone()) {
+            let error = InnerError::CaseCollision(other).with_location(&entry.path());
+            collisions.push(error);
+            continue;
+        }
+
+        if meta.is_dir() || meta.is_file() {
+            entries.push((new_relative, meta.is_dir()));
+        } else {
+            warning(
+                &entry.path(),
+                "not a regular file or directory, skipping it",
+            );
         }
     }
 
-    let dirs = async { join_all(dir_tasks).await.into_iter().collect::<Vec<_>>() };
-    let files = async { join_all(file_tasks).await.into_iter().collect::<Vec<_>>() };
-    let (dirs, files) = join!(dirs, files);
+    let mut errors: Errors = collisions.into();
 
-    let mut errors: Errors = files
-        .into_iter()
-        .filter_map(|r| r.err())
-        .collect::<Vec<_>>()
-        .into();
+    if settings.sequential {
+        entries.sort_unstable();
+        for (new_relative, is_dir) in entries {
+            let result = match is_dir {
+                true => dir(cfg, ctx, new_relative).await,
+                false => file(cfg, ctx, new_relative).await.map_err(Errors::from),
+            };
+
+            if let Err(error) = result {
+                errors.join(error);
+            }
+        }
+    } else {
+        let mut dir_tasks = vec![];
+        let mut file_tasks = vec![];
+        for (new_relative, is_dir) in entries {
+            match is_dir {
+                true => dir_tasks.push(dir(cfg, ctx, new_relative)),
+                false => file_tasks.push(file(cfg, ctx, new_relative)),
+            }
+        }
 
-    for error in dirs.into_iter().filter_map(|r| r.err()) {
-        errors.join(error);
+        let dirs = async { join_all(dir_tasks).await.into_iter().collect::<Vec<_>>() };
+        let files = async { join_all(file_tasks).await.into_iter().collect::<Vec<_>>() };
+        let (dirs, files) = join!(dirs, files);
+
+        for error in files.into_iter().filter_map(|r| r.err()) {
+            errors.join(error.into());
+        }
+
+        for error in dirs.into_iter().filter_map(|r| r.err()) {
+            errors.join(error);
+        }
     }
 
     if errors.is_empty() {
@@ -106,11 +897,38 @@ async fn dir(cfg: &Config, env: &Env, relative: PathBuf) -> Result<(), Errors> {
     }
 }
 
-async fn file(cfg: &Config, env: &Env, relative: PathBuf) -> Result<(), Error> {
+/// The name of the file that the entry at `relative` in the template tree is built into.
+fn output_name(relative: &Path) -> String {
+    let mut name = PathBuf::from(relative.file_name().unwrap_or_default());
+    while [TEMPLATE_EXTENSION, ENCRYPTED_EXTENSION, GPG_EXTENSION]
+        .iter()
+        .any(|extension| name.extension() == Some(OsStr::new(extension)))
+    {
+        name.set_extension("");
+    }
+    name.to_string_lossy().into_owned()
+}
+
+/// The path in the build tree that the file at `relative` in the template tree is built into.
+pub fn output_path(relative: &Path) -> PathBuf {
+    relative.with_file_name(output_name(relative))
+}
+
+async fn file(cfg: &Config, ctx: &Context, relative: PathBuf) -> Result<(), Error> {
+    ctx.progress.tick();
+
     let template_path = cfg.template_dir.join(&relative);
     let mut new_path = cfg.build_dir.join(&relative);
 
-    debug!("rendering {:?}", template_path);
+    if is_private(&relative) || is_encrypted(&relative) {
+        if cfg.skip_secrets {
+            trace!("{template_path:?} is private, leaving it out");
+            return Ok(());
+        }
+        return private_file(cfg, ctx, &relative).await;
+    }
+
+    trace!("rendering {:?}", template_path);
 
     if template_path.extension() == Some(OsStr::new(TEMPLATE_EXTENSION)) {
         // perform templating
@@ -130,76 +948,367 @@ async fn file(cfg: &Config, env: &Env, relative: PathBuf) -> Result<(), Error> {
             .with_location(&template_path)?
             .permissions();
 
-        let mut rendered = Vec::<u8>::new();
-        parse_template(&file_str)
-            .with_location(&template_path)?
-            .write(env, &mut rendered)
-            .with_location(&template_path)?;
-        let rendered = std::str::from_utf8(&rendered).unwrap();
+        let (front_matter, body) = split_front_matter(&file_str).with_location(&template_path)?;
+        ctx.use_variables(&template_path, body, front_matter.when.as_deref())?;
+
+        let variables = template_variables(body).with_location(&template_path)?;
+        if cfg.skip_secrets && ctx.secrets.any(&variables) {
+            trace!("{template_path:?} uses secrets, leaving it out");
+            return Ok(());
+        }
+
+        if let Some(when) = &front_matter.when {
+            if !evaluate(when, &ctx.env).with_location(&template_path)? {
+                trace!("condition of {template_path:?} doesn't hold, skipping it");
+                new_path.set_extension("");
+                ctx.record_target(cfg, &new_path, None);
+                return prune(cfg, &new_path).await;
+            }
+        }
+
+        if let Some(list) = &front_matter.foreach {
+            return multi_file(cfg, ctx, &relative, &front_matter, list, body, permissions).await;
+        }
 
         // remove template file extension
         new_path.set_extension("");
 
-        let mut rendered_file = File::create(&new_path).await.with_location(&new_path)?;
+        ctx.record_target(cfg, &new_path, front_matter.target.as_deref());
 
-        // write the rendered file
-        rendered_file
-            .write_all(rendered.as_bytes())
-            .await
-            .with_location(&new_path)?;
+        // secrets aren't in the lock, so whether they changed is unknown
+        let unchanged = match &ctx.changes {
+            Some(changes) if !ctx.secrets.any(&variables) => {
+                let output = new_path.strip_prefix(&cfg.build_dir).unwrap_or(&new_path);
+                changes.is_unchanged(&relative, file_str.as_bytes(), body)
+                    && changes.is_built(output, &new_path).await
+            }
+            _ => false,
+        };
 
-        // make sure the permissions match the original
-        rendered_file
-            .set_permissions(permissions)
-            .await
-            .with_location(&new_path)?;
+        if unchanged {
+            trace!("{template_path:?} and its variables are unchanged, not rendering it");
+            ctx.record(cfg, &new_path, relative.to_string_lossy());
+            return Ok(());
+        }
+
+        let env = ctx.env_for(cfg, &template_path, body).await?;
+        let rendered = render(&template_path, body, &env)?;
+
+        write_rendered(cfg, &new_path, &rendered, permissions).await?;
+
+        if let Some(mode) = &front_matter.mode {
+            let mode = parse_mode(mode).with_location(&template_path)?;
+            trace!("setting mode of {new_path:?} to {mode:04o}");
+            set_permissions(&new_path, Permissions::from_mode(mode))
+                .await
+                .with_location(&new_path)?;
+        }
+
+        ctx.record(cfg, &new_path, relative.to_string_lossy());
     } else {
         // else just copy the file
-        debug!("copying {template_path:?} -> {new_path:?}");
+        trace!("copying {template_path:?} -> {new_path:?}");
         copy(&template_path, &new_path)
             .await
+            .with_location(&template_path)
+            .during(Operation::Copy)?;
+
+        apply_mode_rules(cfg, &new_path).await?;
+        ctx.record(cfg, &new_path, relative.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Decrypt a file from the private directory or another encrypted file, and render it if it's a
+/// template.
+async fn private_file(cfg: &Config, ctx: &Context, relative: &Path) -> Result<(), Error> {
+    let template_path = cfg.template_dir.join(relative);
+    let mut new_path = cfg.build_dir.join(relative);
+
+    if !is_encrypted(relative) {
+        return Err(InnerError::NotEncrypted.with_location(&template_path));
+    }
+
+    trace!("decrypting {:?}", template_path);
+    let mut content = decrypt(cfg, &template_path).await?;
+    if let Ok(s) = std::str::from_utf8(&content) {
+        mark_sensitive(s);
+    }
+
+    // remove encrypted file extension
+    new_path.set_extension("");
+
+    if new_path.extension() == Some(OsStr::new(TEMPLATE_EXTENSION)) {
+        let body = String::from_utf8(content)
+            .map_err(|_| InnerError::NotUtf8)
             .with_location(&template_path)?;
+        let env = ctx.env_for(cfg, &template_path, &body).await?;
+        let rendered = render(&template_path, &body, &env)?;
+        mark_sensitive(&rendered);
+
+        content = rendered.into_bytes();
+        new_path.set_extension("");
     }
 
+    // formatters never see the plaintext, and it's only ever readable by the owner
+    write_private(&new_path, &content).await?;
+    ctx.record(cfg, &new_path, relative.to_string_lossy());
+
     Ok(())
 }
 
-fn get_username() -> String {
-    env::var("USER")
-        .ok()
-        .or_else(|| env::var("USERNAME").ok())
-        .unwrap_or_default()
+/// Write decrypted `content` to the build tree, such that only the owner can read it at any
+/// point.
+async fn write_private(new_path: &Path, content: &[u8]) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(PRIVATE_FILE_MODE)
+        .open(new_path)
+        .await
+        .with_location(new_path)
+        .during(Operation::Render)?;
+
+    // the file may have existed with another mode
+    file.set_permissions(Permissions::from_mode(PRIVATE_FILE_MODE))
+        .await
+        .with_location(new_path)?;
+
+    file.write_all(content)
+        .await
+        .with_location(new_path)
+        .during(Operation::Render)
 }
 
-async fn get_hostname() -> String {
-    async fn read_hostname_file() -> eyre::Result<String> {
-        Ok(read_to_string("/etc/hostname").await?)
+/// Render all files in a directory in sorted order and concatenate them into one file, placed
+/// next to the directory.
+async fn assemble(cfg: &Config, ctx: &Context, relative: &Path, output: &str) -> Result<(), Error> {
+    let template_path = cfg.template_dir.join(relative);
+
+    let mut names = vec![];
+    let mut walker = read_dir(&template_path)
+        .await
+        .with_location(&template_path)?;
+
+    while let Some(entry) = walker.next_entry().await.with_location(&template_path)? {
+        let meta = entry.metadata().await.with_location(&entry.path())?;
+        if meta.is_file() && !cfg.skip(&relative.join(entry.file_name())) {
+            names.push(entry.file_name());
+        }
     }
 
-    async fn run_hostname_cmd() -> eyre::Result<String> {
-        Command::new("hostname")
-            .output()
+    names.sort_unstable();
+
+    let mut assembled = String::new();
+    for name in &names {
+        let fragment_path = template_path.join(name);
+
+        trace!("assembling {:?}", fragment_path);
+        let content = read_to_string(&fragment_path)
             .await
-            .map_err(Into::into)
-            .and_then(|out| Ok(from_utf8(&out.stdout).map(str::to_string)?))
+            .with_location(&fragment_path)?;
+
+        if fragment_path.extension() == Some(OsStr::new(TEMPLATE_EXTENSION)) {
+            let (_, body) = split_front_matter(&content).with_location(&fragment_path)?;
+            ctx.use_variables(&fragment_path, body, None)?;
+            let env = ctx.env_for(cfg, &fragment_path, body).await?;
+            assembled.push_str(&render(&fragment_path, body, &env)?);
+        } else {
+            assembled.push_str(&content);
+        }
+    }
+
+    let settings_path = template_path.join(DIR_SETTINGS_FILE);
+    let permissions = metadata(&settings_path)
+        .await
+        .with_location(&settings_path)?
+        .permissions();
+
+    let parent = relative.parent().unwrap_or(Path::new(""));
+    let output_path = cfg.build_dir.join(parent).join(output);
+    write_rendered(cfg, &output_path, &assembled, permissions).await?;
+    ctx.record(cfg, &output_path, relative.to_string_lossy());
+
+    Ok(())
+}
+
+/// Render a template once per item of a list variable, as described by its front matter.
+async fn multi_file(
+    cfg: &Config,
+    ctx: &Context,
+    relative: &Path,
+    front_matter: &FrontMatter,
+    list: &str,
+    body: &str,
+    permissions: Permissions,
+) -> Result<(), Error> {
+    let template_path = cfg.template_dir.join(relative);
+
+    let items = ctx
+        .lists
+        .get(list)
+        .ok_or_else(|| InnerError::UnknownList(list.to_string()))
+        .with_location(&template_path)?;
+
+    let loop_var = front_matter.loop_var.as_deref().unwrap_or("item");
+    let output = front_matter
+        .output
+        .as_deref()
+        .ok_or(InnerError::MissingOutput)
+        .with_location(&template_path)?;
+    let placeholder = format!("{{{loop_var}}}");
+
+    let parent = relative.parent().unwrap_or(Path::new(""));
+    let mut outputs = vec![];
+
+    let base_env = ctx.env_for(cfg, &template_path, body).await?;
+    for item in items {
+        // the output of an item mustn't end up outside the directory of the template
+        if !is_file_name(item) {
+            return Err(InnerError::InvalidListItem(item.clone()).with_location(&template_path));
+        }
+
+        let mut env = Env::clone(&base_env);
+        env.insert(loop_var.to_string(), Value::Str(item.clone()));
+
+        let rendered = render(&template_path, body, &env)?;
+
+        let output_relative = parent.join(output.replace(&placeholder, item));
+        let output_path = cfg.build_dir.join(&output_relative);
+        write_rendered(cfg, &output_path, &rendered, permissions.clone()).await?;
+        ctx.record(cfg, &output_path, relative.to_string_lossy());
+
+        outputs.push(output_relative);
+    }
+
+    let key = relative.to_string_lossy().into_owned();
+    if let Some(previous) = ctx.previous_outputs.get(&key) {
+        for stale in previous.iter().filter(|path| !outputs.contains(path)) {
+            prune(cfg, &cfg.build_dir.join(stale)).await?;
+        }
     }
 
-    read_hostname_file()
-        .or_else(|_| run_hostname_cmd())
+    ctx.outputs.lock().unwrap().insert(key, outputs);
+
+    Ok(())
+}
+
+/// Whether `name` is a single, ordinary path component.
+fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains('/')
+        && matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        )
+}
+
+fn render(template_path: &Path, template: &str, env: &Env) -> Result<String, Error> {
+    let mut rendered = Vec::<u8>::new();
+    parsed_template(template)
+        .with_location(template_path)
+        .during(Operation::Render)?
+        .write(env, &mut rendered)
+        .with_location(template_path)
+        .during(Operation::Render)?;
+
+    Ok(String::from_utf8(rendered).unwrap())
+}
+
+/// Write a rendered template to the build tree.
+async fn write_rendered(
+    cfg: &Config,
+    new_path: &Path,
+    rendered: &str,
+    permissions: Permissions,
+) -> Result<(), Error> {
+    if cfg.skip_empty && rendered.trim().is_empty() {
+        trace!("{new_path:?} rendered to nothing, skipping it");
+        return prune(cfg, new_path).await;
+    }
+
+    let output_relative = new_path.strip_prefix(&cfg.build_dir).unwrap_or(new_path);
+    let rendered = cfg
+        .formatters
+        .format(output_relative, new_path, rendered.to_string())
+        .await?;
+
+    let mut rendered_file = File::create(new_path)
         .await
-        .unwrap_or(String::new())
-        .trim()
-        .to_string()
+        .with_location(new_path)
+        .during(Operation::Render)?;
+
+    // write the rendered file
+    rendered_file
+        .write_all(rendered.as_bytes())
+        .await
+        .with_location(new_path)
+        .during(Operation::Render)?;
+
+    // make sure the permissions match the original
+    rendered_file
+        .set_permissions(permissions)
+        .await
+        .with_location(new_path)?;
+
+    apply_mode_rules(cfg, new_path).await
 }
 
-async fn get_operating_system() -> String {
-    Command::new("uname")
-        .output()
+/// Set the mode of a file in the build tree if the permission rules require it.
+async fn apply_mode_rules(cfg: &Config, new_path: &Path) -> Result<(), Error> {
+    let output_relative = new_path.strip_prefix(&cfg.build_dir).unwrap_or(new_path);
+    if let Some(mode) = cfg.permissions.file_mode(output_relative) {
+        trace!("setting mode of {new_path:?} to {mode:04o}");
+        set_permissions(new_path, Permissions::from_mode(mode))
+            .await
+            .with_location(new_path)?;
+    }
+
+    Ok(())
+}
+
+/// Remove a previously built file, and the symlink to it in the link tree.
+async fn prune(cfg: &Config, build_path: &Path) -> Result<(), Error> {
+    let relative = build_path
+        .strip_prefix(&cfg.build_dir)
+        .unwrap_or(build_path);
+    let link_path = cfg.link_path(relative);
+
+    let is_symlink = symlink_metadata(&link_path)
         .await
+        .map(|meta| meta.file_type().is_symlink())
+        .unwrap_or(false);
+
+    let is_linked = match (
+        canonicalize(&link_path).await,
+        canonicalize(build_path).await,
+    ) {
+        (Ok(link_target), Ok(build_path)) => link_target == build_path,
+        _ => false,
+    };
+
+    if is_symlink && is_linked {
+        trace!("removing link {:?}", link_path);
+        remove_file(&link_path)
+            .await
+            .with_location(&link_path)
+            .during(Operation::Remove)?;
+    }
+
+    match remove_file(build_path).await {
+        Ok(()) => trace!("removed {:?}", build_path),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.with_location(build_path).during(Operation::Remove)),
+    }
+
+    Ok(())
+}
+
+fn get_username() -> String {
+    env::var("USER")
         .ok()
-        .as_ref()
-        .and_then(|out| from_utf8(&out.stdout).ok())
-        .unwrap_or("unknown")
-        .trim()
-        .to_lowercase()
+        .or_else(|| env::var("USERNAME").ok())
+        .unwrap_or_default()
 }
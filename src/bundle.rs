@@ -0,0 +1,42 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::Config;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use tokio::fs::read_to_string;
+
+/// File at the root of the template tree which maps bundle names to lists of paths.
+///
+/// ```toml
+/// core = [".bashrc", ".config/nvim"]
+/// ```
+pub const BUNDLES_FILE: &str = ".bundles.toml";
+
+/// The bundle applied by a minimal bootstrap.
+pub const CORE_BUNDLE: &str = "core";
+
+/// Read all bundles, if there is a bundles file.
+pub async fn read_bundles(cfg: &Config) -> Result<BTreeMap<String, Vec<PathBuf>>, Error> {
+    let path = cfg.template_dir.join(BUNDLES_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(BTreeMap::new());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+/// Read the paths, relative to the template tree, that make up the bundle `name`.
+pub async fn read_bundle(cfg: &Config, name: &str) -> Result<Vec<PathBuf>, Error> {
+    let path = cfg.template_dir.join(BUNDLES_FILE);
+
+    debug!("reading {:?}", path);
+    let s = read_to_string(&path).await.with_location(&path)?;
+    let mut bundles: HashMap<String, Vec<PathBuf>> = toml::de::from_str(&s).with_location(&path)?;
+
+    bundles
+        .remove(name)
+        .ok_or_else(|| InnerError::UnknownBundle(name.to_string()))
+        .with_location(&path)
+}
@@ -0,0 +1,157 @@
+use crate::backup::BACKUP_DIR;
+use crate::diff::list_files;
+use crate::error::{ErrorLocation, Errors};
+use crate::generations::GENERATIONS_DIR;
+use crate::scratch::SCRATCH_DIR;
+use crate::settings::read_settings;
+use crate::state::{read_sources, unix_time};
+use crate::Config;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::{metadata, read_dir, remove_dir_all, remove_file};
+
+/// Remove what has piled up in the build and state dirs, and return how many bytes were freed.
+///
+/// That is files in the build tree which no sync produced, scratch builds, and backups older than
+/// `older_than`. With `all`, the whole build tree and every backup is removed.
+pub async fn clean(cfg: &Config, all: bool, older_than: Option<Duration>) -> Result<u64, Errors> {
+    let mut freed = 0;
+
+    if all {
+        freed += remove_contents(&cfg.build_dir).await?;
+        warn!("removed the build tree, sync to create it again");
+    } else {
+        // without a record of what was built everything looks stale
+        let sources = read_sources(cfg).await?;
+        if !sources.is_empty() {
+            for relative in list_files(&cfg.build_dir).await? {
+                if sources.contains_key(&*relative.to_string_lossy()) {
+                    continue;
+                }
+
+                let path = cfg.build_dir.join(relative);
+                debug!("removing stale {path:?}");
+                freed += metadata(&path).await.with_location(&path)?.len();
+                remove_file(&path).await.with_location(&path)?;
+            }
+        }
+    }
+
+    let scratch_dir = cfg.state_dir.join(SCRATCH_DIR);
+    if scratch_dir.exists() {
+        freed += remove(&scratch_dir).await?;
+    }
+
+    let backup_dir = cfg.state_dir.join(BACKUP_DIR);
+    let now = unix_time();
+    for at in list_ids(&backup_dir).await? {
+        let expired = older_than.is_some_and(|age| now.saturating_sub(at) > age.as_secs());
+        if all || expired {
+            freed += remove(&backup_dir.join(at.to_string())).await?;
+        }
+    }
+
+    Ok(freed)
+}
+
+/// How many of the build trees of past syncs are kept when the settings don't say.
+const DEFAULT_KEEP_GENERATIONS: usize = 5;
+
+/// Remove the build trees of past syncs and the backups which the retention settings no longer
+/// keep, and return how many bytes were freed. This runs after every successful sync.
+pub async fn gc(cfg: &Config) -> Result<u64, Errors> {
+    let settings = read_settings(&cfg.config_dir).await?;
+    let mut freed = 0;
+
+    let generations_dir = cfg.state_dir.join(GENERATIONS_DIR);
+    let generations = list_ids(&generations_dir).await?;
+    let keep = settings
+        .keep_generations
+        .unwrap_or(DEFAULT_KEEP_GENERATIONS);
+    let expired = generations.len().saturating_sub(keep);
+    for id in &generations[..expired] {
+        freed += remove(&generations_dir.join(id.to_string())).await?;
+    }
+
+    if let Some(days) = settings.keep_backups_days {
+        let backup_dir = cfg.state_dir.join(BACKUP_DIR);
+        let now = unix_time();
+        for at in list_ids(&backup_dir).await? {
+            if now.saturating_sub(at) > days * 24 * 60 * 60 {
+                freed += remove(&backup_dir.join(at.to_string())).await?;
+            }
+        }
+    }
+
+    Ok(freed)
+}
+
+/// The entries of `dir` which are named after when they were made, oldest first.
+async fn list_ids(dir: &Path) -> Result<Vec<u64>, Errors> {
+    let mut entries = match read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.with_location(dir).into()),
+    };
+
+    let mut ids = vec![];
+    while let Some(entry) = entries.next_entry().await.with_location(dir)? {
+        if let Ok(id) = entry.file_name().to_string_lossy().parse::<u64>() {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+
+    Ok(ids)
+}
+
+/// Parse an age such as `30d`, `12h` or `2w`.
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let (number, unit) = age.split_at(age.len().saturating_sub(1));
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid age {age:?}, expected e.g. 30d")),
+    };
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid age {age:?}, expected e.g. 30d"))?;
+
+    Ok(Duration::from_secs(number * seconds))
+}
+
+/// Remove everything in the directory at `path`, but not the directory itself.
+async fn remove_contents(path: &Path) -> Result<u64, Errors> {
+    let mut freed = 0;
+
+    let mut entries = read_dir(path).await.with_location(path)?;
+    while let Some(entry) = entries.next_entry().await.with_location(path)? {
+        freed += remove(&entry.path()).await?;
+    }
+
+    Ok(freed)
+}
+
+/// Remove the file or directory at `path`, and return its size.
+async fn remove(path: &Path) -> Result<u64, Errors> {
+    let meta = metadata(path).await.with_location(path)?;
+    if !meta.is_dir() {
+        remove_file(path).await.with_location(path)?;
+        return Ok(meta.len());
+    }
+
+    let mut size = 0;
+    for relative in list_files(path).await? {
+        let file = path.join(relative);
+        size += metadata(&file).await.with_location(&file)?.len();
+    }
+
+    debug!("removing {path:?}");
+    remove_dir_all(path).await.with_location(path)?;
+    Ok(size)
+}
@@ -0,0 +1,141 @@
+use crate::error::InnerError;
+use blueprint::{Env, Value};
+
+/// Evaluate a condition from the front matter of a template, such as
+/// `os == 'linux' && !work`.
+///
+/// A bare name is true if it's a flag or boolean variable which is set, `==` and `!=` compare a
+/// string variable to a quoted string, and `!`, `&&`, `||` and parentheses combine conditions.
+/// Unset variables are false, and equal to no string.
+pub fn evaluate(condition: &str, env: &Env) -> Result<bool, InnerError> {
+    parse(condition, env).map(|(value, _)| value)
+}
+
+/// List the variables used by a condition.
+pub fn condition_variables(condition: &str) -> Result<Vec<String>, InnerError> {
+    let (_, names) = parse(condition, &Env::new())?;
+    Ok(names.into_iter().map(str::to_string).collect())
+}
+
+fn parse<'a>(condition: &'a str, env: &'a Env) -> Result<(bool, Vec<&'a str>), InnerError> {
+    let mut parser = Parser {
+        rest: condition,
+        env,
+        names: vec![],
+    };
+
+    let value = parser.or()?;
+    if !parser.rest.trim().is_empty() {
+        return Err(parser.error());
+    }
+
+    Ok((value, parser.names))
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    env: &'a Env,
+
+    /// The variables seen so far.
+    names: Vec<&'a str>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self) -> InnerError {
+        InnerError::Condition(self.rest.trim().to_string())
+    }
+
+    /// Consume `token` if it's next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<bool, InnerError> {
+        let mut value = self.and()?;
+        while self.eat("||") {
+            // evaluate both sides, so that syntax errors aren't hidden
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<bool, InnerError> {
+        let mut value = self.unary()?;
+        while self.eat("&&") {
+            value &= self.unary()?;
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<bool, InnerError> {
+        if self.eat("!=") {
+            return Err(self.error());
+        }
+
+        if self.eat("!") {
+            return Ok(!self.unary()?);
+        }
+
+        if self.eat("(") {
+            let value = self.or()?;
+            if !self.eat(")") {
+                return Err(self.error());
+            }
+            return Ok(value);
+        }
+
+        let name = self.name()?;
+        let value = self.env.get(name);
+        self.names.push(name);
+
+        let equal = if self.eat("==") {
+            true
+        } else if self.eat("!=") {
+            false
+        } else {
+            return Ok(matches!(value, Some(Value::Bool(true))));
+        };
+
+        let string = self.string()?;
+        let is_equal = matches!(value, Some(Value::Str(s)) if s == string);
+        Ok(is_equal == equal)
+    }
+
+    fn name(&mut self) -> Result<&'a str, InnerError> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+
+        if end == 0 {
+            return Err(self.error());
+        }
+
+        let (name, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Ok(name)
+    }
+
+    fn string(&mut self) -> Result<&'a str, InnerError> {
+        self.rest = self.rest.trim_start();
+        let Some(quote) = self.rest.chars().next().filter(|c| *c == '\'' || *c == '"') else {
+            return Err(self.error());
+        };
+
+        let Some(end) = self.rest[1..].find(quote) else {
+            return Err(self.error());
+        };
+
+        let string = &self.rest[1..end + 1];
+        self.rest = &self.rest[end + 2..];
+        Ok(string)
+    }
+}
@@ -0,0 +1,172 @@
+use crate::process::run;
+use crate::target::Target;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    fn program(self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker",
+            Runtime::Podman => "podman",
+        }
+    }
+}
+
+/// A running container, accessed through the CLI of its container runtime.
+pub struct Container {
+    runtime: Runtime,
+    name: String,
+}
+
+impl Container {
+    pub fn new(runtime: Runtime, name: String) -> Self {
+        Container { runtime, name }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the home directory of the default user in the container.
+    pub async fn home(&self) -> io::Result<PathBuf> {
+        let home = self
+            .exec(&[OsStr::new("printenv"), OsStr::new("HOME")])
+            .await?;
+        Ok(home.trim().into())
+    }
+
+    /// Run a command inside the container and return its stdout.
+    async fn exec(&self, args: &[&OsStr]) -> io::Result<String> {
+        let mut cmd = Command::new(self.runtime.program());
+        cmd.arg("exec").arg(&self.name).args(args);
+        run(cmd).await
+    }
+}
+
+impl Target for Container {
+    fn create_dir<'a>(
+        &'a self,
+        path: &'a Path,
+        mode: Option<u32>,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let mode = mode.map(|mode| format!("{mode:o}"));
+            let mut args = vec![OsStr::new("mkdir"), OsStr::new("-p")];
+            if let Some(mode) = &mode {
+                args.extend([OsStr::new("-m"), OsStr::new(mode)]);
+            }
+            args.push(path.as_os_str());
+
+            self.exec(&args).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn is_symlink<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        async move {
+            let script = OsStr::new("test -L \"$1\" && echo yes || true");
+            let args = [
+                OsStr::new("sh"),
+                OsStr::new("-c"),
+                script,
+                OsStr::new("sh"),
+                path.as_os_str(),
+            ];
+            let out = self.exec(&args).await?;
+            Ok(out.trim() == "yes")
+        }
+        .boxed()
+    }
+
+    fn read_link<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<PathBuf>>> {
+        async move {
+            let script = OsStr::new("readlink \"$1\" || true");
+            let args = [
+                OsStr::new("sh"),
+                OsStr::new("-c"),
+                script,
+                OsStr::new("sh"),
+                path.as_os_str(),
+            ];
+            let out = self.exec(&args).await?;
+            let original = out.trim_end_matches('\n');
+            Ok(Some(PathBuf::from(original)).filter(|_| !original.is_empty()))
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            self.exec(&[OsStr::new("rm"), OsStr::new("-f"), path.as_os_str()])
+                .await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn symlink<'a>(&'a self, original: &'a Path, link: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let args = [
+                OsStr::new("ln"),
+                OsStr::new("-s"),
+                original.as_os_str(),
+                link.as_os_str(),
+            ];
+            self.exec(&args).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn copy_file<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let dest = format!("{}:{}", self.name, to.display());
+
+            let mut cmd = Command::new(self.runtime.program());
+            cmd.arg("cp").arg(from).arg(dest);
+            run(cmd).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn back_up<'a>(&'a self, path: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        async move {
+            let script = OsStr::new("test -f \"$1\" && ! test -L \"$1\" && echo yes || true");
+            let args = [
+                OsStr::new("sh"),
+                OsStr::new("-c"),
+                script,
+                OsStr::new("sh"),
+                path.as_os_str(),
+            ];
+            if self.exec(&args).await?.trim() != "yes" {
+                return Ok(false);
+            }
+
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            let source = format!("{}:{}", self.name, path.display());
+            let mut cmd = Command::new(self.runtime.program());
+            cmd.arg("cp").arg(source).arg(to);
+            run(cmd).await?;
+
+            self.remove_file(path).await?;
+            Ok(true)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,114 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::process::{run, run_with_input};
+use crate::Config;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+
+/// File at the root of the template tree with dconf settings, by path and key.
+///
+/// ```toml
+/// ["org/gnome/desktop/interface"]
+/// color-scheme = "prefer-dark"
+/// text-scaling-factor = 1.25
+///
+/// ["org/gnome/desktop/wm/keybindings"]
+/// close = ["<Super>q"]
+/// ```
+///
+/// Strings, numbers, booleans and arrays of them are converted to their GVariant form.
+pub const DCONF_FILE: &str = "dconf.toml";
+
+type Settings = BTreeMap<String, BTreeMap<String, toml::Value>>;
+
+/// Load the settings from the tree into dconf, if there are any.
+pub async fn load_settings(cfg: &Config) -> Result<(), Error> {
+    let Some((path, settings)) = read_settings(cfg).await? else {
+        return Ok(());
+    };
+
+    let mut keyfile = String::new();
+    for (dir, keys) in &settings {
+        let _ = writeln!(keyfile, "[{}]", dir.trim_matches('/'));
+        for (key, value) in keys {
+            let value = to_gvariant(value).with_location(&path)?;
+            let _ = writeln!(keyfile, "{key}={value}");
+        }
+        keyfile.push('\n');
+    }
+
+    let mut cmd = Command::new("dconf");
+    cmd.args(["load", "/"]);
+
+    info!("loading {} dconf paths", settings.len());
+    run_with_input(cmd, keyfile.as_bytes())
+        .await
+        .with_location(&path)?;
+
+    Ok(())
+}
+
+/// Print the settings from the tree which differ from the current dconf database.
+///
+/// Returns whether any difference was found.
+pub async fn diff_settings(cfg: &Config) -> Result<bool, Error> {
+    let Some((path, settings)) = read_settings(cfg).await? else {
+        return Ok(false);
+    };
+
+    let mut changed = false;
+    for (dir, keys) in &settings {
+        for (key, value) in keys {
+            let wanted = to_gvariant(value).with_location(&path)?;
+            let key_path = format!("/{}/{key}", dir.trim_matches('/'));
+
+            let mut cmd = Command::new("dconf");
+            cmd.arg("read").arg(&key_path);
+            let current = run(cmd).await.with_location(&path)?;
+            let current = current.trim();
+
+            if current != wanted {
+                changed = true;
+                let current = if current.is_empty() { "unset" } else { current };
+                println!("{key_path}: {current} -> {wanted}");
+            }
+        }
+    }
+
+    Ok(changed)
+}
+
+async fn read_settings(cfg: &Config) -> Result<Option<(PathBuf, Settings)>, Error> {
+    let path = cfg.template_dir.join(DCONF_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(None);
+    };
+
+    let settings = toml::de::from_str(&s).with_location(&path)?;
+    Ok(Some((path, settings)))
+}
+
+fn to_gvariant(value: &toml::Value) -> Result<String, InnerError> {
+    match value {
+        toml::Value::String(s) => Ok(format!(
+            "'{}'",
+            s.replace('\\', "\\\\").replace('\'', "\\'")
+        )),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(format!("{f:?}")),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Array(items) => {
+            let items = items
+                .iter()
+                .map(to_gvariant)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(format!("[{}]", items.join(", ")))
+        }
+        _ => Err(InnerError::Type),
+    }
+}
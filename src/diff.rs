@@ -0,0 +1,303 @@
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::private::is_decrypted;
+use crate::redact::redact;
+use crate::state::{read_sources, Sources};
+use crate::Config;
+use async_recursion::async_recursion;
+use futures::future::join_all;
+use std::fmt::Write;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs::{read, read_dir};
+
+/// Number of unchanged lines shown around each change.
+const CONTEXT: usize = 3;
+
+/// One side of a diff: a directory, the name it is shown as in the diff headers, and what the
+/// files in it were built from.
+pub struct Side<'a> {
+    pub label: &'a str,
+    pub root: &'a Path,
+    pub sources: &'a Sources,
+}
+
+/// Print a unified diff of all `files` (relative paths) that differ between the two trees.
+///
+/// Files missing on one side are diffed against an empty file. The content of private files is
+/// never shown, and sensitive values are masked. Returns whether any difference was found.
+pub async fn diff_trees(old: &Side<'_>, new: &Side<'_>, files: &[PathBuf]) -> Result<bool, Errors> {
+    let version = |side: &Side<'_>, relative: &Path| Version {
+        path: side.root.join(relative),
+        label: format!("{}/{}", side.label, relative.display()),
+    };
+
+    let pairs = files
+        .iter()
+        .map(|relative| {
+            let private =
+                is_decrypted(old.sources, relative) || is_decrypted(new.sources, relative);
+            (version(old, relative), version(new, relative), private)
+        })
+        .collect();
+
+    print_diffs(pairs).await
+}
+
+/// Print a unified diff of what linking `files` (relative paths in the build tree) of the tree
+/// built with `built`, usually in a scratch directory, would change in the link dir of `cfg`, like
+/// [diff_trees].
+pub async fn diff_links(cfg: &Config, built: &Config, files: &[PathBuf]) -> Result<bool, Errors> {
+    let sources = read_sources(built).await?;
+    let pairs = files
+        .iter()
+        .map(|relative| {
+            let link_path = cfg.link_path(relative);
+            let build_path = built.build_dir.join(relative);
+            let old = Version {
+                label: link_path.display().to_string(),
+                path: link_path,
+            };
+            let new = Version {
+                label: build_path.display().to_string(),
+                path: build_path,
+            };
+            (old, new, is_decrypted(&sources, relative))
+        })
+        .collect();
+
+    print_diffs(pairs).await
+}
+
+/// A version of a file, and the name it is shown as in the diff headers.
+struct Version {
+    path: PathBuf,
+    label: String,
+}
+
+/// Print the diffs of `pairs` of versions, of which only whether they differ is shown if they are
+/// private.
+async fn print_diffs(pairs: Vec<(Version, Version, bool)>) -> Result<bool, Errors> {
+    let diffs = join_all(
+        pairs
+            .iter()
+            .map(|(old, new, private)| diff_file(old, new, *private)),
+    )
+    .await;
+
+    let mut errors = vec![];
+    let mut changed = false;
+
+    for result in diffs {
+        match result {
+            Ok(Some(diff)) => {
+                changed = true;
+                print!("{}", redact(&diff));
+            }
+            Ok(None) => {}
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(changed)
+    } else {
+        Err(errors.into())
+    }
+}
+
+async fn diff_file(old: &Version, new: &Version, private: bool) -> Result<Option<String>, Error> {
+    async fn read_version(version: &Version) -> Result<Option<Vec<u8>>, Error> {
+        match read(&version.path).await {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.with_location(&version.path)),
+        }
+    }
+
+    let old_content = read_version(old).await?;
+    let new_content = read_version(new).await?;
+
+    if old_content == new_content {
+        return Ok(None);
+    }
+
+    let label = |version: &Version, content: &Option<Vec<u8>>| match content {
+        Some(_) => version.label.clone(),
+        None => "/dev/null".to_string(),
+    };
+    let old_label = label(old, &old_content);
+    let new_label = label(new, &new_content);
+
+    if private {
+        return Ok(Some(format!(
+            "Private files {old_label} and {new_label} differ\n"
+        )));
+    }
+
+    let old_content = old_content.unwrap_or_default();
+    let new_content = new_content.unwrap_or_default();
+
+    match (
+        std::str::from_utf8(&old_content),
+        std::str::from_utf8(&new_content),
+    ) {
+        (Ok(old_str), Ok(new_str)) => Ok(Some(unified(&old_label, &new_label, old_str, new_str))),
+        _ => Ok(Some(format!(
+            "Binary files {old_label} and {new_label} differ\n"
+        ))),
+    }
+}
+
+/// List all files under `root`, relative to `root`.
+pub async fn list_files(root: &Path) -> Result<Vec<PathBuf>, Errors> {
+    let mut files = list_dir(root, PathBuf::new()).await?;
+    files.sort_unstable();
+    Ok(files)
+}
+
+#[async_recursion]
+async fn list_dir(root: &Path, relative: PathBuf) -> Result<Vec<PathBuf>, Errors> {
+    let path = root.join(&relative);
+    let mut walker = read_dir(&path).await.with_location(&path)?;
+
+    let mut dir_tasks = vec![];
+    let mut files = vec![];
+
+    while let Some(entry) = walker.next_entry().await.with_location(&path)? {
+        let meta = entry.metadata().await.with_location(&entry.path())?;
+        let new_relative = relative.join(entry.file_name());
+
+        if meta.is_dir() {
+            dir_tasks.push(list_dir(root, new_relative));
+        } else if meta.is_file() {
+            files.push(new_relative);
+        }
+    }
+
+    let mut errors = Errors::default();
+
+    for result in join_all(dir_tasks).await {
+        match result {
+            Ok(mut more_files) => files.append(&mut more_files),
+            Err(error) => errors.join(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(files)
+    } else {
+        Err(errors)
+    }
+}
+
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl Line<'_> {
+    fn in_old(&self) -> bool {
+        !matches!(self, Line::Added(_))
+    }
+
+    fn in_new(&self) -> bool {
+        !matches!(self, Line::Removed(_))
+    }
+}
+
+/// Create a unified diff between two strings.
+pub fn unified(old_label: &str, new_label: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "--- {old_label}");
+    let _ = writeln!(out, "+++ {new_label}");
+
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Line::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut k = 0;
+    while k < changes.len() {
+        // extend the hunk as long as the next change is close enough to share context
+        let start = changes[k].saturating_sub(CONTEXT);
+        while k + 1 < changes.len() && changes[k + 1] - changes[k] <= 2 * CONTEXT {
+            k += 1;
+        }
+        let end = (changes[k] + CONTEXT + 1).min(lines.len());
+        k += 1;
+
+        let old_start = lines[..start].iter().filter(|l| l.in_old()).count();
+        let new_start = lines[..start].iter().filter(|l| l.in_new()).count();
+        let hunk = &lines[start..end];
+        let old_len = hunk.iter().filter(|l| l.in_old()).count();
+        let new_len = hunk.iter().filter(|l| l.in_new()).count();
+
+        // empty ranges are numbered by the line before them
+        let old_start = if old_len == 0 {
+            old_start
+        } else {
+            old_start + 1
+        };
+        let new_start = if new_len == 0 {
+            new_start
+        } else {
+            new_start + 1
+        };
+
+        let _ = writeln!(out, "@@ -{old_start},{old_len} +{new_start},{new_len} @@");
+        for line in hunk {
+            let _ = match line {
+                Line::Same(s) => writeln!(out, " {s}"),
+                Line::Removed(s) => writeln!(out, "-{s}"),
+                Line::Added(s) => writeln!(out, "+{s}"),
+            };
+        }
+    }
+
+    out
+}
+
+/// Compute a line diff based on the longest common subsequence.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let (n, m) = (old.len(), new.len());
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            lines.push(Line::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(Line::Removed(old[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(new[j]));
+            j += 1;
+        }
+    }
+
+    lines.extend(old[i..].iter().copied().map(Line::Removed));
+    lines.extend(new[j..].iter().copied().map(Line::Added));
+
+    lines
+}
@@ -0,0 +1,39 @@
+use crate::error::{Error, ErrorLocation};
+use serde::Deserialize;
+use std::path::Path;
+use tokio::fs::read_to_string;
+
+/// File which can be placed in any directory of the template tree to configure how it's built.
+///
+/// ```toml
+/// # render and concatenate all files in this directory, in sorted order, into ../.bashrc
+/// assemble = ".bashrc"
+///
+/// # build the entries of this directory one at a time, in sorted order
+/// sequential = true
+/// ```
+pub const DIR_SETTINGS_FILE: &str = ".dotfiles.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirSettings {
+    /// Concatenate the files in the directory into a file with this name in the parent directory.
+    pub assemble: Option<String>,
+
+    /// Build the files and directories in the directory one at a time, in the order of their
+    /// names, instead of all at once.
+    #[serde(default)]
+    pub sequential: bool,
+}
+
+/// Read the settings of a directory in the template tree, if it has any.
+pub async fn read_dir_settings(template_path: &Path) -> Result<DirSettings, Error> {
+    let path = template_path.join(DIR_SETTINGS_FILE);
+
+    let Ok(s) = read_to_string(&path).await else {
+        return Ok(DirSettings::default());
+    };
+
+    debug!("parsing {:?}", path);
+    toml::de::from_str(&s).with_location(&path)
+}
@@ -0,0 +1,174 @@
+use crate::builder::TEMPLATE_EXTENSION;
+use crate::diff::list_files;
+use crate::error::{ErrorLocation, Errors};
+use crate::state::read_sources;
+use crate::target::{LocalFs, Target};
+use crate::Config;
+use futures::future::{ready, BoxFuture};
+use futures::FutureExt;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs::{read, symlink_metadata};
+
+/// A target which prints what linking would do to the local filesystem, instead of doing it.
+///
+/// The tree is built into a scratch directory for a dry run, so links into `scratch` are shown as
+/// links into `build_dir`. What would be done is printed by [DryRun::print], sorted by path, since
+/// the linker visits files in no particular order.
+pub struct DryRun {
+    scratch: PathBuf,
+    build_dir: PathBuf,
+    lines: Mutex<Vec<(PathBuf, String)>>,
+}
+
+impl DryRun {
+    pub fn new(scratch: PathBuf, build_dir: PathBuf) -> Self {
+        DryRun {
+            scratch,
+            build_dir,
+            lines: Mutex::new(vec![]),
+        }
+    }
+
+    /// Print what would be done so far.
+    pub fn print(self) {
+        let mut lines = self.lines.into_inner().unwrap();
+        lines.sort();
+        for (_, line) in lines {
+            println!("{line}");
+        }
+    }
+
+    fn report(&self, path: &Path, line: String) {
+        self.lines.lock().unwrap().push((path.to_path_buf(), line));
+    }
+
+    fn real_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.scratch) {
+            Ok(relative) => self.build_dir.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+}
+
+impl Target for DryRun {
+    fn create_dir<'a>(
+        &'a self,
+        path: &'a Path,
+        _mode: Option<u32>,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            if symlink_metadata(path).await.is_err() {
+                self.report(path, format!("would create directory {}", path.display()));
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn is_symlink<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        LocalFs.is_symlink(path)
+    }
+
+    fn read_link<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<PathBuf>>> {
+        LocalFs.read_link(path)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        // files are only removed to be replaced, which is shown by symlink and copy_file
+        async move { symlink_metadata(path).await.map(|_| ()) }.boxed()
+    }
+
+    fn symlink<'a>(&'a self, original: &'a Path, link: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let original = self.real_path(original);
+            match LocalFs.read_link(link).await? {
+                Some(current) if current == original => {}
+                Some(_) => {
+                    let line = format!("would relink {} -> {}", link.display(), original.display());
+                    self.report(link, line);
+                }
+                None if symlink_metadata(link).await.is_ok() => self.report(
+                    link,
+                    format!("would replace {} with a link", link.display()),
+                ),
+                None => {
+                    let line = format!("would link {} -> {}", link.display(), original.display());
+                    self.report(link, line);
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn copy_file<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            if read(to).await.ok() != Some(read(from).await?) {
+                let line = format!(
+                    "would copy {} to {}",
+                    self.real_path(from).display(),
+                    to.display()
+                );
+                self.report(to, line);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn back_up<'a>(&'a self, path: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        async move {
+            match symlink_metadata(path).await {
+                Ok(meta) if meta.is_file() => {
+                    let line = format!("would back up {} to {}", path.display(), to.display());
+                    self.report(path, line);
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+        .boxed()
+    }
+
+    fn get_acl<'a>(&'a self, _path: &'a Path) -> BoxFuture<'a, io::Result<Option<String>>> {
+        ready(Ok(None)).boxed()
+    }
+}
+
+/// Print which files building into `scratch` rendered, copied or removed compared to the last
+/// build in `cfg.build_dir`.
+pub async fn print_build_changes(cfg: &Config, scratch: &Config) -> Result<(), Errors> {
+    let old = match symlink_metadata(&cfg.build_dir).await {
+        Ok(_) => list_files(&cfg.build_dir).await?,
+        Err(_) => vec![],
+    };
+    let new = list_files(&scratch.build_dir).await?;
+    let sources = read_sources(scratch).await?;
+
+    for relative in &new {
+        let new_path = scratch.build_dir.join(relative);
+        let old_path = cfg.build_dir.join(relative);
+
+        let new_content = read(&new_path).await.with_location(&new_path)?;
+        if read(&old_path).await.ok() == Some(new_content) {
+            continue;
+        }
+
+        let source = sources.get(&*relative.to_string_lossy());
+        let rendered = source.is_some_and(|source| {
+            Path::new(source).extension() == Some(OsStr::new(TEMPLATE_EXTENSION))
+        });
+
+        let verb = if rendered { "render" } else { "copy" };
+        println!("would {verb} {}", old_path.display());
+    }
+
+    for relative in old.iter().filter(|relative| !new.contains(relative)) {
+        println!("would remove {}", cfg.build_dir.join(relative).display());
+    }
+
+    Ok(())
+}
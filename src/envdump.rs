@@ -0,0 +1,62 @@
+use crate::builder::build_env;
+use crate::error::Errors;
+use crate::Config;
+use blueprint::Value;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum EnvFormat {
+    /// A JSON object of variable names to strings and booleans.
+    #[default]
+    Json,
+
+    /// `export` statements which can be sourced by a POSIX shell.
+    Shell,
+}
+
+/// Print the facts, variables and flags that templates are rendered with.
+pub async fn print_env(cfg: &Config, format: EnvFormat) -> Result<(), Errors> {
+    let env = build_env(cfg).await?;
+
+    let values: BTreeMap<&str, serde_json::Value> = env
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Str(s) => serde_json::Value::String(s.clone()),
+                Value::Bool(b) => serde_json::Value::Bool(*b),
+                other => serde_json::Value::String(format!("{other:?}")),
+            };
+            (key.as_str(), value)
+        })
+        .collect();
+
+    match format {
+        EnvFormat::Json => {
+            let json = serde_json::to_string_pretty(&values).expect("serializing a map");
+            println!("{json}");
+        }
+        EnvFormat::Shell => {
+            for (key, value) in values {
+                let value = match value {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                println!(
+                    "export {}='{}'",
+                    shell_name(key),
+                    value.replace('\'', "'\\''")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace the characters that can't be part of a shell variable name.
+fn shell_name(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
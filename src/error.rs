@@ -1,17 +1,62 @@
+use crate::managers::Manager;
+use crate::redact::redact;
+use std::collections::BTreeMap;
+use std::error::Error as _;
+use std::fmt;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use thiserror::Error;
 
+/// The most errors an [Errors] keeps, the rest are only counted.
+static MAX_ERRORS: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+pub fn set_max_errors(max: usize) {
+    MAX_ERRORS.store(max, Ordering::Relaxed);
+}
+
 #[derive(Default)]
 pub struct Errors {
     errors: Vec<Error>,
+
+    /// Where the errors left out because of [MAX_ERRORS] happened.
+    dropped: Vec<PathBuf>,
+
+    /// Whether the command still did part of what it should.
+    partial: bool,
 }
 
 pub struct Error {
     location: PathBuf,
+    operation: Option<Operation>,
     inner: InnerError,
 }
 
+/// What was being done to the location of an [Error] when it occurred.
+#[derive(Clone, Copy, Debug)]
+pub enum Operation {
+    Render,
+    Copy,
+    Link,
+    Remove,
+    Mkdir,
+    Backup,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Operation::Render => "rendering",
+            Operation::Copy => "copying",
+            Operation::Link => "linking",
+            Operation::Remove => "removing",
+            Operation::Mkdir => "creating directory",
+            Operation::Backup => "backing up",
+        };
+        f.write_str(s)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum InnerError {
     #[error("IO Error: {0}")]
@@ -23,13 +68,128 @@ pub enum InnerError {
     #[error("Failed to parse toml file")]
     Toml(#[from] toml::de::Error),
 
+    #[error("Failed to serialize toml")]
+    TomlSer(#[from] toml::ser::Error),
+
+    #[error("Failed to parse json file")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Failed to parse yaml file")]
+    Yaml(#[from] serde_yaml::Error),
+
     #[error("Unsupported variable type")]
     Type,
+
+    #[error("Unknown list variable {0:?}")]
+    UnknownList(String),
+
+    #[error("Front matter with foreach must also set output")]
+    MissingOutput,
+
+    #[error("List item {0:?} can't be used in a file name")]
+    InvalidListItem(String),
+
+    #[error("Invalid condition at {0:?}")]
+    Condition(String),
+
+    #[error("Warning: {0}")]
+    Warning(String),
+
+    #[error("Unknown bundle {0:?}")]
+    UnknownBundle(String),
+
+    #[error("Unknown host {0:?}")]
+    UnknownHost(String),
+
+    #[error("Unknown profile {0:?}")]
+    UnknownProfile(String),
+
+    #[error("No sync {0} in the history")]
+    UnknownSync(u64),
+
+    #[error("No build of sync {0} is kept")]
+    UnknownGeneration(u64),
+
+    #[error("Includes itself")]
+    IncludeCycle,
+
+    #[error("File is not valid UTF-8")]
+    NotUtf8,
+
+    #[error("Invalid mode {0:?}, expected an octal number")]
+    Mode(String),
+
+    #[error("Mode is {actual:04o}, expected {expected:04o}")]
+    WrongMode { actual: u32, expected: u32 },
+
+    #[error("Directory is a symlink")]
+    SymlinkedDir,
+
+    #[error("Found {0} possible secrets")]
+    SecretsFound(usize),
+
+    #[error("Validation failed: {0}")]
+    Validation(String),
+
+    #[error("Files in the private directory must be encrypted")]
+    NotEncrypted,
+
+    #[error("There already is a pre-commit hook, and another one set aside as {0:?}")]
+    HookExists(PathBuf),
+
+    #[error("Sensitive variable {0:?} changed since the last sync, sync without --frozen")]
+    SecretChanged(String),
+
+    #[error("There is no lock file, sync without --frozen first")]
+    NoLock,
+
+    #[error("Managed by {manager} ({original:?}), to manage it here {}", .manager.resolution())]
+    ExternallyManaged { manager: Manager, original: PathBuf },
+
+    #[error("{0} needs the network, which --offline disallows")]
+    Offline(&'static str),
+
+    #[error("Not allowed to run {0:?}, run interactively or pass --trust-all")]
+    Untrusted(String),
+
+    #[error("Not linked from a file in the tree")]
+    NotInTree,
+
+    #[error("Managed by the shared tree, change it there")]
+    InSharedTree,
+
+    #[error("Not inside the link dir")]
+    OutsideLinkDir,
+
+    #[error("Already exists")]
+    AlreadyExists,
+
+    #[error("Not a regular file")]
+    NotRegularFile,
+
+    #[error("Collides with {0:?} on case-insensitive or normalizing file systems")]
+    CaseCollision(PathBuf),
+
+    #[error("There is no backup {0}")]
+    UnknownBackup(u64),
+
+    #[error("{0:?} is a reserved name on Windows")]
+    ReservedName(String),
+
+    #[error(
+        "Path is {0} characters long, longer than Windows allows unless long paths are enabled"
+    )]
+    PathTooLong(usize),
+
+    #[error("Needs to be run interactively")]
+    NotATerminal,
 }
 
 impl From<Vec<Error>> for Errors {
     fn from(errors: Vec<Error>) -> Self {
-        Errors { errors }
+        let mut all = Errors::default();
+        all.extend(errors, vec![]);
+        all
     }
 }
 
@@ -38,31 +198,130 @@ where
     E: Into<Error>,
 {
     fn from(error: E) -> Self {
-        Errors {
-            errors: vec![error.into()],
-        }
+        Errors::from(vec![error.into()])
     }
 }
 
 impl Errors {
-    pub fn join(&mut self, mut other: Errors) {
-        self.errors.append(&mut other.errors);
+    pub fn join(&mut self, other: Errors) {
+        self.partial |= other.partial;
+        self.extend(other.errors, other.dropped);
+    }
+
+    /// Mark the errors as only affecting part of what the command did.
+    pub fn partial(self) -> Self {
+        Errors {
+            partial: true,
+            ..self
+        }
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    /// Where the errors happened, including those left out because of [MAX_ERRORS].
+    pub fn locations(&self) -> impl Iterator<Item = &Path> {
+        self.errors
+            .iter()
+            .map(|error| error.location.as_path())
+            .chain(self.dropped.iter().map(PathBuf::as_path))
+    }
+
+    fn extend(&mut self, errors: Vec<Error>, mut dropped: Vec<PathBuf>) {
+        let room = MAX_ERRORS
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.errors.len());
+
+        let mut errors = errors.into_iter();
+        self.errors.extend(errors.by_ref().take(room));
+        self.dropped.append(&mut dropped);
+        self.dropped.extend(errors.map(|error| error.location));
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len() + self.dropped.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.errors.is_empty()
+        self.len() == 0
     }
 
     pub fn log(self) {
-        if self.errors.is_empty() {
+        if self.is_empty() {
             return;
         }
 
-        error!("{} errors occured:", self.errors.len());
-        for (i, error) in self.errors.iter().enumerate() {
-            error!("  err {:02} at {:?}:", i, error.location);
-            error!("      {}", error.inner);
+        // errors of the same kind in the same directory are usually the same mistake
+        let mut groups: BTreeMap<String, BTreeMap<&Path, Vec<&Path>>> = BTreeMap::new();
+        for error in &self.errors {
+            let dir = error.location.parent().unwrap_or(Path::new(""));
+            groups
+                .entry(redact(&error.message()))
+                .or_default()
+                .entry(dir)
+                .or_default()
+                .push(&error.location);
         }
+
+        error!("{} errors occured:", self.len());
+        for (message, dirs) in groups {
+            error!("  {message}");
+            for (dir, mut locations) in dirs {
+                locations.sort_unstable();
+                error!("    in {dir:?}:");
+                for location in locations {
+                    let name = location.strip_prefix(dir).unwrap_or(location);
+                    error!("      {name:?}");
+                }
+            }
+        }
+
+        if !self.dropped.is_empty() {
+            error!("  and {} more…", self.dropped.len());
+        }
+    }
+}
+
+impl Error {
+    /// The error and the errors which caused it, and what was being done.
+    fn message(&self) -> String {
+        let mut message = self.inner.to_string();
+
+        let mut source = self.inner.source();
+        while let Some(e) = source {
+            // some variants already include their source in their message
+            let s = e.to_string();
+            if !message.contains(&s) {
+                message = format!("{message}: {s}");
+            }
+            source = e.source();
+        }
+
+        match self.operation {
+            Some(operation) => format!("while {operation}: {message}"),
+            None => message,
+        }
+    }
+}
+
+pub trait ErrorOperation {
+    /// Record that the error occurred while doing `operation`.
+    fn during(self, operation: Operation) -> Self;
+}
+
+impl ErrorOperation for Error {
+    fn during(self, operation: Operation) -> Error {
+        Error {
+            operation: Some(operation),
+            ..self
+        }
+    }
+}
+
+impl<T> ErrorOperation for Result<T, Error> {
+    fn during(self, operation: Operation) -> Result<T, Error> {
+        self.map_err(|e| e.during(operation))
     }
 }
 
@@ -80,6 +339,7 @@ where
     fn with_location(self, path: &Path) -> Error {
         Error {
             location: path.to_owned(),
+            operation: None,
             inner: self.into(),
         }
     }
@@ -94,6 +354,7 @@ where
     fn with_location(self, path: &Path) -> Result<T, Error> {
         self.map_err(|e| Error {
             location: path.to_owned(),
+            operation: None,
             inner: e.into(),
         })
     }
@@ -0,0 +1,246 @@
+use crate::archive::write_archive;
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::private::is_decrypted;
+use crate::process::sh_quote;
+use crate::state::{read_sources, Sources};
+use crate::Config;
+use async_recursion::async_recursion;
+use clap::ValueEnum;
+use futures::future::join_all;
+use std::fmt::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::{metadata, read_dir, read_to_string};
+use tokio::join;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    /// An ansible playbook.
+    Ansible,
+
+    /// A POSIX shell script.
+    Sh,
+
+    /// A gzipped tarball of the build tree, for use with `restore`.
+    Tar,
+}
+
+enum Entry {
+    Dir(PathBuf),
+    File {
+        relative: PathBuf,
+        content: String,
+        mode: u32,
+    },
+}
+
+impl Entry {
+    fn relative(&self) -> &Path {
+        match self {
+            Entry::Dir(relative) => relative,
+            Entry::File { relative, .. } => relative,
+        }
+    }
+}
+
+/// Print a standalone script which recreates the build tree and links it into place, or an
+/// archive of the build tree.
+///
+/// The private directory and other decrypted files are left out, their content would end up in
+/// plain text.
+pub async fn export_tree(cfg: &Config, format: Format) -> Result<(), Errors> {
+    let script = match format {
+        Format::Ansible => ansible(&entries(cfg).await?),
+        Format::Sh => sh(&entries(cfg).await?),
+        Format::Tar => return Ok(write_archive(cfg).await?),
+    };
+
+    print!("{script}");
+
+    Ok(())
+}
+
+/// Collect all entries of the build tree, parents before children.
+async fn entries(cfg: &Config) -> Result<Vec<Entry>, Errors> {
+    let sources = read_sources(cfg).await?;
+    let mut entries = dir(cfg, &sources, PathBuf::new()).await?;
+    entries.sort_unstable_by(|a, b| a.relative().cmp(b.relative()));
+    Ok(entries)
+}
+
+#[async_recursion]
+async fn dir(cfg: &Config, sources: &Sources, relative: PathBuf) -> Result<Vec<Entry>, Errors> {
+    let build_path = cfg.build_dir.join(&relative);
+
+    info!("traversing {:?}", build_path);
+
+    let mut walker = read_dir(&build_path).await.with_location(&build_path)?;
+
+    let mut dir_tasks = vec![];
+    let mut file_tasks = vec![];
+
+    while let Some(entry) = walker.next_entry().await.with_location(&build_path)? {
+        let meta = entry.metadata().await.with_location(&entry.path())?;
+        let new_relative = relative.join(entry.file_name());
+
+        if cfg.skip(&new_relative) || is_decrypted(sources, &new_relative) {
+            debug!("skipping {:?}", entry.path());
+            continue;
+        }
+
+        if meta.is_dir() {
+            dir_tasks.push(dir(cfg, sources, new_relative));
+        } else if meta.is_file() {
+            file_tasks.push(file(cfg, new_relative));
+        }
+    }
+
+    let dirs = async { join_all(dir_tasks).await.into_iter().collect::<Vec<_>>() };
+    let files = async { join_all(file_tasks).await.into_iter().collect::<Vec<_>>() };
+    let (dirs, files) = join!(dirs, files);
+
+    let mut entries = vec![];
+    let mut errors = vec![];
+
+    if !relative.as_os_str().is_empty() {
+        entries.push(Entry::Dir(relative));
+    }
+
+    for result in files.into_iter() {
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    let mut errors: Errors = errors.into();
+
+    for result in dirs.into_iter() {
+        match result {
+            Ok(mut more_entries) => entries.append(&mut more_entries),
+            Err(error) => errors.join(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(entries)
+    } else {
+        Err(errors)
+    }
+}
+
+async fn file(cfg: &Config, relative: PathBuf) -> Result<Entry, Error> {
+    let build_path = cfg.build_dir.join(&relative);
+
+    debug!("reading {:?}", build_path);
+
+    let content = read_to_string(&build_path).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::InvalidData {
+            InnerError::NotUtf8.with_location(&build_path)
+        } else {
+            e.with_location(&build_path)
+        }
+    })?;
+
+    let mode = metadata(&build_path)
+        .await
+        .with_location(&build_path)?
+        .permissions()
+        .mode()
+        & 0o777;
+
+    Ok(Entry::File {
+        relative,
+        content,
+        mode,
+    })
+}
+
+fn sh(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    out.push_str("#!/bin/sh\n");
+    out.push_str("# generated by dotfiles export\n");
+    out.push_str("set -eu\n\n");
+    out.push_str("build_dir=\"${DOTFILES_BUILD_DIR:-${XDG_CACHE_HOME:-$HOME/.cache}/dotfiles}\"\n");
+    out.push_str("link_dir=\"${DOTFILES_LINK_DIR:-$HOME}\"\n\n");
+    out.push_str("mkdir -p \"$build_dir\" \"$link_dir\"\n");
+
+    for entry in entries {
+        let path = sh_quote(&entry.relative().to_string_lossy());
+        match entry {
+            Entry::Dir(_) => {
+                let _ = writeln!(out, "mkdir -p \"$build_dir\"/{path} \"$link_dir\"/{path}");
+            }
+            Entry::File { content, mode, .. } => {
+                let content = sh_quote(content);
+                let _ = writeln!(out, "printf '%s' {content} > \"$build_dir\"/{path}");
+                let _ = writeln!(out, "chmod {mode:o} \"$build_dir\"/{path}");
+                let _ = writeln!(out, "ln -sf \"$build_dir\"/{path} \"$link_dir\"/{path}");
+            }
+        }
+    }
+
+    out
+}
+
+fn ansible(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    out.push_str("# generated by dotfiles export\n");
+    out.push_str("- hosts: all\n");
+    out.push_str("  vars:\n");
+    out.push_str("    build_dir: \"{{ ansible_env.HOME }}/.cache/dotfiles\"\n");
+    out.push_str("    link_dir: \"{{ ansible_env.HOME }}\"\n");
+    out.push_str("  tasks:\n");
+
+    for entry in entries {
+        let path = yaml_raw(&entry.relative().to_string_lossy());
+        match entry {
+            Entry::Dir(_) => {
+                let _ = writeln!(out, "    - name: \"{path}\"");
+                let _ = writeln!(
+                    out,
+                    "      loop: [\"{{{{ build_dir }}}}\", \"{{{{ link_dir }}}}\"]"
+                );
+                let _ = writeln!(out, "      ansible.builtin.file:");
+                let _ = writeln!(out, "        path: \"{{{{ item }}}}/{path}\"");
+                let _ = writeln!(out, "        state: directory");
+            }
+            Entry::File { content, mode, .. } => {
+                let content = yaml_raw(content);
+                let _ = writeln!(out, "    - name: \"{path}\"");
+                let _ = writeln!(out, "      ansible.builtin.copy:");
+                let _ = writeln!(out, "        dest: \"{{{{ build_dir }}}}/{path}\"");
+                let _ = writeln!(out, "        content: \"{content}\"");
+                let _ = writeln!(out, "        mode: \"{mode:04o}\"");
+                let _ = writeln!(out, "    - name: \"link {path}\"");
+                let _ = writeln!(out, "      ansible.builtin.file:");
+                let _ = writeln!(out, "        src: \"{{{{ build_dir }}}}/{path}\"");
+                let _ = writeln!(out, "        dest: \"{{{{ link_dir }}}}/{path}\"");
+                let _ = writeln!(out, "        state: link");
+                let _ = writeln!(out, "        force: true");
+            }
+        }
+    }
+
+    out
+}
+
+/// Escape a string for use inside a double-quoted yaml scalar, and stop ansible from templating it.
+fn yaml_raw(s: &str) -> String {
+    let mut out = String::from("{% raw %}");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push_str("{% endraw %}");
+    out
+}
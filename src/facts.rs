@@ -0,0 +1,154 @@
+use crate::error::{Error, ErrorLocation};
+use crate::state::{read_state, unix_time, write_state};
+use crate::wsl::{detect_wsl, Wsl};
+use crate::Config;
+use futures::TryFutureExt;
+use serde::{Deserialize, Serialize};
+use std::str::from_utf8;
+use std::time::Duration;
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+
+/// Name of the file in the config dir which pins facts about the current machine, instead of
+/// detecting them.
+///
+/// ```toml
+/// hostname = "laptop"
+/// os = "darwin"
+/// ```
+const FACTS_FILE: &str = "facts.toml";
+
+/// Name of the file in the state dir which caches the detected facts.
+const DETECTED_FILE: &str = "detected.toml";
+
+/// How long detected facts are reused before detecting them again.
+const DETECTED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Facts about the current machine which are set by the user rather than detected.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Facts {
+    pub hostname: Option<String>,
+    pub os: Option<String>,
+}
+
+impl Facts {
+    /// Use the facts set in `other`, and the ones in `self` for the rest.
+    pub fn overridden_by(self, other: Facts) -> Facts {
+        Facts {
+            hostname: other.hostname.or(self.hostname),
+            os: other.os.or(self.os),
+        }
+    }
+}
+
+/// Facts about the current machine which are slow to detect, and therefore cached.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Detected {
+    /// When the facts were detected, in seconds since the epoch.
+    pub detected_at: u64,
+
+    /// The hostname as configured, which may or may not be fully qualified.
+    pub hostname: String,
+
+    /// The fully qualified name of the machine according to the resolver, if it has one.
+    pub fqdn: Option<String>,
+
+    pub os: String,
+    pub wsl: Option<Wsl>,
+}
+
+/// Detect the facts about the current machine, or reuse the ones detected recently unless
+/// `refresh` is set.
+pub async fn detect_facts(cfg: &Config, refresh: bool) -> Result<Detected, Error> {
+    let now = unix_time();
+
+    let cached: Detected = read_state(cfg, DETECTED_FILE).await?;
+    if !refresh && now.saturating_sub(cached.detected_at) < DETECTED_TTL.as_secs() {
+        debug!("using facts detected at {}", cached.detected_at);
+        return Ok(cached);
+    }
+
+    info!("detecting facts");
+    let detected = Detected {
+        detected_at: now,
+        hostname: get_hostname(cfg.termux).await,
+        // the resolver may ask a name server
+        fqdn: match cfg.offline {
+            true => cached.fqdn,
+            false => get_fqdn().await,
+        },
+        os: get_operating_system().await,
+        wsl: detect_wsl().await,
+    };
+
+    write_state(cfg, DETECTED_FILE, &detected).await?;
+    Ok(detected)
+}
+
+pub async fn read_facts(cfg: &Config) -> Result<Facts, Error> {
+    let path = cfg.config_dir.join(FACTS_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(Facts::default());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+async fn get_hostname(termux: bool) -> String {
+    async fn read_hostname_file() -> eyre::Result<String> {
+        Ok(read_to_string("/etc/hostname").await?)
+    }
+
+    async fn run_hostname_cmd() -> eyre::Result<String> {
+        Command::new("hostname")
+            .output()
+            .await
+            .map_err(Into::into)
+            .and_then(|out| Ok(from_utf8(&out.stdout).map(str::to_string)?))
+    }
+
+    // android has neither /etc/hostname nor a useful hostname command
+    async fn run_getprop(property: &str) -> eyre::Result<String> {
+        let out = Command::new("getprop").arg(property).output().await?;
+        let value = from_utf8(&out.stdout)?.trim().to_string();
+        if value.is_empty() {
+            eyre::bail!("{property} is not set");
+        }
+        Ok(value)
+    }
+
+    let hostname = if termux {
+        run_getprop("net.hostname")
+            .or_else(|_| run_getprop("ro.product.device"))
+            .or_else(|_| run_hostname_cmd())
+            .await
+    } else {
+        read_hostname_file().or_else(|_| run_hostname_cmd()).await
+    };
+
+    hostname.unwrap_or(String::new()).trim().to_string()
+}
+
+/// Ask the resolver for the fully qualified name of the current machine.
+async fn get_fqdn() -> Option<String> {
+    let out = Command::new("hostname").arg("-f").output().await.ok()?;
+    let fqdn = from_utf8(&out.stdout).ok()?.trim().trim_end_matches('.');
+
+    (out.status.success() && fqdn.contains('.')).then(|| fqdn.to_lowercase())
+}
+
+async fn get_operating_system() -> String {
+    Command::new("uname")
+        .output()
+        .await
+        .ok()
+        .as_ref()
+        .and_then(|out| from_utf8(&out.stdout).ok())
+        .unwrap_or("unknown")
+        .trim()
+        .to_lowercase()
+}
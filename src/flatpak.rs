@@ -0,0 +1,80 @@
+use crate::error::{ErrorLocation, Errors};
+use crate::process::run;
+use crate::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+
+/// File at the root of the template tree declaring the overrides of flatpak apps.
+///
+/// ```toml
+/// ["org.mozilla.firefox"]
+/// filesystems = ["~/Downloads"]
+/// nofilesystems = ["home"]
+/// env = { MOZ_ENABLE_WAYLAND = "1" }
+/// ```
+///
+/// The user overrides of each listed app are reset before these are applied, so removing an
+/// override from the file removes it from the app.
+pub const FLATPAK_FILE: &str = ".flatpak.toml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Overrides {
+    #[serde(default)]
+    filesystems: Vec<String>,
+
+    #[serde(default)]
+    nofilesystems: Vec<String>,
+
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+/// Apply the flatpak overrides declared in the tree, if there are any.
+pub async fn apply_overrides(cfg: &Config) -> Result<(), Errors> {
+    let path = cfg.template_dir.join(FLATPAK_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(());
+    };
+
+    let apps: BTreeMap<String, Overrides> = toml::de::from_str(&s).with_location(&path)?;
+
+    let mut errors = vec![];
+    for (app, overrides) in &apps {
+        let mut reset = Command::new("flatpak");
+        reset.args(["override", "--user", "--reset", app]);
+
+        let mut cmd = Command::new("flatpak");
+        cmd.args(["override", "--user"]);
+        for filesystem in &overrides.filesystems {
+            cmd.arg(format!("--filesystem={filesystem}"));
+        }
+        for filesystem in &overrides.nofilesystems {
+            cmd.arg(format!("--nofilesystem={filesystem}"));
+        }
+        for (key, value) in &overrides.env {
+            cmd.arg(format!("--env={key}={value}"));
+        }
+        cmd.arg(app);
+
+        info!("applying flatpak overrides of {app}");
+        if let Err(e) = run(reset).await {
+            errors.push(e.with_location(&path));
+            continue;
+        }
+        if let Err(e) = run(cmd).await {
+            errors.push(e.with_location(&path));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
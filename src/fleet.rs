@@ -0,0 +1,96 @@
+use crate::error::{ErrorLocation, Errors};
+use crate::inventory::read_inventory;
+use crate::process::run;
+use crate::push::push;
+use crate::Config;
+use clap::Subcommand;
+use futures::future::join_all;
+use std::path::Path;
+use tokio::process::Command;
+
+#[derive(Subcommand)]
+pub enum FleetAction {
+    /// Sync every host in the inventory, or only the given ones.
+    Sync {
+        hosts: Vec<String>,
+
+        /// Run this command on each host over ssh, e.g. "dotfiles sync", instead of pushing the
+        /// tree rendered here.
+        #[arg(long, value_name = "COMMAND")]
+        remote: Option<String>,
+    },
+}
+
+/// Sync the hosts in the inventory over ssh, all at once, and print a report of how each went.
+///
+/// Unless `remote` is set, the tree is rendered here for each host and pushed to it, see
+/// [push]. Hosts which fail don't stop the others.
+pub async fn sync_fleet(cfg: &Config, only: &[String], remote: Option<&str>) -> Result<(), Errors> {
+    cfg.require_network("syncing the fleet")
+        .with_location(&cfg.inventory_path)?;
+
+    let hosts: Vec<_> = read_inventory(cfg)
+        .await?
+        .into_values()
+        .filter(|host| only.is_empty() || only.contains(&host.name))
+        .collect();
+
+    let results = join_all(hosts.into_iter().map(|host| async move {
+        let destination = host.ssh_destination();
+        let name = host.name.clone();
+        let result = match remote {
+            Some(command) => run_remote(&destination, command).await,
+            None => {
+                let cfg = Config {
+                    host: Some(host),
+                    ..cfg.clone()
+                };
+                push(&cfg, &destination).await.map(|pushed| {
+                    format!(
+                        "pushed {} files, removed {} files",
+                        pushed.changed, pushed.removed
+                    )
+                })
+            }
+        };
+        (name, result)
+    }))
+    .await;
+
+    let mut errors = Errors::default();
+    let mut synced = 0;
+
+    println!("fleet sync:");
+    for (name, result) in results {
+        match result {
+            Ok(summary) => {
+                synced += 1;
+                println!("  {name}: ok, {summary}");
+            }
+            Err(e) => {
+                println!("  {name}: failed with {} errors", e.len());
+                errors.join(e);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else if synced > 0 {
+        Err(errors.partial())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Run `command` on the host at `destination`, and summarize its output.
+async fn run_remote(destination: &str, command: &str) -> Result<String, Errors> {
+    let mut cmd = Command::new("ssh");
+    cmd.arg(destination).arg(command);
+
+    info!("running {command:?} on {destination}");
+    let out = run(cmd).await.with_location(Path::new(destination))?;
+
+    let summary = out.lines().last().unwrap_or("ran").to_string();
+    Ok(summary)
+}
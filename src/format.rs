@@ -0,0 +1,50 @@
+use crate::error::{Error, ErrorLocation};
+use crate::glob::{read_command_rules, GlobRules};
+use crate::process::{command_line, run_with_input};
+use crate::Config;
+use std::path::Path;
+
+/// Name of the file in the config dir which maps globs in the tree to formatters.
+///
+/// ```toml
+/// "**/*.toml" = "taplo fmt -"
+/// "**/*.sh" = "shfmt --filename %f"
+/// "**/*.json" = "jq ."
+/// ```
+///
+/// Formatters read the rendered file on stdin and write the formatted file to stdout. `%f` is
+/// replaced with the path of the file in the build dir.
+pub const FORMATTERS_FILE: &str = "formatters.toml";
+
+#[derive(Clone, Debug, Default)]
+pub struct Formatters {
+    rules: GlobRules<String>,
+}
+
+impl Formatters {
+    /// Run the formatter matching the file at `relative` in the tree, if any, on its rendered
+    /// content.
+    pub async fn format(
+        &self,
+        relative: &Path,
+        build_path: &Path,
+        rendered: String,
+    ) -> Result<String, Error> {
+        let Some(command) = self.rules.find(relative) else {
+            return Ok(rendered);
+        };
+
+        debug!("formatting {:?} with {:?}", build_path, command);
+        let cmd = command_line(command, build_path);
+        run_with_input(cmd, rendered.as_bytes())
+            .await
+            .with_location(build_path)
+    }
+}
+
+pub async fn read_formatters(cfg: &Config) -> Result<Formatters, Error> {
+    let path = cfg.config_dir.join(FORMATTERS_FILE);
+    Ok(Formatters {
+        rules: read_command_rules(&path).await?,
+    })
+}
@@ -0,0 +1,59 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Delimiter of the front matter block at the very start of a template.
+///
+/// ```text
+/// +++
+/// foreach = "monitors"
+/// as = "monitor"
+/// output = "monitor-{monitor}.conf"
+/// when = "os == 'linux'"
+/// +++
+/// ```
+const DELIMITER: &str = "+++";
+
+/// Settings for a single template, parsed from its front matter.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FrontMatter {
+    /// Render the template once per item of this list variable.
+    pub foreach: Option<String>,
+
+    /// Name of the loop variable when using `foreach`. Defaults to `item`.
+    #[serde(rename = "as")]
+    pub loop_var: Option<String>,
+
+    /// File name of each output when using `foreach`, where `{<loop variable>}` is replaced by
+    /// the item.
+    pub output: Option<String>,
+
+    /// Link the rendered file here instead, relative to the link dir unless absolute. Ignored when
+    /// using `foreach`.
+    pub target: Option<PathBuf>,
+
+    /// Mode of the rendered file, e.g. `"0600"`.
+    pub mode: Option<String>,
+
+    /// Only render the template if this condition holds, e.g. `"os == 'linux'"`.
+    pub when: Option<String>,
+}
+
+/// Split a template into its front matter, if any, and its body.
+pub fn split_front_matter(template: &str) -> Result<(FrontMatter, &str), toml::de::Error> {
+    let Some(rest) = template
+        .strip_prefix(DELIMITER)
+        .and_then(|rest| rest.strip_prefix('\n'))
+    else {
+        return Ok((FrontMatter::default(), template));
+    };
+
+    let Some(end) = rest.find(&format!("\n{DELIMITER}\n")) else {
+        return Ok((FrontMatter::default(), template));
+    };
+
+    let front_matter = toml::de::from_str(&rest[..end])?;
+    let body = &rest[end + DELIMITER.len() + 2..];
+
+    Ok((front_matter, body))
+}
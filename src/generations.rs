@@ -0,0 +1,84 @@
+use crate::diff::{diff_trees, list_files, Side};
+use crate::error::{ErrorLocation, Errors, InnerError};
+use crate::state::{read_sources, write_sources};
+use crate::Config;
+use std::path::PathBuf;
+use tokio::fs::{copy, create_dir_all};
+
+/// Name of the directory in the state dir with a copy of the build tree of each sync.
+pub const GENERATIONS_DIR: &str = "generations";
+
+/// Name of the directory in a generation with the copy of the build tree.
+const BUILD_DIR: &str = "build";
+
+/// Where the build tree of the sync `id` is kept, along with what its files were built from.
+fn generation_dir(cfg: &Config, id: u64) -> PathBuf {
+    cfg.state_dir.join(GENERATIONS_DIR).join(id.to_string())
+}
+
+/// The config of the generation `id`, where the state dir holds its sources.
+fn generation_cfg(cfg: &Config, id: u64) -> Config {
+    let dir = generation_dir(cfg, id);
+    Config {
+        build_dir: dir.join(BUILD_DIR),
+        state_dir: dir,
+        ..cfg.clone()
+    }
+}
+
+/// Keep a copy of the build tree as the generation `id`, named after the sync which built it.
+pub async fn save_generation(cfg: &Config, id: u64) -> Result<(), Errors> {
+    let generation = generation_cfg(cfg, id);
+
+    for relative in list_files(&cfg.build_dir).await? {
+        let from = cfg.build_dir.join(&relative);
+        let to = generation.build_dir.join(&relative);
+        if let Some(parent) = to.parent() {
+            create_dir_all(parent).await.with_location(parent)?;
+        }
+        copy(&from, &to).await.with_location(&from)?;
+    }
+
+    // which files were decrypted, and must not be shown in diffs
+    write_sources(&generation, &read_sources(cfg).await?).await?;
+
+    Ok(())
+}
+
+/// Print a diff of the build trees of the syncs `from` and `to`, as listed by history.
+///
+/// Returns whether they differ.
+pub async fn diff_generations(cfg: &Config, from: u64, to: u64) -> Result<bool, Errors> {
+    let from_cfg = generation_cfg(cfg, from);
+    let to_cfg = generation_cfg(cfg, to);
+
+    for (id, generation) in [(from, &from_cfg), (to, &to_cfg)] {
+        if !generation.build_dir.is_dir() {
+            return Err(InnerError::UnknownGeneration(id)
+                .with_location(&generation.state_dir)
+                .into());
+        }
+    }
+
+    let mut files = list_files(&from_cfg.build_dir).await?;
+    files.append(&mut list_files(&to_cfg.build_dir).await?);
+    files.sort_unstable();
+    files.dedup();
+
+    let from_label = from.to_string();
+    let to_label = to.to_string();
+    let from_sources = read_sources(&from_cfg).await?;
+    let to_sources = read_sources(&to_cfg).await?;
+    let from_side = Side {
+        label: &from_label,
+        root: &from_cfg.build_dir,
+        sources: &from_sources,
+    };
+    let to_side = Side {
+        label: &to_label,
+        root: &to_cfg.build_dir,
+        sources: &to_sources,
+    };
+
+    diff_trees(&from_side, &to_side, &files).await
+}
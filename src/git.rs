@@ -0,0 +1,153 @@
+use crate::process::run;
+use std::io;
+use std::path::Path;
+use tokio::process::Command;
+
+fn git(dir: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(dir);
+    cmd
+}
+
+/// Get the commit checked out in the repository at `dir`.
+pub async fn head(dir: &Path) -> io::Result<String> {
+    let mut cmd = git(dir);
+    cmd.args(["rev-parse", "HEAD"]);
+
+    Ok(run(cmd).await?.trim().to_string())
+}
+
+/// Clone the repository at `url` into `dir`.
+pub async fn clone(url: &str, dir: &Path, shallow: bool) -> io::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.arg("clone");
+    if shallow {
+        cmd.arg("--depth=1");
+    }
+    cmd.arg(url).arg(dir);
+
+    run(cmd).await?;
+    Ok(())
+}
+
+/// Create an empty repository in `dir`.
+pub async fn init(dir: &Path) -> io::Result<()> {
+    let mut cmd = Command::new("git");
+    cmd.arg("init").arg(dir);
+
+    run(cmd).await?;
+    Ok(())
+}
+
+/// Pull the branch checked out in the repository at `dir`.
+pub async fn pull(dir: &Path) -> io::Result<()> {
+    let mut cmd = git(dir);
+    cmd.args(["pull", "--no-rebase", "--no-edit"]);
+
+    run(cmd).await?;
+    Ok(())
+}
+
+/// Stage every change in the repository at `dir`, and list the staged files with how they
+/// changed, e.g. `M` for modified.
+pub async fn stage_all(dir: &Path) -> io::Result<Vec<(String, String)>> {
+    let mut cmd = git(dir);
+    cmd.args(["add", "--all"]);
+    run(cmd).await?;
+
+    let mut cmd = git(dir);
+    cmd.args(["diff", "--cached", "--name-status"]);
+
+    let staged = run(cmd)
+        .await?
+        .lines()
+        .filter_map(|line| {
+            // renames and copies list the old and the new path
+            let (status, paths) = line.split_once('\t')?;
+            let path = paths.rsplit('\t').next()?;
+            Some((status.to_string(), path.to_string()))
+        })
+        .collect();
+    Ok(staged)
+}
+
+/// Commit what is staged in the repository at `dir`.
+pub async fn commit(dir: &Path, message: &str) -> io::Result<()> {
+    let mut cmd = git(dir);
+    cmd.args(["commit", "--quiet", "--message", message]);
+
+    run(cmd).await?;
+    Ok(())
+}
+
+/// Push the branch checked out in the repository at `dir` to its upstream.
+pub async fn push(dir: &Path) -> io::Result<()> {
+    let mut cmd = git(dir);
+    cmd.arg("push");
+
+    run(cmd).await?;
+    Ok(())
+}
+
+/// List the tracked files with changes which aren't committed.
+pub async fn modified_files(dir: &Path) -> io::Result<Vec<String>> {
+    let mut cmd = git(dir);
+    cmd.args(["diff", "--name-only", "HEAD"]);
+
+    Ok(run(cmd).await?.lines().map(str::to_string).collect())
+}
+
+/// Discard the uncommitted changes to `paths`.
+pub async fn restore(dir: &Path, paths: &[String]) -> io::Result<()> {
+    let mut cmd = git(dir);
+    cmd.args(["checkout", "HEAD", "--"]).args(paths);
+
+    run(cmd).await?;
+    Ok(())
+}
+
+/// Get the content of `path` at `commit`, if it existed then.
+pub async fn show(dir: &Path, commit: &str, path: &str) -> io::Result<Option<String>> {
+    let mut cmd = git(dir);
+    cmd.arg("show").arg(format!("{commit}:{path}"));
+
+    match run(cmd).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e)
+            if ["does not exist", "but not in"]
+                .iter()
+                .any(|message| e.to_string().contains(message)) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Merge the changes from `base` to `theirs` into `ours`, all paths to files.
+///
+/// Returns the merged content, and whether it has conflicts, which are marked in it.
+pub async fn merge_file(ours: &Path, base: &Path, theirs: &Path) -> io::Result<(String, bool)> {
+    let mut cmd = Command::new("git");
+    cmd.args(["merge-file", "--stdout", "--diff3"])
+        .args(["-L", "local", "-L", "last sync", "-L", "upstream"])
+        .arg(ours)
+        .arg(base)
+        .arg(theirs);
+
+    debug!("running {cmd:?}");
+    let out = cmd.output().await?;
+
+    // the exit code is the number of conflicts, capped at 127, or 255 on errors such as binary
+    // files, which leave nothing on stdout
+    match out.status.code() {
+        Some(code @ 0..=127) => {
+            let merged = String::from_utf8_lossy(&out.stdout).into_owned();
+            Ok((merged, code > 0))
+        }
+        _ => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            Err(io::Error::other(stderr.trim().to_string()))
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::error::{Error, ErrorLocation};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs::read_to_string;
+
+/// A glob pattern matched against relative paths.
+///
+/// `*` and `?` match within a single path component, `**` matches any number of components.
+#[derive(Clone, Debug)]
+pub struct Glob {
+    pattern: String,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        Glob {
+            pattern: pattern.trim_matches('/').to_string(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let pattern: Vec<&str> = self.pattern.split('/').collect();
+        let path = path.to_string_lossy();
+        let path: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        match_components(&pattern, &path)
+    }
+}
+
+/// Something for each path in the tree matching a glob. If several globs match a path, the
+/// longest one wins.
+#[derive(Clone, Debug)]
+pub struct GlobRules<T> {
+    rules: Vec<(Glob, T)>,
+}
+
+impl<T> Default for GlobRules<T> {
+    fn default() -> Self {
+        GlobRules { rules: vec![] }
+    }
+}
+
+impl<T> GlobRules<T> {
+    /// What the rule for `relative` in the tree says, if any rule matches it.
+    pub fn find(&self, relative: &Path) -> Option<&T> {
+        self.rules
+            .iter()
+            .filter(|(glob, _)| glob.matches(relative))
+            .max_by_key(|(glob, _)| glob.as_str().len())
+            .map(|(_, value)| value)
+    }
+}
+
+impl<T> FromIterator<(String, T)> for GlobRules<T> {
+    fn from_iter<I: IntoIterator<Item = (String, T)>>(rules: I) -> Self {
+        GlobRules {
+            rules: rules
+                .into_iter()
+                .map(|(glob, value)| (Glob::new(&glob), value))
+                .collect(),
+        }
+    }
+}
+
+/// Read the file at `path` which maps globs in the tree to commands, where there are no rules if
+/// it doesn't exist.
+pub async fn read_command_rules(path: &Path) -> Result<GlobRules<String>, Error> {
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(GlobRules::default());
+    };
+
+    let raw: HashMap<String, String> = toml::de::from_str(&s).with_location(path)?;
+    Ok(raw.into_iter().collect())
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_components(rest, &path[i..])),
+        Some((component, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                let component: Vec<char> = component.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                match_wildcard(&component, &name) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_wildcard(pattern: &[char], s: &[char]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some(('*', rest)) => (0..=s.len()).any(|i| match_wildcard(rest, &s[i..])),
+        Some(('?', rest)) => !s.is_empty() && match_wildcard(rest, &s[1..]),
+        Some((c, rest)) => s.first() == Some(c) && match_wildcard(rest, &s[1..]),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::builder::TEMPLATE_EXTENSION;
+use crate::diff::list_files;
+use crate::error::Errors;
+use crate::glob::Glob;
+use crate::lint::read_template;
+use crate::reload::read_reloads;
+use crate::tplcache::save_template_cache;
+use crate::Config;
+use clap::ValueEnum;
+use futures::future::join_all;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum GraphFormat {
+    /// A graphviz digraph, e.g. for `dot -Tsvg`.
+    #[default]
+    Dot,
+
+    /// One line per template.
+    Text,
+}
+
+/// A template, and what it depends on and triggers.
+struct Node {
+    relative: PathBuf,
+    variables: Vec<String>,
+    reloads: Vec<String>,
+}
+
+/// Print how variables flow into templates, and which reload commands the templates trigger.
+pub async fn print_graph(cfg: &Config, format: GraphFormat) -> Result<(), Errors> {
+    let mut files = list_files(&cfg.template_dir).await?;
+    files.retain(|relative| {
+        !cfg.skip(relative) && relative.extension() == Some(OsStr::new(TEMPLATE_EXTENSION))
+    });
+
+    let reloads: Vec<(Glob, String)> = read_reloads(cfg)
+        .await?
+        .into_iter()
+        .map(|(glob, command)| (Glob::new(&glob), command))
+        .collect();
+
+    let mut nodes = vec![];
+    let mut errors = vec![];
+    for (relative, result) in files
+        .iter()
+        .zip(join_all(files.iter().map(|relative| read_template(cfg, relative))).await)
+    {
+        let template = match result {
+            Ok(template) => template,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+
+        let mut variables = template.variables;
+        variables.sort();
+        variables.dedup();
+
+        let output = relative.with_extension("");
+        let reloads = reloads
+            .iter()
+            .filter(|(glob, _)| glob.matches(&output))
+            .map(|(_, command)| command.clone())
+            .collect();
+
+        nodes.push(Node {
+            relative: relative.clone(),
+            variables,
+            reloads,
+        });
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    match format {
+        GraphFormat::Dot => print_dot(&nodes),
+        GraphFormat::Text => {
+            for node in &nodes {
+                let mut line =
+                    format!("{}: {}", node.relative.display(), node.variables.join(", "));
+                if !node.reloads.is_empty() {
+                    line.push_str(&format!(" -> {}", node.reloads.join(", ")));
+                }
+                println!("{line}");
+            }
+        }
+    }
+
+    save_template_cache(cfg).await?;
+
+    Ok(())
+}
+
+fn print_dot(nodes: &[Node]) {
+    println!("digraph dotfiles {{");
+    println!("    rankdir=LR;");
+
+    for node in nodes {
+        let template = format!("{:?}", node.relative.to_string_lossy());
+        println!("    {template} [shape=note];");
+
+        for variable in &node.variables {
+            println!("    {variable:?} [shape=ellipse];");
+            println!("    {variable:?} -> {template};");
+        }
+
+        for command in &node.reloads {
+            println!("    {command:?} [shape=box];");
+            println!("    {template} -> {command:?};");
+        }
+    }
+
+    println!("}}");
+}
@@ -0,0 +1,67 @@
+use crate::builder::TEMPLATE_EXTENSION;
+use crate::diff::list_files;
+use crate::error::{ErrorLocation, Errors};
+use crate::private::{is_decrypted, is_private};
+use crate::redact::redact;
+use crate::state::read_sources;
+use crate::Config;
+use std::ffi::OsStr;
+use tokio::fs::read;
+
+/// Print the lines of the template tree, or of the build tree if `rendered` is set, which contain
+/// `pattern`, with where the file ends up or what it was made from.
+///
+/// Private files, and files decrypted into the build tree, are never searched.
+pub async fn grep_tree(cfg: &Config, pattern: &str, rendered: bool) -> Result<usize, Errors> {
+    let root = match rendered {
+        true => &cfg.build_dir,
+        false => &cfg.template_dir,
+    };
+
+    let sources = match rendered {
+        true => read_sources(cfg).await?,
+        false => Default::default(),
+    };
+
+    let mut files = list_files(root).await?;
+    files.retain(|relative| {
+        let private = match rendered {
+            true => is_decrypted(&sources, relative),
+            false => is_private(relative),
+        };
+        !cfg.skip(relative) && !relative.starts_with(".git") && !private
+    });
+
+    let mut found = 0;
+    for relative in files {
+        let path = root.join(&relative);
+        let content = read(&path).await.with_location(&path)?;
+
+        // binary files can't contain lines
+        let Ok(content) = String::from_utf8(content) else {
+            continue;
+        };
+
+        let origin = if rendered {
+            let source = sources.get(&*relative.to_string_lossy());
+            let source = source.map(String::as_str).unwrap_or("unknown");
+            format!("-> {}, from {source}", cfg.link_path(&relative).display())
+        } else {
+            let output = match relative.extension() == Some(OsStr::new(TEMPLATE_EXTENSION)) {
+                true => relative.with_extension(""),
+                false => relative.clone(),
+            };
+            format!("-> {}", cfg.link_path(&output).display())
+        };
+
+        for (i, line) in content.lines().enumerate() {
+            if line.contains(pattern) {
+                found += 1;
+                let line = redact(line.trim());
+                println!("{}:{}: {line} ({origin})", relative.display(), i + 1);
+            }
+        }
+    }
+
+    Ok(found)
+}
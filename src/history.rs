@@ -0,0 +1,143 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::lock::Lock;
+use crate::mv::find_target;
+use crate::state::{read_journal, read_sources, unix_time, write_journal, SyncEntry, JOURNAL_FILE};
+use crate::Config;
+use clap::Subcommand;
+use std::path::{absolute, Path};
+
+#[derive(Subcommand)]
+pub enum HistoryAction {
+    /// Show the commit a sync applied and the files it changed.
+    Show {
+        /// The sync to show, as listed by history.
+        id: u64,
+    },
+}
+
+/// Record the sync at `at` which produced `lock` in the journal, along with the files which
+/// changed since the sync recorded in `previous`.
+pub async fn record_sync(
+    cfg: &Config,
+    at: u64,
+    previous: Option<&Lock>,
+    lock: &Lock,
+) -> Result<(), Error> {
+    let files = previous
+        .map(|previous| lock.changes_since(previous))
+        .unwrap_or_default();
+
+    let mut journal = read_journal(cfg).await?;
+    journal.syncs.push(SyncEntry {
+        at,
+        commit: lock.commit().map(str::to_string),
+        files,
+    });
+    write_journal(cfg, &journal).await
+}
+
+/// List the past syncs, or only those which changed `path`, oldest first.
+///
+/// `path` is either in the template tree, where a directory matches every file under it, or a
+/// link to a file in the tree.
+pub async fn print_history(cfg: &Config, path: Option<&Path>) -> Result<(), Error> {
+    let only = match path {
+        Some(path) => Some(source_of(cfg, path).await?),
+        None => None,
+    };
+
+    let journal = read_journal(cfg).await?;
+    let now = unix_time();
+
+    for sync in &journal.syncs {
+        let changed = sync
+            .files
+            .keys()
+            .filter(|file| match &only {
+                Some(only) => Path::new(file).starts_with(only),
+                None => true,
+            })
+            .count();
+
+        if only.is_some() && changed == 0 {
+            continue;
+        }
+
+        let commit = sync.commit.as_deref().map_or("-", short_commit);
+        println!(
+            "{}  {:>8} ago  {commit}  {changed} files changed",
+            sync.at,
+            age(now.saturating_sub(sync.at)),
+        );
+    }
+
+    Ok(())
+}
+
+/// Print what the sync `id` applied: the commit, the files which changed since the sync before it,
+/// and the changes made to the tree with add and rm in between.
+pub async fn show_sync(cfg: &Config, id: u64) -> Result<(), Error> {
+    let journal = read_journal(cfg).await?;
+    let location = cfg.state_dir.join(JOURNAL_FILE);
+
+    let Some(index) = journal.syncs.iter().position(|sync| sync.at == id) else {
+        return Err(InnerError::UnknownSync(id).with_location(&location));
+    };
+    let sync = &journal.syncs[index];
+    let since = index
+        .checked_sub(1)
+        .map(|before| journal.syncs[before].at)
+        .unwrap_or_default();
+
+    println!("sync {id}, {} ago", age(unix_time().saturating_sub(id)));
+    match &sync.commit {
+        Some(commit) => println!("commit: {commit}"),
+        None => println!("commit: none, the tree isn't a git repository"),
+    }
+
+    if sync.files.is_empty() {
+        println!("no files changed");
+    }
+    for (path, change) in &sync.files {
+        println!("{:<9} {path}", format!("{change}:"));
+    }
+
+    for entry in &journal.entries {
+        if entry.at > since && entry.at <= id {
+            println!(
+                "{}: {} ({})",
+                entry.action,
+                entry.source,
+                entry.target.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The path in the template tree `path` refers to.
+async fn source_of(cfg: &Config, path: &Path) -> Result<String, Error> {
+    let absolute = absolute(path).with_location(path)?;
+    if let Ok(relative) = absolute.strip_prefix(&cfg.template_dir) {
+        return Ok(relative.to_string_lossy().into_owned());
+    }
+
+    let sources = read_sources(cfg).await?;
+    let (_, source) = find_target(cfg, &sources, &absolute)?;
+    Ok(source)
+}
+
+fn short_commit(commit: &str) -> &str {
+    commit.get(..10).unwrap_or(commit)
+}
+
+/// Roughly how long `secs` seconds is, in the largest unit which fits.
+fn age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
@@ -0,0 +1,147 @@
+use crate::builder::build_env;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::state::{read_trusted, write_trusted};
+use crate::trust::is_trusted;
+use crate::warnings::warning;
+use crate::Config;
+use blueprint::Value;
+use serde::Deserialize;
+use std::path::Path;
+use tokio::fs::read_to_string;
+
+/// Directory in the template tree with executables that are run around syncing, named after the
+/// [Hook] they implement.
+pub const HOOKS_DIR: &str = "hooks";
+
+/// Name of the file in the config dir with command lines that are run around syncing, in addition
+/// to the executables in [HOOKS_DIR].
+///
+/// ```toml
+/// pre-sync = "systemctl --user stop kanshi"
+/// post-sync = "swaymsg reload"
+/// ```
+const HOOKS_FILE: &str = "hooks.toml";
+
+/// Prefix of the environment variables which hooks get the env of the templates in.
+const ENV_PREFIX: &str = "DOTFILES_";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Hook {
+    /// Run before building the tree.
+    PreSync,
+
+    /// Run after the tree is linked and applied.
+    PostSync,
+}
+
+impl Hook {
+    fn name(self) -> &'static str {
+        match self {
+            Hook::PreSync => "pre-sync",
+            Hook::PostSync => "post-sync",
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct HookCommands {
+    pre_sync: Option<String>,
+    post_sync: Option<String>,
+}
+
+/// Run the executable and the command line for `hook`, if there are any, with the env of the
+/// templates as `DOTFILES_*` environment variables.
+pub async fn run_hook(cfg: &Config, hook: Hook) -> Result<(), Errors> {
+    let mut hooks = vec![];
+
+    let script = cfg.template_dir.join(HOOKS_DIR).join(hook.name());
+    if let Ok(content) = read_to_string(&script).await {
+        // the whole script is what the user allows to run
+        hooks.push(("%f".to_string(), content, script));
+    }
+
+    let config_path = cfg.config_dir.join(HOOKS_FILE);
+    let commands = read_hook_commands(&config_path).await?;
+    let command = match hook {
+        Hook::PreSync => commands.pre_sync,
+        Hook::PostSync => commands.post_sync,
+    };
+    if let Some(command) = command {
+        hooks.push((command.clone(), command, config_path));
+    }
+
+    if hooks.is_empty() {
+        return Ok(());
+    }
+
+    let env = build_env(cfg).await?;
+    let mut trusted = read_trusted(cfg).await?;
+    let mut errors = vec![];
+
+    for (line, content, path) in hooks {
+        let name = format!("{} hook in {path:?}", hook.name());
+        match is_trusted(cfg, &mut trusted, &name, &content).await {
+            Ok(true) => {}
+            Ok(false) => {
+                warning(&path, format!("not running the {} hook", hook.name()));
+                continue;
+            }
+            Err(e) => {
+                errors.push(e.with_location(&path));
+                continue;
+            }
+        }
+
+        let mut cmd = cfg.sandbox.command(cfg, &line, &path);
+        for (key, value) in &env {
+            let value = match value {
+                Value::Str(s) => s.clone(),
+                Value::Bool(b) => b.to_string(),
+                _ => continue,
+            };
+            cmd.env(env_name(key), value);
+        }
+
+        info!("running the {} hook in {path:?}", hook.name());
+        match cfg.sandbox.run(cmd).await {
+            Ok(out) => {
+                for line in out.lines() {
+                    info!("  {line}");
+                }
+            }
+            Err(e) => errors.push(e.with_location(&path)),
+        }
+    }
+
+    write_trusted(cfg, &trusted).await?;
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+/// Name of the environment variable for the variable `key`, e.g. `DOTFILES_WINDOWS_USER`.
+fn env_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+
+    format!("{ENV_PREFIX}{key}")
+}
+
+async fn read_hook_commands(path: &Path) -> Result<HookCommands, Error> {
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(HookCommands::default());
+    };
+
+    toml::de::from_str(&s).with_location(path)
+}
@@ -0,0 +1,134 @@
+use crate::error::{Error, ErrorLocation};
+use crate::glob::Glob;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs::{read_dir, read_to_string};
+
+/// File which can be placed in any directory of the template tree to leave paths below it out of
+/// the tree, with the syntax of a `.gitignore`.
+///
+/// ```text
+/// README.md
+/// *.swp
+/// /scratch/
+/// !keep.swp
+/// ```
+pub const IGNORE_FILE: &str = ".dotignore";
+
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreRules {
+    /// Rules of shallower ignore files come first, so that deeper ones take precedence.
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    /// Directory of the ignore file, relative to the template tree.
+    base: PathBuf,
+    glob: Glob,
+    negate: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRules {
+    /// Whether `relative`, or a directory it's in, is ignored. `template_dir` is used to check
+    /// whether paths matched by patterns which only apply to directories are directories.
+    pub fn is_ignored(&self, template_dir: &Path, relative: &Path) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        // like git, files in an ignored directory can't be unignored
+        let mut path = PathBuf::new();
+        relative.components().any(|component| {
+            path.push(component);
+            self.matches(template_dir, &path)
+        })
+    }
+
+    fn matches(&self, template_dir: &Path, relative: &Path) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            let Ok(rest) = relative.strip_prefix(&rule.base) else {
+                continue;
+            };
+
+            if rest.as_os_str().is_empty() || !rule.glob.matches(rest) {
+                continue;
+            }
+
+            if rule.dir_only && !template_dir.join(relative).is_dir() {
+                continue;
+            }
+
+            ignored = !rule.negate;
+        }
+        ignored
+    }
+
+    fn parse(&mut self, base: &Path, s: &str) {
+        for line in s.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+
+            let dir_only = line.ends_with('/');
+            let pattern = line.trim_end_matches('/');
+
+            // patterns without a slash except at the end match at any depth
+            let glob = if pattern.contains('/') {
+                Glob::new(pattern)
+            } else {
+                Glob::new(&format!("**/{pattern}"))
+            };
+
+            self.rules.push(Rule {
+                base: base.to_path_buf(),
+                glob,
+                negate,
+                dir_only,
+            });
+        }
+    }
+}
+
+/// Read the ignore files in the template tree, skipping directories which are already ignored.
+pub async fn read_ignores(template_dir: &Path) -> Result<IgnoreRules, Error> {
+    let mut rules = IgnoreRules::default();
+    let mut dirs = vec![PathBuf::new()];
+
+    // breadth first, so that shallower rules come first
+    while !dirs.is_empty() {
+        let mut next = vec![];
+        for relative in dirs {
+            let path = template_dir.join(&relative).join(IGNORE_FILE);
+            if let Ok(s) = read_to_string(&path).await {
+                debug!("parsing {:?}", path);
+                rules.parse(&relative, &s);
+            }
+
+            let dir = template_dir.join(&relative);
+            let mut entries = match read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.with_location(&dir)),
+            };
+            while let Some(entry) = entries.next_entry().await.with_location(&dir)? {
+                let entry_relative = relative.join(entry.file_name());
+                let file_type = entry.file_type().await.with_location(&entry.path())?;
+                if file_type.is_dir() && !rules.is_ignored(template_dir, &entry_relative) {
+                    next.push(entry_relative);
+                }
+            }
+        }
+        dirs = next;
+    }
+
+    Ok(rules)
+}
@@ -0,0 +1,74 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::Config;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use tokio::fs::read_to_string;
+
+/// A machine described in the inventory file.
+///
+/// ```toml
+/// [laptop]
+/// hostname = "laptop"
+/// os = "linux"
+/// ssh = "me@laptop.lan"
+/// flags = ["battery", "monitor=eDP-1"]
+///
+/// [laptop.variables]
+/// font_size = "12"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct Host {
+    /// The name of the table in the inventory.
+    #[serde(skip)]
+    pub name: String,
+
+    /// Defaults to the name of the host.
+    hostname: Option<String>,
+
+    /// Defaults to the os of the current machine.
+    pub os: Option<String>,
+
+    /// Where `fleet` connects to the host, defaults to the hostname.
+    ssh: Option<String>,
+
+    /// Flags, like those given on the command line.
+    #[serde(default)]
+    pub flags: Vec<String>,
+
+    /// Variables overlaid on top of the variables file.
+    #[serde(default)]
+    pub variables: HashMap<String, toml::Value>,
+}
+
+impl Host {
+    pub fn hostname(&self) -> String {
+        self.hostname.clone().unwrap_or_else(|| self.name.clone())
+    }
+
+    /// The ssh destination of the host.
+    pub fn ssh_destination(&self) -> String {
+        self.ssh.clone().unwrap_or_else(|| self.hostname())
+    }
+}
+
+pub async fn read_inventory(cfg: &Config) -> Result<BTreeMap<String, Host>, Error> {
+    let path = &cfg.inventory_path;
+
+    debug!("reading {:?}", path);
+    let s = read_to_string(path).await.with_location(path)?;
+    let mut hosts: BTreeMap<String, Host> = toml::de::from_str(&s).with_location(path)?;
+
+    for (name, host) in &mut hosts {
+        host.name = name.clone();
+    }
+
+    Ok(hosts)
+}
+
+pub async fn read_host(cfg: &Config, name: &str) -> Result<Host, Error> {
+    read_inventory(cfg)
+        .await?
+        .remove(name)
+        .ok_or_else(|| InnerError::UnknownHost(name.to_string()))
+        .with_location(&cfg.inventory_path)
+}
@@ -0,0 +1,130 @@
+use crate::block::replace_block;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::process::run;
+use crate::Config;
+use std::ffi::OsStr;
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::{read_dir, read_to_string, set_permissions, write, DirBuilder};
+use tokio::join;
+use tokio::process::Command;
+
+/// Directory at the root of the template tree containing public keys to trust.
+///
+/// SSH keys in `.keys/ssh/*.pub` are installed into `~/.ssh/authorized_keys`, and gpg keys in
+/// `.keys/gpg/*.asc` are imported into the keyring.
+pub const KEYS_DIR: &str = ".keys";
+
+const AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+
+/// Name of the managed block in `authorized_keys`.
+const BLOCK_NAME: &str = "keys";
+
+/// Install the public keys declared in the tree.
+pub async fn install_keys(cfg: &Config) -> Result<(), Errors> {
+    let (ssh, gpg) = join!(install_ssh_keys(cfg), import_gpg_keys(cfg));
+
+    let mut errors = Errors::default();
+    if let Err(error) = ssh {
+        errors.join(error.into());
+    }
+    if let Err(error) = gpg {
+        errors.join(error);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Write the ssh keys into a managed block of `authorized_keys`, leaving all other lines alone.
+async fn install_ssh_keys(cfg: &Config) -> Result<(), Error> {
+    let paths = key_files(&cfg.template_dir.join(KEYS_DIR).join("ssh"), "pub").await?;
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut block = String::new();
+    for path in &paths {
+        let keys = read_to_string(path).await.with_location(path)?;
+        for key in keys.lines().map(str::trim) {
+            if !key.is_empty() && !key.starts_with('#') {
+                block.push_str(key);
+                block.push('\n');
+            }
+        }
+    }
+
+    let path = cfg.link_dir.join(AUTHORIZED_KEYS);
+    let current = match read_to_string(&path).await {
+        Ok(current) => current,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.with_location(&path)),
+    };
+
+    let updated = replace_block(&current, BLOCK_NAME, &block);
+    if updated == current {
+        debug!("{:?} is up to date", path);
+        return Ok(());
+    }
+
+    let dir = cfg.link_dir.join(".ssh");
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .await
+        .with_location(&dir)?;
+
+    info!("installing {} key files into {:?}", paths.len(), path);
+    write(&path, updated).await.with_location(&path)?;
+
+    set_permissions(&path, Permissions::from_mode(0o600))
+        .await
+        .with_location(&path)
+}
+
+async fn import_gpg_keys(cfg: &Config) -> Result<(), Errors> {
+    let paths = key_files(&cfg.template_dir.join(KEYS_DIR).join("gpg"), "asc").await?;
+
+    let mut errors = vec![];
+    for path in &paths {
+        let mut cmd = Command::new("gpg");
+        cmd.args(["--batch", "--import"]).arg(path);
+
+        debug!("importing {:?}", path);
+        if let Err(e) = run(cmd).await {
+            errors.push(e.with_location(path));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+/// List the files with the given extension in a directory, if it exists.
+async fn key_files(dir: &Path, extension: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut walker = match read_dir(dir).await {
+        Ok(walker) => walker,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.with_location(dir)),
+    };
+
+    let mut paths = vec![];
+    while let Some(entry) = walker.next_entry().await.with_location(dir)? {
+        let path = entry.path();
+        if path.extension() == Some(OsStr::new(extension)) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort_unstable();
+    Ok(paths)
+}
@@ -1,27 +1,146 @@
-use crate::error::{Error, ErrorLocation, Errors};
+use crate::backup::{backup_path, record_backup};
+use crate::error::{Error, ErrorLocation, ErrorOperation, Errors, InnerError, Operation};
+use crate::managers::Manager;
+use crate::progress::Progress;
+use crate::state::read_link_targets;
+use crate::target::Target;
+use crate::warnings::warning;
+use crate::windows::check_windows_path;
+use crate::wsl::WINDOWS_HOME_DIR;
 use crate::Config;
 use async_recursion::async_recursion;
+use clap::ValueEnum;
 use futures::future::join_all;
-use std::io::ErrorKind;
-use std::path::PathBuf;
-use tokio::fs::{create_dir, read_dir, remove_file, symlink};
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs::read_dir;
 use tokio::join;
 
-pub async fn link_tree(cfg: &Config) -> Result<(), Errors> {
-    dir(cfg, PathBuf::new()).await
+/// How files in the build tree are deployed into the link tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Symlink each file back into the build tree.
+    #[default]
+    Symlink,
+
+    /// Copy each file, for targets where the build tree isn't reachable.
+    Copy,
+}
+
+/// What to do when a directory in the link tree is a symlink to somewhere else.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkedDirs {
+    /// Link into the directory that the symlink points to, with a warning if that is outside of
+    /// the link dir.
+    #[default]
+    Follow,
+
+    /// Replace the symlink with a directory.
+    Replace,
+
+    /// Treat the symlink as a conflict and don't link anything below it.
+    Error,
+}
+
+/// What to do with paths in the link tree which are managed by another dotfiles manager, such as
+/// home-manager, chezmoi or stow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExternalFiles {
+    /// Leave them alone, with a warning.
+    #[default]
+    Skip,
+
+    /// Leave them alone, and report an error.
+    Error,
+}
+
+pub async fn link_tree(cfg: &Config, target: &dyn Target) -> Result<(), Errors> {
+    // the build may have changed where files are linked
+    let cfg = &Config {
+        link_targets: read_link_targets(cfg).await?,
+        ..cfg.clone()
+    };
+
+    let progress = Progress::new("linked");
+    dir(cfg, target, &progress, PathBuf::new()).await?;
+    progress.finish();
+
+    Ok(())
 }
 
 #[async_recursion]
-async fn dir(cfg: &Config, relative: PathBuf) -> Result<(), Errors> {
+async fn dir(
+    cfg: &Config,
+    target: &dyn Target,
+    progress: &Progress,
+    relative: PathBuf,
+) -> Result<(), Errors> {
     let build_path = cfg.build_dir.join(&relative);
-    let link_path = cfg.link_dir.join(&relative);
+    let link_path = cfg.link_path(&relative);
+
+    trace!("traversing {:?} ({link_path:?})", build_path);
+
+    if cfg.on_windows(&relative) {
+        check_windows_path(&link_path).with_location(&link_path)?;
+    }
 
-    info!("traversing {:?} ({link_path:?})", build_path);
+    if is_external(cfg, target, &link_path).await? {
+        return Ok(());
+    }
 
-    match create_dir(&link_path).await {
+    match target.create_dir(&link_path, cfg.dir_mode).await {
         Ok(_) => {}
         Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
-        Err(e) => return Err(e.with_location(&link_path).into()),
+        Err(e) => return Err(e.with_location(&link_path).during(Operation::Mkdir).into()),
+    }
+
+    // the root of the link tree is allowed to be a symlink
+    if link_path != cfg.link_dir
+        && target
+            .is_symlink(&link_path)
+            .await
+            .with_location(&link_path)?
+    {
+        match cfg.symlinked_dirs {
+            SymlinkedDirs::Follow => {
+                let original = target
+                    .read_link(&link_path)
+                    .await
+                    .with_location(&link_path)?
+                    .unwrap_or_default();
+
+                let parent = link_path.parent().unwrap_or(&link_path);
+                let resolved = normalize(&parent.join(original));
+
+                let root = match relative.starts_with(WINDOWS_HOME_DIR) {
+                    true => cfg.link_path(Path::new(WINDOWS_HOME_DIR)),
+                    false => cfg.link_dir.clone(),
+                };
+
+                if resolved.starts_with(&root) {
+                    trace!("following symlinked directory {:?}", link_path);
+                } else {
+                    let message = format!("following it to {resolved:?}, outside of {root:?}");
+                    warning(&link_path, message);
+                }
+            }
+            SymlinkedDirs::Replace => {
+                warning(&link_path, "replacing symlinked directory with a directory");
+                target
+                    .remove_file(&link_path)
+                    .await
+                    .with_location(&link_path)
+                    .during(Operation::Remove)?;
+                target
+                    .create_dir(&link_path, cfg.dir_mode)
+                    .await
+                    .with_location(&link_path)
+                    .during(Operation::Mkdir)?;
+            }
+            SymlinkedDirs::Error => {
+                return Err(InnerError::SymlinkedDir.with_location(&link_path).into())
+            }
+        }
     }
 
     let mut walker = read_dir(&build_path).await.with_location(&build_path)?;
@@ -33,10 +152,15 @@ async fn dir(cfg: &Config, relative: PathBuf) -> Result<(), Errors> {
         let meta = entry.metadata().await.with_location(&entry.path())?;
         let new_relative = relative.join(entry.file_name());
 
+        if cfg.skip(&new_relative) {
+            trace!("skipping {:?}", entry.path());
+            continue;
+        }
+
         if meta.is_dir() {
-            dir_tasks.push(dir(cfg, new_relative));
+            dir_tasks.push(dir(cfg, target, progress, new_relative));
         } else if meta.is_file() {
-            file_tasks.push(file(cfg, new_relative));
+            file_tasks.push(file(cfg, target, progress, new_relative));
         }
     }
 
@@ -61,21 +185,145 @@ async fn dir(cfg: &Config, relative: PathBuf) -> Result<(), Errors> {
     }
 }
 
-async fn file(cfg: &Config, relative: PathBuf) -> Result<(), Error> {
+async fn file(
+    cfg: &Config,
+    target: &dyn Target,
+    progress: &Progress,
+    relative: PathBuf,
+) -> Result<(), Error> {
+    progress.tick();
     let build_path = cfg.build_dir.join(&relative);
-    let link_path = cfg.link_dir.join(&relative);
+    let link_path = cfg.link_path(&relative);
+
+    if cfg.on_windows(&relative) {
+        check_windows_path(&link_path).with_location(&link_path)?;
+    }
+
+    if is_external(cfg, target, &link_path).await? {
+        return Ok(());
+    }
+
+    // files linked somewhere else than usual may not have a directory to be linked into
+    if cfg.link_targets.contains_key(&*relative.to_string_lossy()) {
+        if let Some(parent) = link_path.parent() {
+            match target.create_dir(parent, cfg.dir_mode).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.with_location(parent).during(Operation::Mkdir)),
+            }
+        }
+    }
+
+    // leave the currently linked file alone if the new one is broken
+    cfg.validators.validate(&relative, &build_path).await?;
+
+    let acl = if cfg.preserve_acl {
+        target.get_acl(&link_path).await.with_location(&link_path)?
+    } else {
+        None
+    };
+
+    if let Some(backup_dir) = &cfg.backup_dir {
+        let backup_path = backup_path(cfg, backup_dir, &link_path);
+        if target
+            .back_up(&link_path, &backup_path)
+            .await
+            .with_location(&link_path)
+            .during(Operation::Backup)?
+        {
+            info!("moved {:?} to {:?}", link_path, backup_path);
+
+            // a dry run only says it would have
+            if backup_path.exists() {
+                record_backup(backup_dir, &backup_path, &link_path).await?;
+            }
+        }
+    }
 
-    match remove_file(&link_path).await {
-        Ok(_) => {
-            debug!("removed existing file {:?}", link_path);
+    if cfg.link_mode == LinkMode::Copy {
+        trace!("copying {:?} to {:?}", build_path, link_path);
+        target
+            .replace_with_copy(&build_path, &link_path)
+            .await
+            .with_location(&link_path)
+            .during(Operation::Copy)?;
+    } else {
+        match link(target, &build_path, &link_path).await {
+            // android's shared storage doesn't support symlinks
+            Err(e) if cfg.termux && e.kind() == ErrorKind::PermissionDenied => {
+                warning(&link_path, "can't symlink, copying instead");
+                target
+                    .copy_file(&build_path, &link_path)
+                    .await
+                    .with_location(&link_path)
+                    .during(Operation::Copy)?;
+            }
+            result => result.with_location(&link_path).during(Operation::Link)?,
         }
-        Err(e) if e.kind() == ErrorKind::NotFound => {}
-        Err(e) => return Err(e.with_location(&link_path)),
+    }
+
+    if let Some(acl) = acl {
+        trace!("restoring acl of {:?}", link_path);
+        target
+            .set_acl(&link_path, &acl)
+            .await
+            .with_location(&link_path)?;
+    }
+
+    if cfg.selinux {
+        target
+            .restore_context(&link_path)
+            .await
+            .with_location(&link_path)?;
+    }
+
+    Ok(())
+}
+
+/// Check whether `link_path` is managed by another dotfiles manager, and should be left alone.
+async fn is_external(cfg: &Config, target: &dyn Target, link_path: &Path) -> Result<bool, Error> {
+    let Some(original) = target.read_link(link_path).await.with_location(link_path)? else {
+        return Ok(false);
+    };
+
+    let Some(manager) = Manager::detect(&original) else {
+        return Ok(false);
     };
 
-    debug!("linking {:?} to {:?}", link_path, build_path);
+    match cfg.external_files {
+        ExternalFiles::Skip => {
+            let message = format!(
+                "skipping it, it is managed by {manager} ({original:?}), to manage it here {}",
+                manager.resolution()
+            );
+            warning(link_path, message);
+            Ok(true)
+        }
+        ExternalFiles::Error => {
+            Err(InnerError::ExternallyManaged { manager, original }.with_location(link_path))
+        }
+    }
+}
+
+/// Resolve `.` and `..` in `path` without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+async fn link(target: &dyn Target, build_path: &Path, link_path: &Path) -> io::Result<()> {
+    trace!("linking {:?} to {:?}", link_path, build_path);
     let symlink_content = if build_path.is_absolute() {
-        build_path
+        build_path.to_path_buf()
     } else {
         // TODO: this probably doesn't work for paths containing ".."
         // TODO: this doesn't work if link path is absolute
@@ -83,14 +331,12 @@ async fn file(cfg: &Config, relative: PathBuf) -> Result<(), Error> {
         for _ in link_path.iter().skip(1).filter(|&c| c == ".") {
             relative_symlink.push("..");
         }
-        relative_symlink.push(&build_path);
+        relative_symlink.push(build_path);
 
         relative_symlink
     };
 
-    symlink(symlink_content, &link_path)
+    target
+        .replace_with_symlink(&symlink_content, link_path)
         .await
-        .with_location(&link_path)?;
-
-    Ok(())
 }
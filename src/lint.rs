@@ -0,0 +1,258 @@
+use crate::builder::{read_variables, FACTS, TEMPLATE_EXTENSION};
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::frontmatter::split_front_matter;
+use crate::peeker::read_docs;
+use crate::tplcache::{save_template_cache, template_variables};
+use crate::Config;
+use futures::future::join_all;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use tokio::fs::read_to_string;
+
+/// Name of the file in the config dir which configures the lint rules.
+const RULES_FILE: &str = "lint.toml";
+
+/// Which lints to run.
+///
+/// ```toml
+/// trailing_whitespace = true
+/// mixed_indentation = true
+/// variable_casing = true
+/// unknown_variables = true
+/// max_line_length = 120 # 0 to disable
+/// ```
+#[derive(Deserialize)]
+#[serde(default)]
+struct Rules {
+    trailing_whitespace: bool,
+    mixed_indentation: bool,
+    variable_casing: bool,
+    unknown_variables: bool,
+    max_line_length: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            trailing_whitespace: true,
+            mixed_indentation: true,
+            variable_casing: true,
+            unknown_variables: true,
+            max_line_length: 120,
+        }
+    }
+}
+
+struct Finding {
+    path: PathBuf,
+    line: usize,
+    rule: &'static str,
+    message: String,
+}
+
+pub struct Template {
+    pub path: PathBuf,
+    pub content: String,
+    pub variables: Vec<String>,
+}
+
+/// Check all templates against the lint rules and print the findings.
+///
+/// Returns the number of findings.
+pub async fn lint_tree(cfg: &Config) -> Result<usize, Errors> {
+    let rules = read_rules(cfg).await?;
+
+    let mut files = list_files(&cfg.template_dir).await?;
+    files.retain(|relative| {
+        !cfg.skip(relative) && relative.extension() == Some(OsStr::new(TEMPLATE_EXTENSION))
+    });
+
+    let mut templates = vec![];
+    let mut errors = vec![];
+    for result in join_all(files.iter().map(|relative| read_template(cfg, relative))).await {
+        match result {
+            Ok(template) => templates.push(template),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+
+    let mut findings = vec![];
+    for template in &templates {
+        lint_lines(&rules, template, &mut findings);
+    }
+
+    if rules.variable_casing {
+        lint_casing(&templates, &mut findings);
+    }
+
+    if rules.unknown_variables {
+        lint_unknown(cfg, &templates, &mut findings).await?;
+    }
+
+    findings.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    for finding in &findings {
+        println!(
+            "{}:{}: [{}] {}",
+            finding.path.display(),
+            finding.line,
+            finding.rule,
+            finding.message
+        );
+    }
+
+    save_template_cache(cfg).await?;
+
+    Ok(findings.len())
+}
+
+async fn read_rules(cfg: &Config) -> Result<Rules, Error> {
+    let path = cfg.config_dir.join(RULES_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(Rules::default());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+pub async fn read_template(cfg: &Config, relative: &Path) -> Result<Template, Error> {
+    let path = cfg.template_dir.join(relative);
+
+    debug!("reading {:?}", path);
+    let content = read_to_string(&path).await.with_location(&path)?;
+
+    let (_, body) = split_front_matter(&content).with_location(&path)?;
+
+    let variables = template_variables(body).with_location(&path)?;
+
+    Ok(Template {
+        path,
+        content,
+        variables,
+    })
+}
+
+fn lint_lines(rules: &Rules, template: &Template, findings: &mut Vec<Finding>) {
+    let mut indentation = None;
+
+    for (i, line) in template.content.lines().enumerate() {
+        let mut finding = |rule, message: String| {
+            findings.push(Finding {
+                path: template.path.clone(),
+                line: i + 1,
+                rule,
+                message,
+            })
+        };
+
+        if rules.trailing_whitespace && line.trim_end() != line {
+            finding("trailing_whitespace", "trailing whitespace".into());
+        }
+
+        let length = line.chars().count();
+        if rules.max_line_length > 0 && length > rules.max_line_length {
+            finding(
+                "max_line_length",
+                format!("line is {length} characters long"),
+            );
+        }
+
+        if rules.mixed_indentation {
+            match (indentation, line.chars().next()) {
+                (None, Some(c @ (' ' | '\t'))) => indentation = Some(c),
+                (Some(expected), Some(c @ (' ' | '\t'))) if c != expected => {
+                    finding(
+                        "mixed_indentation",
+                        format!("indented with {c:?}, but the file is indented with {expected:?}"),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Report variables which are spelled with different casing in different places.
+fn lint_casing(templates: &[Template], findings: &mut Vec<Finding>) {
+    let mut spellings: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+    for template in templates {
+        for var in &template.variables {
+            let normalized = var.to_lowercase().replace('-', "_");
+            spellings
+                .entry(normalized)
+                .or_default()
+                .insert(var.as_str());
+        }
+    }
+
+    for variants in spellings.values().filter(|variants| variants.len() > 1) {
+        let all: Vec<&str> = variants.iter().copied().collect();
+        for template in templates {
+            for var in template
+                .variables
+                .iter()
+                .filter(|var| variants.contains(var.as_str()))
+            {
+                findings.push(Finding {
+                    path: template.path.clone(),
+                    line: line_of(&template.content, var),
+                    rule: "variable_casing",
+                    message: format!("{var:?} is also spelled as {}", all.join(", ")),
+                });
+            }
+        }
+    }
+}
+
+/// Report variables which aren't defined by the variables file, the facts or the variable
+/// documentation, and which are therefore only ever set by flags.
+///
+/// Only checked if there is any variable documentation to check against.
+async fn lint_unknown(
+    cfg: &Config,
+    templates: &[Template],
+    findings: &mut Vec<Finding>,
+) -> Result<(), Errors> {
+    let docs = read_docs(cfg).await?;
+    if docs.is_empty() {
+        return Ok(());
+    }
+
+    let values = read_variables(cfg).await?;
+
+    for template in templates {
+        for var in &template.variables {
+            let known =
+                FACTS.contains(&var.as_str()) || docs.contains_key(var) || values.contains_key(var);
+
+            if !known {
+                findings.push(Finding {
+                    path: template.path.clone(),
+                    line: line_of(&template.content, var),
+                    rule: "unknown_variables",
+                    message: format!("{var:?} is not documented, conditionals on it are only true if set as a flag"),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the first line which mentions `needle`.
+fn line_of(content: &str, needle: &str) -> usize {
+    content
+        .lines()
+        .position(|line| line.contains(needle))
+        .map(|i| i + 1)
+        .unwrap_or(1)
+}
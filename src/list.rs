@@ -0,0 +1,37 @@
+use crate::bundle::read_bundles;
+use crate::diff::list_files;
+use crate::error::Errors;
+use crate::state::read_sources;
+use crate::Config;
+use std::path::Path;
+
+/// Print each file in the build tree, with what it was made from and the bundles that include it.
+pub async fn list_tree(cfg: &Config) -> Result<(), Errors> {
+    let files = list_files(&cfg.build_dir).await?;
+    let sources = read_sources(cfg).await?;
+    let bundles = read_bundles(cfg).await?;
+
+    for relative in files {
+        let key = relative.to_string_lossy();
+        let source = sources.get(key.as_ref()).map(String::as_str);
+
+        let mut line = format!("{key} <- {}", source.unwrap_or("unknown"));
+
+        let included_by: Vec<&str> = bundles
+            .iter()
+            .filter(|(_, paths)| {
+                let source = Path::new(source.unwrap_or(&key));
+                paths.iter().any(|path| source.starts_with(path))
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        if !included_by.is_empty() {
+            line.push_str(&format!(" (bundles: {})", included_by.join(", ")));
+        }
+
+        println!("{line}");
+    }
+
+    Ok(())
+}
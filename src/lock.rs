@@ -0,0 +1,447 @@
+use crate::builder::{build_env, to_value};
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::format::FORMATTERS_FILE;
+use crate::git;
+use crate::permissions::PERMISSIONS_FILE;
+use crate::private::PRIVATE_FILE_MODE;
+use crate::redact::is_sensitive;
+use crate::state::{read_state, write_state};
+use crate::tplcache::template_variables;
+use crate::validate::VALIDATORS_FILE;
+use crate::Config;
+use blueprint::{Env, Value};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display};
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs::{copy, create_dir_all, read, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Name of the file in the state dir recording the env of the last successful sync.
+const LOCK_FILE: &str = "lock.toml";
+
+/// Name of the file in the state dir with the key that sensitive variables are hashed with.
+const LOCK_KEY_FILE: &str = "lock.key";
+
+/// What the last successful sync was made from.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Lock {
+    /// The commit of the template tree, if it is a git repository.
+    #[serde(default)]
+    commit: Option<String>,
+
+    /// Hash of each file in the template tree, by path.
+    #[serde(default)]
+    files: BTreeMap<String, String>,
+
+    /// The facts, variables and flags, except the sensitive ones.
+    env: BTreeMap<String, toml::Value>,
+
+    /// Keyed hash of each sensitive variable, by name, so that their values never reach the disk.
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+
+    /// Hash of the settings which change what files are built into, see [settings_hash].
+    #[serde(default)]
+    settings: String,
+
+    /// Hash of each file in the build tree, by path, to tell whether it was replaced since.
+    #[serde(default)]
+    outputs: BTreeMap<String, String>,
+}
+
+impl Lock {
+    /// Record the current state of the template tree and the env.
+    pub async fn current(cfg: &Config) -> Result<Self, Errors> {
+        let env = build_env(cfg).await?;
+        let key = read_lock_key(cfg).await?;
+        let files = hash_files(cfg).await?;
+        let commit = git::head(&cfg.template_dir).await.ok();
+
+        Ok(Lock {
+            commit,
+            files,
+            settings: settings_hash(cfg).await?,
+            outputs: hash_tree(&cfg.build_dir).await?,
+            ..Lock::from_env(&env, &key)
+        })
+    }
+
+    fn from_env(env: &Env, key: &LockKey) -> Self {
+        let mut lock = Lock::default();
+        for (name, value) in env.iter() {
+            let value = match value {
+                Value::Str(s) if is_sensitive(s) => {
+                    lock.secrets.insert(name.clone(), key.hash(s));
+                    continue;
+                }
+                Value::Str(s) => toml::Value::String(s.clone()),
+                Value::Bool(b) => toml::Value::Boolean(*b),
+                other => toml::Value::String(format!("{other:?}")),
+            };
+            lock.env.insert(name.clone(), value);
+        }
+        lock
+    }
+
+    /// The commit of the template tree at the last sync, if it was a git repository.
+    pub fn commit(&self) -> Option<&str> {
+        self.commit.as_deref()
+    }
+
+    /// Hash of the file at `relative` in the template tree at the last sync.
+    pub fn file_hash(&self, relative: &str) -> Option<&str> {
+        self.files.get(relative).map(String::as_str)
+    }
+
+    /// The files in the template tree which differ from the sync recorded in `previous`, by path.
+    pub fn changes_since(&self, previous: &Lock) -> BTreeMap<String, FileChange> {
+        file_changes(&previous.files, &self.files)
+    }
+
+    /// The env of the last sync, without the sensitive variables, see [Lock::resolve_secrets].
+    pub fn to_env(&self) -> Result<Env, InnerError> {
+        let mut env = Env::new();
+        for (key, value) in &self.env {
+            env.insert(key.clone(), to_value(value.clone())?);
+        }
+        Ok(env)
+    }
+
+    /// Whether any variable of the last sync was sensitive, and only its hash was recorded.
+    pub fn has_secrets(&self) -> bool {
+        !self.secrets.is_empty()
+    }
+
+    /// Set the sensitive variables in `env` to their values in the `current` env, as long as they
+    /// are the same as at the last sync.
+    pub fn resolve_secrets(
+        &self,
+        key: &LockKey,
+        current: &Env,
+        env: &mut Env,
+    ) -> Result<(), InnerError> {
+        for (name, hash) in &self.secrets {
+            match current.get(name) {
+                Some(Value::Str(s)) if key.hash(s) == *hash => {
+                    env.insert(name.clone(), Value::Str(s.clone()));
+                }
+                _ => return Err(InnerError::SecretChanged(name.clone())),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a file in the template tree changed between two syncs.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChange {
+    Added,
+    Modified,
+    Removed,
+}
+
+impl Display for FileChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileChange::Added => write!(f, "added"),
+            FileChange::Modified => write!(f, "modified"),
+            FileChange::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+/// The files which were added, modified or removed going from the hashes `old` to `new`.
+fn file_changes(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> BTreeMap<String, FileChange> {
+    let mut changes = BTreeMap::new();
+    for (path, old_hash) in old {
+        match new.get(path) {
+            Some(new_hash) if new_hash == old_hash => {}
+            Some(_) => {
+                changes.insert(path.clone(), FileChange::Modified);
+            }
+            None => {
+                changes.insert(path.clone(), FileChange::Removed);
+            }
+        }
+    }
+
+    for path in new.keys() {
+        if !old.contains_key(path) {
+            changes.insert(path.clone(), FileChange::Added);
+        }
+    }
+
+    changes
+}
+
+/// What changed since the last successful sync.
+pub struct Changes {
+    /// Hash of each file in the template tree at the last sync, by path.
+    files: BTreeMap<String, String>,
+
+    /// Variables which were added, removed or changed since the last sync.
+    variables: BTreeSet<String>,
+
+    /// Whether the settings which change what files are built into changed since the last sync.
+    settings: bool,
+
+    /// Hash of each file in the build tree at the last sync, by path.
+    outputs: BTreeMap<String, String>,
+}
+
+impl Changes {
+    /// Compare the last successful sync with the current `env` and `settings`, as hashed by
+    /// [settings_hash].
+    pub fn since(lock: Lock, env: &Env, key: &LockKey, settings: &str) -> Self {
+        let current = Lock::from_env(env, key);
+
+        let mut variables = changed_keys(&lock.env, &current.env);
+        variables.append(&mut changed_keys(&lock.secrets, &current.secrets));
+
+        Changes {
+            files: lock.files,
+            variables,
+            settings: lock.settings != settings,
+            outputs: lock.outputs,
+        }
+    }
+
+    /// Whether the template at `relative` would render the same as at the last sync, because
+    /// neither it, any variable it uses nor the settings have changed.
+    pub fn is_unchanged(&self, relative: &Path, content: &[u8], body: &str) -> bool {
+        if self.settings {
+            return false;
+        }
+
+        let key = relative.to_string_lossy();
+        if self.files.get(key.as_ref()) != Some(&content_hash(content)) {
+            return false;
+        }
+
+        match template_variables(body) {
+            Ok(variables) => !variables.iter().any(|v| self.variables.contains(v)),
+            Err(_) => false,
+        }
+    }
+
+    /// Whether the file at `build_path`, which is `output` in the build tree, is still what the
+    /// last sync built.
+    pub async fn is_built(&self, output: &Path, build_path: &Path) -> bool {
+        let Some(hash) = self.outputs.get(&*output.to_string_lossy()) else {
+            return false;
+        };
+
+        read(build_path)
+            .await
+            .is_ok_and(|content| content_hash(&content) == *hash)
+    }
+}
+
+pub async fn read_lock(cfg: &Config) -> Result<Lock, Error> {
+    let lock: Option<Lock> = read_state(cfg, LOCK_FILE).await?;
+    lock.ok_or(InnerError::NoLock)
+        .with_location(&cfg.state_dir.join(LOCK_FILE))
+}
+
+pub async fn write_lock(cfg: &Config, lock: &Lock) -> Result<(), Error> {
+    write_state(cfg, LOCK_FILE, lock).await
+}
+
+/// Copy the lock file and the key its secrets are hashed with from the state dir of `from` into
+/// that of `to`, for frozen builds with a state of their own.
+pub async fn copy_lock(from: &Config, to: &Config) -> Result<(), Error> {
+    create_dir_all(&to.state_dir)
+        .await
+        .with_location(&to.state_dir)?;
+
+    for name in [LOCK_FILE, LOCK_KEY_FILE] {
+        let path = from.state_dir.join(name);
+        match copy(&path, to.state_dir.join(name)).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.with_location(&path)),
+        }
+    }
+
+    Ok(())
+}
+
+/// The keys which are only in one of `old` and `new`, or have different values.
+fn changed_keys<T: PartialEq>(
+    old: &BTreeMap<String, T>,
+    new: &BTreeMap<String, T>,
+) -> BTreeSet<String> {
+    old.iter()
+        .filter(|(key, value)| new.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .chain(new.keys().filter(|key| !old.contains_key(*key)).cloned())
+        .collect()
+}
+
+/// The key which sensitive variables are hashed with in the lock file, so that their values
+/// can't be guessed from the hashes by anyone without access to the state dir.
+pub struct LockKey(Vec<u8>);
+
+impl LockKey {
+    fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("any key size works");
+        mac.update(value.as_bytes());
+        hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// Read the key sensitive variables are hashed with, creating it on first use.
+pub async fn read_lock_key(cfg: &Config) -> Result<LockKey, Error> {
+    let path = cfg.state_dir.join(LOCK_KEY_FILE);
+    match read(&path).await {
+        Ok(key) => return Ok(LockKey(key)),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.with_location(&path)),
+    }
+
+    let mut key = vec![0; 32];
+    let random = Path::new("/dev/urandom");
+    let mut file = File::open(random).await.with_location(random)?;
+    file.read_exact(&mut key).await.with_location(random)?;
+
+    create_dir_all(&cfg.state_dir)
+        .await
+        .with_location(&cfg.state_dir)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(PRIVATE_FILE_MODE)
+        .open(&path)
+        .await
+        .with_location(&path)?;
+    file.write_all(&key).await.with_location(&path)?;
+
+    Ok(LockKey(key))
+}
+
+/// Print how the env differs from the one used by the last successful sync.
+///
+/// Returns whether there were any differences.
+pub async fn diff_env(cfg: &Config) -> Result<bool, Errors> {
+    let Ok(old) = read_lock(cfg).await else {
+        debug!("no lock file, can't tell whether the env changed");
+        return Ok(false);
+    };
+    let key = read_lock_key(cfg).await?;
+    let new = Lock::from_env(&build_env(cfg).await?, &key);
+
+    let mut changed = false;
+    for (key, old_value) in &old.env {
+        let new_value = new.env.get(key);
+        if new_value == Some(old_value) {
+            continue;
+        }
+
+        changed = true;
+        match new_value {
+            Some(new_value) => println!("env changed: {key}: {old_value} -> {new_value}"),
+            None => println!("env changed: {key}: {old_value} -> unset"),
+        }
+    }
+
+    for (key, new_value) in &new.env {
+        if !old.env.contains_key(key) {
+            println!("env changed: {key}: unset -> {new_value}");
+            changed = true;
+        }
+    }
+
+    // only the hashes of sensitive variables are known, and their values are never shown
+    for key in changed_keys(&old.secrets, &new.secrets) {
+        println!("env changed: {key}: sensitive value changed");
+        changed = true;
+    }
+
+    Ok(changed)
+}
+
+/// Print the files in the template tree which changed since the last successful sync, without
+/// rendering anything.
+///
+/// Returns the number of changed files.
+pub async fn audit(cfg: &Config) -> Result<usize, Errors> {
+    let old = read_lock(cfg).await?;
+    let new_files = hash_files(cfg).await?;
+    let new_commit = git::head(&cfg.template_dir).await.ok();
+
+    match (&old.commit, &new_commit) {
+        (Some(old_commit), Some(new_commit)) if old_commit != new_commit => {
+            println!("applied commit {old_commit}, the tree is at {new_commit}");
+        }
+        _ => {}
+    }
+
+    let changes = file_changes(&old.files, &new_files);
+    for (path, change) in &changes {
+        println!("{:<9} {path}", format!("{change}:"));
+    }
+
+    Ok(changes.len())
+}
+
+/// Hash the content of all files in the template tree.
+async fn hash_files(cfg: &Config) -> Result<BTreeMap<String, String>, Errors> {
+    let mut hashes = hash_tree(&cfg.template_dir).await?;
+    hashes.retain(|relative, _| !Path::new(relative).starts_with(".git"));
+    Ok(hashes)
+}
+
+/// Hash each file under `root`, by path relative to it.
+async fn hash_tree(root: &Path) -> Result<BTreeMap<String, String>, Errors> {
+    let mut hashes = BTreeMap::new();
+    for relative in list_files(root).await? {
+        let path = root.join(&relative);
+        let content = read(&path).await.with_location(&path)?;
+        hashes.insert(
+            relative.to_string_lossy().into_owned(),
+            content_hash(&content),
+        );
+    }
+
+    Ok(hashes)
+}
+
+/// Hash the settings which change what files are built into without being part of the tree: the
+/// formatters, validators and permission rules, and whether empty files are skipped.
+pub async fn settings_hash(cfg: &Config) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    for name in [FORMATTERS_FILE, VALIDATORS_FILE, PERMISSIONS_FILE] {
+        let path = cfg.config_dir.join(name);
+        let content = match read(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.with_location(&path)),
+        };
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+    hasher.update([cfg.skip_empty as u8]);
+
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Hash the content of a file, for comparing it with a previous version.
+///
+/// The hash is persisted, so it must be the same across builds of the tool.
+pub fn content_hash(content: &[u8]) -> String {
+    hex(&Sha256::digest(content))
+}
+
+/// Format `bytes` as lowercase hexadecimal.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
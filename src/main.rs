@@ -1,37 +1,274 @@
 #[macro_use]
 extern crate log;
 
+mod add;
+mod archive;
+mod backup;
+mod batched;
+mod block;
 mod builder;
+mod bundle;
+mod clean;
+mod condition;
+mod container;
+mod dconf;
+mod diff;
+mod dirsettings;
+mod dryrun;
+mod envdump;
 mod error;
+mod export;
+mod facts;
+mod flatpak;
+mod fleet;
+mod format;
+mod frontmatter;
+mod generations;
+mod git;
+mod glob;
+mod graph;
+mod grep;
+mod history;
+mod hooks;
+mod ignore;
+mod inventory;
+mod keys;
 mod linker;
+mod lint;
+mod list;
+mod lock;
+mod managers;
+mod merge;
+mod mv;
+mod normalize;
+mod paths;
 mod peeker;
+mod permissions;
+mod plan;
+mod private;
+mod process;
+mod profile;
+mod progress;
+mod push;
+mod redact;
+mod reload;
+mod remote;
+mod repo;
+mod retry;
+mod rm;
+mod sandbox;
+mod scan;
+mod scratch;
+mod secrets;
+mod services;
+mod settings;
+mod setup;
+mod ssh;
+mod state;
+mod stats;
+mod target;
+mod targets;
+mod termux;
+mod theme;
+mod tplcache;
+mod trust;
+mod unlink;
+mod usage;
+mod validate;
+mod verify;
+mod warnings;
+mod windows;
+mod wsl;
 
-use builder::build_tree;
-use clap::{ArgAction, Parser, Subcommand};
-use error::Errors;
-use linker::link_tree;
+use add::add_target;
+use archive::extract_archive;
+use backup::{list_backups, new_backup_dir, restore_backup, BackupAction};
+use batched::BatchedFs;
+use builder::{build_env, build_tree, output_path};
+use bundle::{read_bundle, BUNDLES_FILE, CORE_BUNDLE};
+use clap::{ArgAction, ArgGroup, Parser, Subcommand};
+use clean::{clean, gc, parse_age};
+use container::{Container, Runtime};
+use dconf::{diff_settings, load_settings, DCONF_FILE};
+use diff::{diff_links, diff_trees, list_files, Side};
+use dirsettings::DIR_SETTINGS_FILE;
+use dryrun::{print_build_changes, DryRun};
+use envdump::{print_env, EnvFormat};
+use error::{set_max_errors, ErrorLocation, Errors, InnerError};
+use export::export_tree;
+use facts::{detect_facts, read_facts, Detected, Facts};
+use flatpak::{apply_overrides, FLATPAK_FILE};
+use fleet::{sync_fleet, FleetAction};
+use format::{read_formatters, Formatters};
+use futures::future::join_all;
+use generations::{diff_generations, save_generation};
+use graph::{print_graph, GraphFormat};
+use grep::grep_tree;
+use history::{print_history, record_sync, show_sync, HistoryAction};
+use hooks::{run_hook, Hook, HOOKS_DIR};
+use ignore::{read_ignores, IgnoreRules, IGNORE_FILE};
+use inventory::{read_host, read_inventory, Host};
+use keys::{install_keys, KEYS_DIR};
+use linker::{link_tree, ExternalFiles, LinkMode, SymlinkedDirs};
+use lint::lint_tree;
+use list::list_tree;
+use lock::{audit, diff_env, read_lock, write_lock, Lock};
 use log::LevelFilter;
-use peeker::print_variables;
+use merge::pull;
+use mv::move_target;
+use normalize::Normalization;
+use paths::{read_paths, PathTable};
+use peeker::{print_types, print_variables, VARS_DOC_FILE};
+use permissions::{parse_mode, read_permissions, PermissionRules};
+use plan::{plan_tree, print_plan, Plan, PlanFormat};
+use private::link_relative;
+use profile::PROFILE_DIR;
+use push::push;
+use reload::run_reloads;
+use remote::fetch_source;
+use repo::{clone_repo, repo, RepoAction};
+use retry::{read_retry, write_retry};
+use rm::remove_target;
+use sandbox::{read_sandbox, Sandbox};
+use scan::{install_hook, scan_secrets};
+use scratch::{build_scratch, new_scratch, remove_scratch};
+use services::{install_services, SERVICES_FILE};
+use settings::read_settings;
+use setup::setup;
+use state::{read_link_targets, read_sources, unix_time, LinkTargets};
+use stats::print_stats;
 use std::env;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use target::{LocalFs, Target};
+use targets::{read_targets, sync_target};
+use termux::detect_termux;
+use theme::apply_theme;
+use tokio::join;
+use tplcache::load_template_cache;
+use unlink::unlink_tree;
+use validate::{read_validators, Validators};
+use verify::verify_tree;
+use warnings::take_warnings;
+use wsl::{Wsl, WINDOWS_HOME_DIR};
 
 #[derive(Parser)]
 struct Args {
     #[arg(short, long, env = "DOTFILES_PATH")]
     template_dir: Option<PathBuf>,
 
+    /// Tree managed by the administrators of a shared machine, such as
+    /// /usr/share/site-dotfiles, which is built before the template dir so that users can extend
+    /// or override it.
+    #[arg(long, env = "DOTFILES_SHARED_PATH")]
+    shared_dir: Option<PathBuf>,
+
+    /// Url of a tarball of the tree, or `s3://bucket/prefix` of a bucket with the tree, which is
+    /// fetched instead of using a git repository.
+    #[arg(long, env = "DOTFILES_SOURCE")]
+    source: Option<String>,
+
     #[arg(short, long)]
     build_dir: Option<PathBuf>,
 
     #[arg(short, long)]
     link_dir: Option<PathBuf>,
 
+    /// Variables file, in toml, or in json or yaml if it has that extension.
     #[arg(long = "variables")]
     variables_path: Option<PathBuf>,
 
+    #[arg(long = "inventory")]
+    inventory_path: Option<PathBuf>,
+
+    /// Render for a host from the inventory instead of the current machine.
+    #[arg(long, global = true)]
+    host: Option<String>,
+
+    /// Run new and changed hooks without asking first.
+    #[arg(long, global = true)]
+    trust_all: bool,
+
+    /// Never touch the network, and fail anything that can't do without it.
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Detect the facts about the current machine again, instead of using the cached ones.
+    #[arg(long, global = true)]
+    refresh_facts: bool,
+
+    /// Render as if the current machine had this hostname.
+    #[arg(long, global = true)]
+    hostname: Option<String>,
+
+    /// Render as if the current machine ran this os, e.g. linux or darwin.
+    #[arg(long, global = true)]
+    os: Option<String>,
+
+    /// Merge this `[profile.<name>]` table of the variables file over the other variables.
+    #[arg(long, global = true, env = "DOTFILES_PROFILE")]
+    profile: Option<String>,
+
+    /// Mode of directories created in the build and link dirs, e.g. 0700.
+    #[arg(long, value_parser = parse_mode_arg)]
+    dir_mode: Option<u32>,
+
+    /// Don't create files for templates which render to only whitespace, and remove old ones.
+    #[arg(long)]
+    skip_empty: bool,
+
+    /// What to do when a directory in the link dir is a symlink.
+    #[arg(long, value_enum, default_value_t)]
+    symlinked_dirs: SymlinkedDirs,
+
+    /// What to do with files in the link dir which are managed by home-manager, chezmoi or stow.
+    #[arg(long, value_enum, default_value_t)]
+    external_files: ExternalFiles,
+
+    /// Normalize the unicode in the names of linked files.
+    #[arg(long, value_enum)]
+    normalize_names: Option<Normalization>,
+
+    /// Restore the default SELinux context of linked files.
+    #[arg(long)]
+    selinux: bool,
+
+    /// Keep the POSIX ACLs of files replaced by the linker.
+    #[arg(long)]
+    preserve_acl: bool,
+
+    /// Link files with one blocking task per operation on a file, such as reading its link or
+    /// replacing it, instead of one per syscall, which is faster for trees with many small files.
+    #[arg(long)]
+    batched_fs: bool,
+
+    /// Move regular files replaced by the linker into a backup in the state dir.
+    #[arg(long, global = true)]
+    backup: bool,
+
     #[arg(short, action = ArgAction::Count)]
     verbosity: u8,
 
+    /// Fail if there were any warnings.
+    #[arg(long, global = true)]
+    deny_warnings: bool,
+
+    /// Stop collecting errors after this many, and only count the rest.
+    #[arg(long, global = true)]
+    max_errors: Option<usize>,
+
+    /// Set the variables which aren't set otherwise from `DOTFILES_VAR_<NAME>` environment
+    /// variables.
+    #[arg(long, global = true)]
+    env_vars: bool,
+
+    /// Set a variable, over the value from the variables file, e.g. `--set font_size=14`.
+    #[arg(long = "set", global = true, value_name = "NAME=VALUE", value_parser = parse_set_arg)]
+    overrides: Vec<(String, String)>,
+
+    /// Flags to set, e.g. `gaming`, or with a value, e.g. `monitor=DP-1`. Set a flag to false to
+    /// turn it off, e.g. `battery=false`.
     flags: Vec<String>,
 
     #[command(subcommand)]
@@ -40,31 +277,436 @@ struct Args {
 
 #[derive(Subcommand)]
 enum Action {
-    Sync,
-    Diff,
-    Print,
+    Sync {
+        /// Render with the exact env of the last successful sync.
+        #[arg(long)]
+        frozen: bool,
+
+        /// Also sync the targets in targets.toml, all at once.
+        #[arg(long, conflicts_with = "dry_run")]
+        all_targets: bool,
+
+        /// Print what would be built and linked, without changing anything.
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Only sync the files which failed to sync last time.
+        #[arg(long, conflicts_with = "all_targets")]
+        retry_failed: bool,
+    },
+    Diff {
+        /// Render the tree for two hosts from the inventory and diff the results.
+        #[arg(long, num_args = 2, value_names = ["HOST_A", "HOST_B"])]
+        between: Option<Vec<String>>,
+
+        /// Diff the build trees of two past syncs instead, as listed by history.
+        #[arg(long, value_name = "GEN", requires = "to", conflicts_with = "between")]
+        from: Option<u64>,
+
+        /// The later of the two syncs to diff.
+        #[arg(long, value_name = "GEN", requires = "from")]
+        to: Option<u64>,
+    },
+    Print {
+        /// Also print the description, type and example of each variable.
+        #[arg(long, conflicts_with = "types")]
+        describe: bool,
+
+        /// Print how templates use each variable, and warn about variables defined otherwise.
+        #[arg(long)]
+        types: bool,
+    },
+
+    /// Copy the built tree into the home directory of a running container, or of a host over ssh.
+    #[command(group(ArgGroup::new("container").required(true).args(["docker", "podman", "ssh"])))]
+    Deploy {
+        #[arg(long)]
+        docker: Option<String>,
+
+        #[arg(long)]
+        podman: Option<String>,
+
+        /// Push only what changed since the last push to this ssh destination, with rsync.
+        #[arg(long, value_name = "DESTINATION")]
+        ssh: Option<String>,
+    },
+
+    /// Set up the tree on a fresh machine, optionally cloning it first.
+    Bootstrap {
+        /// Git repository to clone into the template dir.
+        repo: Option<String>,
+
+        /// Only copy the "core" bundle, for ephemeral environments. Private files, templates which
+        /// use secrets and setting up keys, services and the desktop are left out.
+        #[arg(long)]
+        minimal: bool,
+    },
+
+    /// Render the tree into a scratch directory to check it for errors.
+    Check {
+        /// Check every host in the inventory.
+        #[arg(long)]
+        all_hosts: bool,
+    },
+
+    /// Check that the built files have the modes required by the permission rules.
+    Verify,
+
+    /// Check the templates for style issues.
+    Lint,
+
+    /// Look for secrets in the template tree before they get committed.
+    ScanSecrets {
+        /// Only scan the changes staged in the git repository of the template tree.
+        #[arg(long)]
+        staged: bool,
+
+        /// Install a git pre-commit hook which scans staged changes.
+        #[arg(long)]
+        install_hook: bool,
+    },
+
+    /// Print a script which recreates the built and linked tree without this tool.
+    Export {
+        #[arg(long, value_enum)]
+        format: export::Format,
+    },
+
+    /// List the files in the tree which changed since the last sync.
+    Audit,
+
+    /// List the built files and where they came from.
+    List,
+
+    /// Search the template tree for lines containing a string.
+    Grep {
+        pattern: String,
+
+        /// Search the files of the last build instead.
+        #[arg(long)]
+        rendered: bool,
+    },
+
+    /// Move an existing file into the tree, and link it back to where it was.
+    Add {
+        target: PathBuf,
+
+        /// Add the file as a template.
+        #[arg(long)]
+        template: bool,
+    },
+
+    /// Move the file in the tree which is linked to a path, so that it's linked somewhere else.
+    Mv { from: PathBuf, to: PathBuf },
+
+    /// Remove the links to the build tree from the link dir.
+    Unlink,
+
+    /// Remove the file in the tree which is linked to a path, along with the link.
+    ///
+    /// With --backup, a copy of the built file is kept in the state dir.
+    Rm { target: PathBuf },
+
+    /// List or restore the files backed up by the linker and rm.
+    Backups {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+
+    /// List the past syncs, or only those which changed a file or directory.
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        /// A file or directory in the template tree, or a link to a file in it.
+        path: Option<PathBuf>,
+
+        #[command(subcommand)]
+        action: Option<HistoryAction>,
+    },
+
+    /// Manage the hosts in the inventory from this machine.
+    Fleet {
+        #[command(subcommand)]
+        action: FleetAction,
+    },
+
+    /// Remove stale build outputs, scratch builds and old backups.
+    Clean {
+        /// Remove the whole build tree and every backup.
+        #[arg(long, conflicts_with = "older_than")]
+        all: bool,
+
+        /// Remove backups older than this, e.g. 30d.
+        #[arg(long, value_parser = parse_age)]
+        older_than: Option<Duration>,
+    },
+
+    /// Remove the build trees of past syncs and the backups which the retention settings in
+    /// settings.toml no longer keep. This also happens after every successful sync.
+    Gc,
+
+    /// Summarize the template tree and the last build.
+    Stats,
+
+    /// Show which variables each template uses, and which reload commands it triggers.
+    Graph {
+        #[arg(long, value_enum, default_value_t)]
+        format: GraphFormat,
+    },
+
+    /// Build the tree and show what linking it would do.
+    Plan {
+        #[arg(long, value_enum, default_value_t)]
+        format: PlanFormat,
+    },
+
+    /// Print the facts, variables and flags that templates are rendered with.
+    Env {
+        #[arg(long, value_enum, default_value_t)]
+        format: EnvFormat,
+    },
+
+    /// Unpack an archive created by `export --format tar` into the build dir and link it.
+    Restore { archive: PathBuf },
+
+    /// Interactively set up the template tree and variables, and show what syncing would do.
+    Setup,
+
+    /// Pull the template tree, merging local edits into what was pulled.
+    Pull,
+
+    /// Manage the template dir as a git checkout.
+    Repo {
+        #[command(subcommand)]
+        action: RepoAction,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Config {
+    /// Directory for the configuration of the tool itself.
+    config_dir: PathBuf,
+
+    /// Directory for state which is kept between runs.
+    state_dir: PathBuf,
     template_dir: PathBuf,
+
+    /// Tree which is built before the template dir, see [builder::build_tree].
+    shared_dir: Option<PathBuf>,
+
     build_dir: PathBuf,
     link_dir: PathBuf,
     variables_path: PathBuf,
+    inventory_path: PathBuf,
     flags: Vec<String>,
+
+    /// Profile from the variables file to use, see [builder::read_variables].
+    profile: Option<String>,
+
+    /// Variables set on the command line, which override all others.
+    overrides: Vec<(String, String)>,
+
+    /// Fall back to environment variables for variables which aren't set.
+    env_vars: bool,
+
+    link_mode: LinkMode,
+
+    /// If set, only these paths (relative to the tree) are built and linked.
+    include: Option<Vec<PathBuf>>,
+
+    /// Host from the inventory to render for, instead of the current machine.
+    host: Option<Host>,
+
+    /// Facts about the current machine which override detection.
+    facts: Facts,
+
+    /// Facts detected about the current machine.
+    detected: Detected,
+
+    permissions: PermissionRules,
+    validators: Validators,
+    formatters: Formatters,
+
+    /// How hooks are restricted.
+    sandbox: Sandbox,
+
+    /// Paths in the tree left out by ignore files.
+    ignore: IgnoreRules,
+
+    /// Where files which set a target in their front matter are linked.
+    link_targets: LinkTargets,
+
+    /// The Windows side of the machine, when running under WSL.
+    wsl: Option<Wsl>,
+
+    /// Whether we are running in Termux on Android.
+    termux: bool,
+
+    /// Where logical directories at the root of the tree are linked on each os.
+    paths: PathTable,
+
+    /// Mode of created directories.
+    dir_mode: Option<u32>,
+
+    /// Unicode normalization form of the names of linked files.
+    normalize_names: Option<Normalization>,
+
+    /// Where the linker moves the regular files it replaces, if anywhere.
+    backup_dir: Option<PathBuf>,
+
+    /// Link into the local filesystem with [BatchedFs].
+    batched_fs: bool,
+
+    selinux: bool,
+    preserve_acl: bool,
+    symlinked_dirs: SymlinkedDirs,
+    external_files: ExternalFiles,
+    skip_empty: bool,
+
+    /// Use the env recorded by the last successful sync instead of detecting it.
+    frozen: bool,
+
+    /// Don't touch the network.
+    offline: bool,
+
+    /// Run hooks without asking the user to allow them.
+    trust_all: bool,
+
+    /// Leave out private files and templates which use secrets, for machines which can't decrypt
+    /// or fetch them yet.
+    skip_secrets: bool,
+}
+
+impl Config {
+    /// Whether the file or directory at `relative` should be left out of the tree.
+    pub fn skip(&self, relative: &Path) -> bool {
+        if [
+            BUNDLES_FILE,
+            VARS_DOC_FILE,
+            FLATPAK_FILE,
+            SERVICES_FILE,
+            DCONF_FILE,
+        ]
+        .iter()
+        .any(|reserved| relative == Path::new(reserved))
+        {
+            return true;
+        }
+
+        if [DIR_SETTINGS_FILE, IGNORE_FILE]
+            .iter()
+            .any(|reserved| relative.file_name() == Some(OsStr::new(reserved)))
+        {
+            return true;
+        }
+
+        if self.ignore.is_ignored(&self.template_dir, relative) {
+            return true;
+        }
+
+        if [KEYS_DIR, PROFILE_DIR, HOOKS_DIR]
+            .iter()
+            .any(|dir| relative.starts_with(dir))
+        {
+            return true;
+        }
+
+        if relative.starts_with(WINDOWS_HOME_DIR) && self.wsl.is_none() {
+            return true;
+        }
+
+        !self.included(relative)
+    }
+
+    /// Where the file or directory at `relative` in the tree is linked to.
+    pub fn link_path(&self, relative: &Path) -> PathBuf {
+        if let Some(target) = self.link_targets.get(&*relative.to_string_lossy()) {
+            return target.clone();
+        }
+
+        if let (Some(wsl), Ok(rest)) = (&self.wsl, relative.strip_prefix(WINDOWS_HOME_DIR)) {
+            return wsl.home.join(rest);
+        }
+
+        let link_path = self
+            .link_dir
+            .join(self.paths.translate(link_relative(relative), self.os()));
+
+        match self.normalize_names {
+            Some(normalization) => normalization.apply(&link_path),
+            None => link_path,
+        }
+    }
+
+    /// The os of the machine files are linked on.
+    fn os(&self) -> &str {
+        self.host
+            .as_ref()
+            .and_then(|host| host.os.as_deref())
+            .or(self.facts.os.as_deref())
+            .unwrap_or(env::consts::OS)
+    }
+
+    /// Whether the file or directory at `relative` in the tree is linked onto a Windows file
+    /// system.
+    pub fn on_windows(&self, relative: &Path) -> bool {
+        (self.wsl.is_some() && relative.starts_with(WINDOWS_HOME_DIR)) || self.os() == "windows"
+    }
+
+    /// The target for linking into the local filesystem.
+    pub fn local_fs(&self) -> &'static dyn Target {
+        if self.batched_fs {
+            &BatchedFs
+        } else {
+            &LocalFs
+        }
+    }
+
+    /// Fail with an error if `what` can't be done because of `--offline`.
+    pub fn require_network(&self, what: &'static str) -> Result<(), InnerError> {
+        match self.offline {
+            true => Err(InnerError::Offline(what)),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether `relative` is part of the paths that are built and linked.
+    pub fn included(&self, relative: &Path) -> bool {
+        match &self.include {
+            None => true,
+            Some(paths) => paths
+                .iter()
+                .any(|path| path.starts_with(relative) || relative.starts_with(path)),
+        }
+    }
 }
 
+/// Exit status when only some of the files failed to sync, see `sync --retry-failed`.
+const PARTIAL_EXIT_CODE: i32 = 2;
+
 #[tokio::main]
 async fn main() {
     match run().await {
         Ok(_) => {}
-        Err(errors) => errors.log(),
+        Err(errors) => {
+            take_warnings().log();
+            let code = if errors.is_partial() {
+                PARTIAL_EXIT_CODE
+            } else {
+                1
+            };
+            errors.log();
+            std::process::exit(code);
+        }
     }
 }
 
 async fn run() -> Result<(), Errors> {
     let opt = Args::parse();
 
+    if let Some(max) = opt.max_errors {
+        set_max_errors(max);
+    }
+
     let filter_level = match opt.verbosity {
         0 => LevelFilter::Warn,
         1 => LevelFilter::Info,
@@ -77,11 +719,21 @@ async fn run() -> Result<(), Errors> {
         .init();
 
     let xdg_dirs = xdg::BaseDirectories::with_prefix("dotfiles").unwrap();
+    let settings = read_settings(&xdg_dirs.get_config_home()).await?;
+    let source = opt.source.or(settings.source);
 
     let cfg = Config {
+        config_dir: xdg_dirs.get_config_home(),
+        state_dir: xdg_dirs.get_state_home(),
         template_dir: opt
             .template_dir
-            .unwrap_or_else(|| xdg_dirs.create_config_directory("tree").expect("xdg")),
+            .or(settings.template_dir)
+            .unwrap_or_else(|| match &source {
+                // the fetched tree is replaced wholesale, so it's kept away from the user's files
+                Some(_) => xdg_dirs.get_data_home().join("source"),
+                None => xdg_dirs.create_config_directory("tree").expect("xdg"),
+            }),
+        shared_dir: opt.shared_dir.or(settings.shared_dir),
         build_dir: opt
             .build_dir
             .unwrap_or_else(|| xdg_dirs.create_cache_directory("").expect("xdg")),
@@ -91,29 +743,608 @@ async fn run() -> Result<(), Errors> {
         variables_path: opt
             .variables_path
             .unwrap_or_else(|| xdg_dirs.get_config_file("variables.toml")),
+        inventory_path: opt
+            .inventory_path
+            .unwrap_or_else(|| xdg_dirs.get_config_file("inventory.toml")),
         flags: opt.flags,
+        profile: opt.profile,
+        overrides: opt.overrides,
+        env_vars: opt.env_vars,
+        link_mode: LinkMode::Symlink,
+        include: None,
+        host: None,
+        facts: Facts::default(),
+        detected: Detected::default(),
+        permissions: PermissionRules::default(),
+        validators: Validators::default(),
+        formatters: Formatters::default(),
+        sandbox: Sandbox::default(),
+        ignore: IgnoreRules::default(),
+        link_targets: LinkTargets::default(),
+        paths: PathTable::default(),
+        wsl: None,
+        termux: detect_termux(),
+        dir_mode: opt.dir_mode,
+        normalize_names: opt.normalize_names,
+        backup_dir: None,
+        batched_fs: opt.batched_fs,
+        selinux: opt.selinux,
+        preserve_acl: opt.preserve_acl,
+        symlinked_dirs: opt.symlinked_dirs,
+        external_files: opt.external_files,
+        skip_empty: opt.skip_empty,
+        frozen: false,
+        offline: opt.offline,
+        trust_all: opt.trust_all,
+        skip_secrets: false,
+    };
+
+    // the tree is only fetched again when it's about to be used to sync
+    let fetch = matches!(opt.action, Action::Sync { .. } | Action::Bootstrap { .. });
+    if let Some(url) = &source {
+        if fetch || !cfg.template_dir.exists() {
+            fetch_source(&cfg, url).await?;
+        }
+    }
+
+    let cfg = Config {
+        permissions: read_permissions(&cfg).await?,
+        validators: read_validators(&cfg).await?,
+        formatters: read_formatters(&cfg).await?,
+        sandbox: read_sandbox(&cfg).await?,
+        ignore: read_ignores(&cfg.template_dir).await?,
+        link_targets: read_link_targets(&cfg).await?,
+        paths: read_paths(&cfg).await?,
+        detected: detect_facts(&cfg, opt.refresh_facts).await?,
+        facts: read_facts(&cfg).await?.overridden_by(Facts {
+            hostname: opt.hostname,
+            os: opt.os,
+        }),
+        ..cfg
+    };
+
+    let cfg = Config {
+        wsl: cfg.detected.wsl.clone(),
+        backup_dir: match opt.backup {
+            true => Some(new_backup_dir(&cfg).await?),
+            false => None,
+        },
+        ..cfg
+    };
+
+    let cfg = match opt.host {
+        Some(name) => Config {
+            host: Some(read_host(&cfg, &name).await?),
+            ..cfg
+        },
+        None => cfg,
     };
 
+    load_template_cache(&cfg).await?;
+
+    let deny_warnings = opt.deny_warnings;
     match opt.action {
-        Action::Sync => {
+        Action::Sync {
+            frozen,
+            dry_run: true,
+            ..
+        } => {
+            let cfg = Config { frozen, ..cfg };
+            dry_run(&cfg).await?;
+        }
+        Action::Sync {
+            frozen,
+            retry_failed: true,
+            ..
+        } => {
+            let failed = read_retry(&cfg).await?.failed;
+            if failed.is_empty() {
+                info!("nothing failed last time");
+                return Ok(());
+            }
+
+            let cfg = Config {
+                frozen,
+                include: Some(failed),
+                ..cfg
+            };
+            sync(&cfg).await?;
+        }
+        Action::Sync {
+            frozen,
+            all_targets: false,
+            ..
+        } => {
+            let cfg = Config { frozen, ..cfg };
+            sync(&cfg).await?;
+        }
+        Action::Sync {
+            frozen,
+            all_targets: true,
+            ..
+        } => {
+            let cfg = Config { frozen, ..cfg };
+            let targets = read_targets(&cfg).await?;
+
+            let others = targets
+                .iter()
+                .map(|(name, spec)| sync_target(&cfg, name, spec));
+            let (local, others) = join!(sync(&cfg), join_all(others));
+
+            let mut errors = Errors::default();
+            let results = [("local".to_string(), local)]
+                .into_iter()
+                .chain(targets.into_keys().zip(others));
+
+            for (name, result) in results {
+                match result {
+                    Ok(()) => println!("{name}: synced"),
+                    Err(e) => {
+                        println!("{name}: failed with {} errors", e.len());
+                        errors.join(e);
+                    }
+                }
+            }
+
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+        }
+        Action::Diff {
+            from: Some(from),
+            to: Some(to),
+            ..
+        } => {
+            info!("checking differences between syncs {from} and {to}");
+            diff_generations(&cfg, from, to).await?;
+        }
+        Action::Diff {
+            between: Some(hosts),
+            ..
+        } => {
+            let scratch = new_scratch(&cfg).await?;
+            let diffed = diff_hosts(&cfg, &scratch, &hosts[0], &hosts[1]).await;
+            remove_scratch(&scratch).await?;
+            diffed?;
+        }
+        Action::Diff { .. } => {
+            let scratch = new_scratch(&cfg).await?;
+            let diffed = diff_scratch(&cfg, &scratch).await;
+            remove_scratch(&scratch).await?;
+            diffed?;
+        }
+        Action::Print { describe, types } => {
+            info!("scanning tree");
+            if types {
+                print_types(&cfg).await?;
+            } else {
+                print_variables(&cfg, describe).await?;
+            }
+        }
+        Action::Deploy {
+            ssh: Some(destination),
+            ..
+        } => {
+            let pushed = push(&cfg, &destination).await?;
+            println!(
+                "pushed {} files, removed {} files",
+                pushed.changed, pushed.removed
+            );
+        }
+        Action::Deploy { docker, podman, .. } => {
+            let container = match (docker, podman) {
+                (Some(name), _) => Container::new(Runtime::Docker, name),
+                (_, Some(name)) => Container::new(Runtime::Podman, name),
+                (None, None) => unreachable!("enforced by clap"),
+            };
+
+            let scratch = new_scratch(&cfg).await?;
+            let deployed = deploy_container(&cfg, &scratch, &container).await;
+            remove_scratch(&scratch).await?;
+            deployed?;
+        }
+        Action::Bootstrap { repo, minimal } => {
+            if let Some(repo) = repo {
+                clone_repo(&cfg, &repo, minimal).await?;
+            }
+
+            // the ignore files weren't there before cloning
+            let cfg = Config {
+                ignore: read_ignores(&cfg.template_dir).await?,
+                ..cfg
+            };
+
+            let cfg = if minimal {
+                let core = read_bundle(&cfg, CORE_BUNDLE).await?;
+                Config {
+                    include: Some(core),
+                    link_mode: LinkMode::Copy,
+                    skip_secrets: true,
+                    ..cfg
+                }
+            } else {
+                cfg
+            };
+
+            info!("building tree");
+            build_tree(&cfg).await?;
+
+            info!("linking tree");
+            link_tree(&cfg, cfg.local_fs()).await?;
+
+            post_sync(&cfg, minimal).await?;
+        }
+        Action::Check { all_hosts } => {
+            let hosts: Vec<Option<Host>> = if all_hosts {
+                read_inventory(&cfg)
+                    .await?
+                    .into_values()
+                    .map(Some)
+                    .collect()
+            } else {
+                vec![cfg.host.clone()]
+            };
+
+            let scratch = new_scratch(&cfg).await?;
+            let mut errors = Errors::default();
+            for host in hosts {
+                let name = host
+                    .as_ref()
+                    .map(|host| host.name.clone())
+                    .unwrap_or_else(|| "local".into());
+
+                info!("checking {name}");
+                match build_scratch(&cfg, &scratch, host).await {
+                    Ok(_) => println!("{name}: ok"),
+                    Err(e) => {
+                        println!("{name}: failed");
+                        errors.join(e);
+                    }
+                }
+            }
+            remove_scratch(&scratch).await?;
+
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+        }
+        Action::Verify => {
+            info!("verifying tree");
+            verify_tree(&cfg).await?;
+        }
+        Action::Lint => {
+            info!("linting tree");
+            let findings = lint_tree(&cfg).await?;
+            if findings > 0 {
+                warn!("{findings} lint findings");
+            }
+        }
+        Action::ScanSecrets {
+            staged,
+            install_hook: install,
+        } => {
+            if install {
+                install_hook(&cfg).await?;
+            } else {
+                info!("scanning for secrets");
+                scan_secrets(&cfg, staged).await?;
+            }
+        }
+        Action::Export { format } => {
+            let scratch = new_scratch(&cfg).await?;
+            let exported = export_scratch(&cfg, &scratch, format).await;
+            remove_scratch(&scratch).await?;
+            exported?;
+        }
+        Action::Audit => {
+            info!("auditing tree");
+            let changed = audit(&cfg).await?;
+            if changed == 0 {
+                println!("the last sync is up to date");
+            }
+        }
+        Action::List => {
+            list_tree(&cfg).await?;
+        }
+        Action::Grep { pattern, rendered } => {
+            if grep_tree(&cfg, &pattern, rendered).await? == 0 {
+                info!("no matches for {pattern:?}");
+            }
+        }
+        Action::Add { target, template } => {
+            let relative = add_target(&cfg, &target, template).await?;
+
+            // the linker only picks up the output if it's included too
+            let cfg = Config {
+                include: Some(vec![output_path(&relative), relative]),
+                ..cfg
+            };
+
             info!("building tree");
             build_tree(&cfg).await?;
 
             info!("linking tree");
-            link_tree(&cfg).await?;
+            link_tree(&cfg, cfg.local_fs()).await?;
         }
-        Action::Diff => {
+        Action::Mv { from, to } => {
+            let relative = move_target(&cfg, &from, &to).await?;
+
+            // the linker only picks up the output if it's included too
+            let cfg = Config {
+                include: Some(vec![output_path(&relative), relative]),
+                ..cfg
+            };
+
             info!("building tree");
             build_tree(&cfg).await?;
 
-            info!("checking differences between current state and dotfiles");
-            todo!("not implemented");
+            info!("linking tree");
+            link_tree(&cfg, cfg.local_fs()).await?;
         }
-        Action::Print => {
-            info!("scanning tree");
-            print_variables(&cfg).await?;
+        Action::Unlink => {
+            info!("unlinking tree");
+            let removed = unlink_tree(&cfg).await?;
+            info!("removed {removed} links");
+        }
+        Action::Rm { target } => remove_target(&cfg, &target).await?,
+        Action::Backups {
+            action: BackupAction::List,
+        } => list_backups(&cfg).await?,
+        Action::Backups {
+            action: BackupAction::Restore { id, path },
+        } => restore_backup(&cfg, id, path.as_deref()).await?,
+        Action::History { path, action: None } => print_history(&cfg, path.as_deref()).await?,
+        Action::History {
+            action: Some(HistoryAction::Show { id }),
+            ..
+        } => show_sync(&cfg, id).await?,
+        Action::Fleet {
+            action: FleetAction::Sync { hosts, remote },
+        } => sync_fleet(&cfg, &hosts, remote.as_deref()).await?,
+        Action::Clean { all, older_than } => {
+            let freed = clean(&cfg, all, older_than).await?;
+            println!("freed {freed} bytes");
+        }
+        Action::Gc => {
+            let freed = gc(&cfg).await?;
+            println!("freed {freed} bytes");
+        }
+        Action::Stats => print_stats(&cfg).await?,
+        Action::Graph { format } => print_graph(&cfg, format).await?,
+        Action::Plan { format } => {
+            let scratch = new_scratch(&cfg).await?;
+            let planned = plan_scratch(&cfg, &scratch).await;
+            remove_scratch(&scratch).await?;
+            print_plan(&planned?, format);
+        }
+        Action::Env { format } => {
+            print_env(&cfg, format).await?;
+        }
+        Action::Restore { archive } => {
+            info!("unpacking {archive:?}");
+            extract_archive(&cfg, &archive).await?;
+
+            info!("linking tree");
+            link_tree(&cfg, cfg.local_fs()).await?;
+        }
+        Action::Pull => pull(&cfg).await?,
+        Action::Repo { action } => repo(&cfg, action).await?,
+        Action::Setup => {
+            let cfg = setup(cfg).await?;
+
+            println!();
+            println!("syncing would make these changes:");
+            dry_run(&cfg).await?;
         }
     }
 
+    let warnings = take_warnings();
+    if deny_warnings && !warnings.is_empty() {
+        return Err(warnings.into_errors());
+    }
+    warnings.log();
+
+    Ok(())
+}
+
+/// Build and link the tree, and apply the rest of it.
+///
+/// Files which fail to build or link don't stop the others from being linked, but are recorded
+/// for `--retry-failed`. If the variables can't be read nothing is built, and the last build isn't
+/// relinked.
+async fn sync(cfg: &Config) -> Result<(), Errors> {
+    run_hook(cfg, Hook::PreSync).await?;
+
+    build_env(cfg).await?;
+
+    let mut errors = Errors::default();
+
+    info!("building tree");
+    if let Err(e) = build_tree(cfg).await {
+        errors.join(e);
+    }
+
+    info!("linking tree");
+    if let Err(e) = link_tree(cfg, cfg.local_fs()).await {
+        errors.join(e);
+    }
+
+    let retry = write_retry(cfg, &errors).await?;
+    if !errors.is_empty() {
+        // without a build tree nothing was built
+        let built = list_files(&cfg.build_dir).await.unwrap_or_default();
+        let succeeded = built
+            .iter()
+            .any(|relative| !retry.failed.contains(relative));
+        return Err(if succeeded { errors.partial() } else { errors });
+    }
+
+    post_sync(cfg, false).await?;
+    run_hook(cfg, Hook::PostSync).await
+}
+
+/// Apply the parts of the tree which aren't files, once the tree is linked, and record the sync.
+///
+/// With `minimal` only the sync is recorded, as keys, services and the desktop are left to the
+/// first full sync.
+async fn post_sync(cfg: &Config, minimal: bool) -> Result<(), Errors> {
+    if !minimal {
+        info!("installing public keys");
+        install_keys(cfg).await?;
+
+        info!("installing services");
+        install_services(cfg).await?;
+
+        info!("running reload commands");
+        run_reloads(cfg).await?;
+
+        info!("loading dconf settings");
+        load_settings(cfg).await?;
+
+        info!("applying theme");
+        apply_theme(cfg).await?;
+
+        info!("applying flatpak overrides");
+        apply_overrides(cfg).await?;
+    }
+
+    let at = unix_time();
+    save_generation(cfg, at).await?;
+
+    let previous = read_lock(cfg).await.ok();
+    let lock = Lock::current(cfg).await?;
+    record_sync(cfg, at, previous.as_ref(), &lock).await?;
+    write_lock(cfg, &lock).await?;
+
+    let freed = gc(cfg).await?;
+    debug!("freed {freed} bytes of old generations and backups");
+
+    Ok(())
+}
+
+fn parse_mode_arg(mode: &str) -> Result<u32, String> {
+    parse_mode(mode).map_err(|e| e.to_string())
+}
+
+fn parse_set_arg(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err("expected NAME=VALUE".to_string()),
+    }
+}
+
+/// Build the tree into a scratch directory, and print what building and linking it would change.
+async fn dry_run(cfg: &Config) -> Result<(), Errors> {
+    let scratch = new_scratch(cfg).await?;
+    let checked = dry_run_in(cfg, &scratch).await;
+    remove_scratch(&scratch).await?;
+    checked
+}
+
+async fn dry_run_in(cfg: &Config, scratch_dir: &Path) -> Result<(), Errors> {
+    info!("building tree into a scratch directory");
+    let scratch = build_scratch(cfg, scratch_dir, cfg.host.clone()).await?;
+    print_build_changes(cfg, &scratch).await?;
+
+    let dry_run = DryRun::new(scratch.build_dir.clone(), cfg.build_dir.clone());
+
+    info!("checking links");
+    let linked = link_tree(&scratch, &dry_run).await;
+    dry_run.print();
+    linked
+}
+
+/// Build the tree for the home of `container` in the scratch directory `scratch`, so that the
+/// build and state of this machine are left alone, and copy it into the container.
+async fn deploy_container(
+    cfg: &Config,
+    scratch: &Path,
+    container: &Container,
+) -> Result<(), Errors> {
+    let home = container
+        .home()
+        .await
+        .with_location(Path::new(container.name()))?;
+    let cfg = Config {
+        link_dir: home,
+        link_mode: LinkMode::Copy,
+        ..cfg.clone()
+    };
+
+    info!("building tree");
+    let scratch = build_scratch(&cfg, scratch, cfg.host.clone()).await?;
+
+    info!("copying tree into container");
+    link_tree(&scratch, container).await
+}
+
+/// Build the tree in the scratch directory `scratch`, leaving the linked build tree alone, and
+/// print it in `format`.
+async fn export_scratch(
+    cfg: &Config,
+    scratch: &Path,
+    format: export::Format,
+) -> Result<(), Errors> {
+    info!("building tree");
+    let scratch = build_scratch(cfg, scratch, cfg.host.clone()).await?;
+
+    info!("exporting tree");
+    export_tree(&scratch, format).await
+}
+
+/// Build the tree in the scratch directory `scratch`, and work out what linking it would do.
+async fn plan_scratch(cfg: &Config, scratch: &Path) -> Result<Plan, Errors> {
+    info!("building tree");
+    let scratch = build_scratch(cfg, scratch, cfg.host.clone()).await?;
+
+    info!("planning");
+    plan_tree(cfg, &scratch).await
+}
+
+/// Build the tree in the scratch directory `scratch`, and print how it and the env differ from
+/// what is linked and applied now.
+async fn diff_scratch(cfg: &Config, scratch: &Path) -> Result<(), Errors> {
+    info!("building tree");
+    let scratch = build_scratch(cfg, scratch, cfg.host.clone()).await?;
+
+    info!("checking differences between current state and dotfiles");
+    diff_env(cfg).await?;
+    diff_settings(cfg).await?;
+
+    let mut files = list_files(&scratch.build_dir).await?;
+    files.retain(|relative| !cfg.skip(relative));
+    diff_links(cfg, &scratch, &files).await?;
+    Ok(())
+}
+
+/// Build the trees of the hosts `a` and `b` in the scratch directory `scratch`, and print how
+/// they differ.
+async fn diff_hosts(cfg: &Config, scratch: &Path, a: &str, b: &str) -> Result<(), Errors> {
+    info!("building tree for {a}");
+    let a_cfg = build_scratch(cfg, scratch, Some(read_host(cfg, a).await?)).await?;
+
+    info!("building tree for {b}");
+    let b_cfg = build_scratch(cfg, scratch, Some(read_host(cfg, b).await?)).await?;
+
+    let mut files = list_files(&a_cfg.build_dir).await?;
+    files.append(&mut list_files(&b_cfg.build_dir).await?);
+    files.sort_unstable();
+    files.dedup();
+
+    let a_sources = read_sources(&a_cfg).await?;
+    let b_sources = read_sources(&b_cfg).await?;
+    let a_side = Side {
+        label: a,
+        root: &a_cfg.build_dir,
+        sources: &a_sources,
+    };
+    let b_side = Side {
+        label: b,
+        root: &b_cfg.build_dir,
+        sources: &b_sources,
+    };
+
+    info!("checking differences between {a} and {b}");
+    diff_trees(&a_side, &b_side, &files).await?;
     Ok(())
 }
@@ -0,0 +1,59 @@
+use std::fmt::{self, Display};
+use std::path::{Component, Path};
+
+/// Files below this directory are read only, and managed by something else.
+pub const NIX_STORE: &str = "/nix/store";
+
+/// Another dotfiles manager, which links files into the link dir in its own way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Manager {
+    HomeManager,
+    Nix,
+    Chezmoi,
+    Stow,
+}
+
+impl Manager {
+    /// Work out which manager, if any, created a symlink pointing to `original`.
+    pub fn detect(original: &Path) -> Option<Manager> {
+        let s = original.to_string_lossy();
+
+        if original.starts_with(NIX_STORE) {
+            match s.contains("home-manager") {
+                true => Some(Manager::HomeManager),
+                false => Some(Manager::Nix),
+            }
+        } else if s.contains(".local/share/chezmoi") {
+            Some(Manager::Chezmoi)
+        } else if original
+            .components()
+            .any(|c| c == Component::Normal("stow".as_ref()))
+        {
+            Some(Manager::Stow)
+        } else {
+            None
+        }
+    }
+
+    /// How to hand a file over from the manager.
+    pub fn resolution(self) -> &'static str {
+        match self {
+            Manager::HomeManager => "remove it from your home-manager configuration and switch",
+            Manager::Nix => "remove it from your nix configuration",
+            Manager::Chezmoi => "run `chezmoi forget` on it",
+            Manager::Stow => "unstow its package with `stow -D`",
+        }
+    }
+}
+
+impl Display for Manager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Manager::HomeManager => "home-manager",
+            Manager::Nix => "nix",
+            Manager::Chezmoi => "chezmoi",
+            Manager::Stow => "stow",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,172 @@
+use crate::error::{ErrorLocation, Errors};
+use crate::git;
+use crate::lock::{content_hash, read_lock, Lock};
+use crate::setup::ask;
+use crate::warnings::warning;
+use crate::Config;
+use std::env;
+use std::io::{stdin, IsTerminal};
+use std::path::Path;
+use tokio::fs::{create_dir_all, read, remove_dir_all, write};
+use tokio::process::Command;
+
+/// Name of the directory in the state dir where local edits are kept while pulling.
+const MERGE_DIR: &str = "merge";
+
+/// Pull the template tree, and merge the local edits to it into what was pulled.
+///
+/// Files with uncommitted edits are set aside and reset before pulling, so that git doesn't
+/// refuse to pull or leave conflict markers behind. Each of them is then merged with a three-way
+/// merge, with the file as it was at the last sync as the base. Conflicts are resolved by asking
+/// the user, or left marked in the file when not running interactively.
+pub async fn pull(cfg: &Config) -> Result<(), Errors> {
+    let dir = &cfg.template_dir;
+    cfg.require_network("pulling").with_location(dir)?;
+
+    let modified = git::modified_files(dir).await.with_location(dir)?;
+
+    // the base is the tree at the last sync, or the last commit before the edits
+    let lock = read_lock(cfg).await.ok();
+    let base_commit = match lock.as_ref().and_then(|lock| lock.commit()) {
+        Some(commit) => commit.to_string(),
+        None => git::head(dir).await.with_location(dir)?,
+    };
+
+    // keep the edits somewhere safe until they're merged back
+    let merge_dir = cfg.state_dir.join(MERGE_DIR);
+    for relative in &modified {
+        let path = dir.join(relative);
+        let local = merge_dir.join("local").join(relative);
+        let content = read(&path).await.with_location(&path)?;
+        if let Some(parent) = local.parent() {
+            create_dir_all(parent).await.with_location(parent)?;
+        }
+        write(&local, content).await.with_location(&local)?;
+    }
+
+    if !modified.is_empty() {
+        info!("setting aside edits to {} files", modified.len());
+        git::restore(dir, &modified).await.with_location(dir)?;
+    }
+
+    info!("pulling {dir:?}");
+    let pulled = git::pull(dir).await.with_location(dir);
+
+    let mut errors = Errors::default();
+    for relative in &modified {
+        if let Err(e) = merge_back(cfg, &base_commit, lock.as_ref(), relative).await {
+            errors.join(e);
+        }
+    }
+
+    if errors.is_empty() {
+        let _ = remove_dir_all(&merge_dir).await;
+    }
+    pulled?;
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Merge the local edits to `relative`, set aside in the state dir, into the pulled file.
+async fn merge_back(
+    cfg: &Config,
+    base_commit: &str,
+    lock: Option<&Lock>,
+    relative: &str,
+) -> Result<(), Errors> {
+    let path = cfg.template_dir.join(relative);
+    let merge_dir = cfg.state_dir.join(MERGE_DIR);
+    let local = merge_dir.join("local").join(relative);
+    let base = merge_dir.join("base").join(relative);
+
+    let local_content = read(&local).await.with_location(&local)?;
+    let base_content = git::show(&cfg.template_dir, base_commit, relative)
+        .await
+        .with_location(&path)?
+        .unwrap_or_default();
+
+    // the file may have had uncommitted edits when it was synced, which git doesn't know about
+    let hash = content_hash(base_content.as_bytes());
+    if lock.is_some_and(|lock| lock.file_hash(relative) != Some(hash.as_str())) {
+        warning(
+            &path,
+            "was edited before the last sync, merging with its last commit",
+        );
+    }
+
+    let upstream = match read(&path).await {
+        Ok(content) => content,
+        Err(_) => {
+            // deleted upstream, keep the local edits
+            write(&path, &local_content).await.with_location(&path)?;
+            return Ok(());
+        }
+    };
+
+    if upstream == base_content.as_bytes() || upstream == local_content {
+        write(&path, &local_content).await.with_location(&path)?;
+        return Ok(());
+    }
+
+    if let Some(parent) = base.parent() {
+        create_dir_all(parent).await.with_location(parent)?;
+    }
+    write(&base, &base_content).await.with_location(&base)?;
+
+    let (merged, conflicts) = git::merge_file(&local, &base, &path)
+        .await
+        .with_location(&path)?;
+
+    if !conflicts {
+        info!("merged the edits to {relative}");
+        write(&path, merged).await.with_location(&path)?;
+        return Ok(());
+    }
+
+    if !stdin().is_terminal() {
+        warning(
+            &path,
+            "edits conflict with the pulled changes, resolve the conflict markers",
+        );
+        write(&path, merged).await.with_location(&path)?;
+        return Ok(());
+    }
+
+    println!("{relative}: the edits conflict with the pulled changes");
+    loop {
+        let question = "keep [l]ocal, take [u]pstream, or [e]dit the merge? ";
+        let answer = ask(question).await.with_location(&path)?;
+        match answer.as_str() {
+            "l" => write(&path, &local_content).await.with_location(&path)?,
+            "u" => {}
+            "e" => {
+                write(&path, &merged).await.with_location(&path)?;
+                edit(&path).await?;
+            }
+            _ => continue,
+        }
+        return Ok(());
+    }
+}
+
+/// Open `path` in the user's editor, and wait for them to close it.
+async fn edit(path: &Path) -> Result<(), Errors> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    let status = Command::new(&editor)
+        .arg(path)
+        .status()
+        .await
+        .with_location(path)?;
+
+    if !status.success() {
+        warning(path, format!("{editor} exited with {status}"));
+    }
+    Ok(())
+}
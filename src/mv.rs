@@ -0,0 +1,132 @@
+use crate::builder::output_path;
+use crate::bundle::BUNDLES_FILE;
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::private::{is_private, PRIVATE_DIR};
+use crate::state::{read_sources, write_sources, Sources};
+use crate::Config;
+use std::io::ErrorKind;
+use std::path::{absolute, Path, PathBuf};
+use tokio::fs::{create_dir_all, read_to_string, remove_file, rename, symlink_metadata, write};
+
+/// Move the file in the tree which is linked to `from`, so that it's linked to `to` instead.
+///
+/// The old link and built file are removed, and bundles which mention the file are updated.
+/// Returns the new path of the file in the tree, which is left for the caller to build and link.
+pub async fn move_target(cfg: &Config, from: &Path, to: &Path) -> Result<PathBuf, Error> {
+    let from = absolute(from).with_location(from)?;
+    let to = absolute(to).with_location(to)?;
+
+    let mut sources = read_sources(cfg).await?;
+    let (built, source) = find_target(cfg, &sources, &from)?;
+    let source_path = cfg.template_dir.join(&source);
+
+    let Ok(new_link_relative) = to.strip_prefix(&cfg.link_dir) else {
+        return Err(InnerError::OutsideLinkDir.with_location(&to));
+    };
+
+    let new_source = moved_source(Path::new(&source), new_link_relative);
+
+    let new_source_path = cfg.template_dir.join(&new_source);
+    if new_source_path.exists() {
+        return Err(InnerError::AlreadyExists.with_location(&new_source_path));
+    }
+
+    if let Some(parent) = new_source_path.parent() {
+        create_dir_all(parent).await.with_location(parent)?;
+    }
+
+    info!("moving {source_path:?} to {new_source_path:?}");
+    rename(&source_path, &new_source_path)
+        .await
+        .with_location(&source_path)?;
+
+    rename_in_bundles(cfg, &source, &new_source.to_string_lossy()).await?;
+
+    unlink_target(cfg, &from, &built).await?;
+    sources.remove(&built);
+    write_sources(cfg, &sources).await?;
+
+    Ok(new_source)
+}
+
+/// Where in the tree a file linked to `link_relative` goes, kept private, encrypted and a
+/// template like `source`, so that it's built the same way.
+fn moved_source(source: &Path, link_relative: &Path) -> PathBuf {
+    let mut new_source = match is_private(source) {
+        true => Path::new(PRIVATE_DIR).join(link_relative),
+        false => link_relative.to_path_buf(),
+    };
+
+    // e.g. `.tpl.age`
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    let output = output_path(source);
+    let output_name = output.file_name().unwrap_or_default().to_string_lossy();
+    new_source
+        .as_mut_os_string()
+        .push(&name[output_name.len()..]);
+
+    new_source
+}
+
+/// Find the file in the build tree which is linked to `target`, and the file in the template tree
+/// it was made from.
+pub fn find_target(
+    cfg: &Config,
+    sources: &Sources,
+    target: &Path,
+) -> Result<(String, String), Error> {
+    // the source of each built file is recorded, so find the one which is linked to `target`
+    let Some((built, source)) = sources
+        .iter()
+        .find(|(built, _)| cfg.link_path(Path::new(built)) == target)
+    else {
+        return Err(InnerError::NotInTree.with_location(target));
+    };
+
+    // sources in the shared tree are recorded by their absolute path
+    if !Path::new(source).is_relative() {
+        return Err(InnerError::InSharedTree.with_location(target));
+    }
+
+    // e.g. outputs of multi-output templates or generated files
+    if !cfg.template_dir.join(source).is_file() {
+        return Err(InnerError::NotInTree.with_location(target));
+    }
+
+    Ok((built.clone(), source.clone()))
+}
+
+/// Remove the link at `target`, if it's ours, and the file at `built` in the build tree.
+pub async fn unlink_target(cfg: &Config, target: &Path, built: &str) -> Result<(), Error> {
+    if symlink_metadata(target)
+        .await
+        .is_ok_and(|meta| meta.file_type().is_symlink())
+    {
+        debug!("removing link {target:?}");
+        remove_file(target).await.with_location(target)?;
+    }
+
+    let built_path = cfg.build_dir.join(built);
+    match remove_file(&built_path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.with_location(&built_path)),
+    }
+}
+
+/// Replace mentions of the path `from` in the bundles file with `to`, keeping everything else as
+/// it is.
+async fn rename_in_bundles(cfg: &Config, from: &str, to: &str) -> Result<(), Error> {
+    let path = cfg.template_dir.join(BUNDLES_FILE);
+    let Ok(s) = read_to_string(&path).await else {
+        return Ok(());
+    };
+
+    let renamed = s.replace(&format!("{from:?}"), &format!("{to:?}"));
+    if renamed != s {
+        info!("updating {path:?}");
+        write(&path, renamed).await.with_location(&path)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization form of file names in the link dir.
+///
+/// macOS has historically stored names decomposed while Linux keeps them as written, so a tree
+/// shared between them can end up with names which look the same but aren't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Normalization {
+    /// Composed, e.g. `é` as one code point.
+    Nfc,
+
+    /// Decomposed, e.g. `é` as `e` followed by a combining accent.
+    Nfd,
+}
+
+impl Normalization {
+    pub fn apply(self, path: &Path) -> PathBuf {
+        // names which aren't unicode can't be normalized
+        let Some(s) = path.to_str() else {
+            return path.to_path_buf();
+        };
+
+        match self {
+            Normalization::Nfc => s.nfc().collect::<String>().into(),
+            Normalization::Nfd => s.nfd().collect::<String>().into(),
+        }
+    }
+}
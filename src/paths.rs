@@ -0,0 +1,100 @@
+use crate::error::{Error, ErrorLocation};
+use crate::Config;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::read_to_string;
+
+/// Name of the file in the config dir which maps logical directories at the root of the tree to
+/// a directory, relative to the link dir, for each os.
+///
+/// ```toml
+/// [CONFIG]
+/// linux = ".config"
+/// macos = "Library/Application Support"
+/// windows = "AppData/Roaming"
+/// ```
+///
+/// Entries are merged with the built-in table, see [BUILTIN].
+const PATHS_FILE: &str = "paths.toml";
+
+/// Logical directories which are translated without being configured.
+const BUILTIN: &[(&str, &[(&str, &str)])] = &[
+    (
+        "CONFIG",
+        &[
+            ("linux", ".config"),
+            ("macos", "Library/Application Support"),
+            ("windows", "AppData/Roaming"),
+        ],
+    ),
+    (
+        "DATA",
+        &[
+            ("linux", ".local/share"),
+            ("macos", "Library/Application Support"),
+            ("windows", "AppData/Local"),
+        ],
+    ),
+    (
+        "CACHE",
+        &[
+            ("linux", ".cache"),
+            ("macos", "Library/Caches"),
+            ("windows", "AppData/Local/Temp"),
+        ],
+    ),
+];
+
+#[derive(Clone, Debug, Default)]
+pub struct PathTable {
+    /// Directory for each os, by logical directory.
+    dirs: HashMap<String, HashMap<String, PathBuf>>,
+}
+
+impl PathTable {
+    /// Translate a path in the tree which starts with a logical directory to where it belongs on
+    /// `os`. Other paths, and logical directories without an entry for `os`, are left as they are.
+    pub fn translate(&self, relative: &Path, os: &str) -> PathBuf {
+        let mut components = relative.iter();
+        let Some(first) = components.next() else {
+            return relative.to_path_buf();
+        };
+
+        let translated = self
+            .dirs
+            .get(&*first.to_string_lossy())
+            .and_then(|targets| targets.get(os));
+
+        match translated {
+            Some(dir) => dir.join(components.as_path()),
+            None => relative.to_path_buf(),
+        }
+    }
+}
+
+pub async fn read_paths(cfg: &Config) -> Result<PathTable, Error> {
+    let mut dirs: HashMap<String, HashMap<String, PathBuf>> = BUILTIN
+        .iter()
+        .map(|(dir, targets)| {
+            let targets = targets
+                .iter()
+                .map(|(os, target)| (os.to_string(), PathBuf::from(target)))
+                .collect();
+            (dir.to_string(), targets)
+        })
+        .collect();
+
+    let path = cfg.config_dir.join(PATHS_FILE);
+
+    debug!("trying to read {:?}", path);
+    if let Ok(s) = read_to_string(&path).await {
+        let configured: HashMap<String, HashMap<String, PathBuf>> =
+            toml::de::from_str(&s).with_location(&path)?;
+
+        for (dir, targets) in configured {
+            dirs.entry(dir).or_default().extend(targets);
+        }
+    }
+
+    Ok(PathTable { dirs })
+}
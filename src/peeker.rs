@@ -1,84 +1,259 @@
-use crate::builder::TEMPLATE_EXTENSION;
+use crate::builder::{read_variables, TEMPLATE_EXTENSION};
 use crate::error::{Error, ErrorLocation, Errors};
+use crate::frontmatter::split_front_matter;
+use crate::tplcache::{save_template_cache, template_variables};
+use crate::usage::{infer_usage, Usage};
+use crate::warnings::warning;
 use crate::Config;
 use async_recursion::async_recursion;
-use blueprint::parse_template;
 use futures::future::join_all;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs::{read_dir, read_to_string};
 use tokio::join;
 
+/// File at the root of the template tree documenting the variables used by the templates.
+///
+/// ```toml
+/// [font_size]
+/// description = "Font size of the terminal"
+/// type = "string"
+/// example = "12"
+///
+/// [api_token]
+/// description = "Token for the weather widget"
+/// sensitive = true # never shown in logs, diffs or errors
+/// ```
+pub const VARS_DOC_FILE: &str = "vars.doc.toml";
+
+#[derive(Default, Deserialize)]
+pub struct VarDoc {
+    pub description: Option<String>,
+
+    #[serde(rename = "type")]
+    ty: Option<String>,
+
+    pub example: Option<toml::Value>,
+
+    #[serde(default)]
+    sensitive: bool,
+}
+
+impl VarDoc {
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+}
+
 /// Iterate over the directory tree and print all variables used in all template files.
-pub async fn print_variables(cfg: &Config) -> Result<(), Errors> {
-    for var in dir(cfg, PathBuf::new()).await? {
-        println!("{}", var);
+///
+/// If `describe` is set, also print the documentation, type and example of each variable.
+///
+/// Templates which fail to parse are reported as errors after the variables of all other templates
+/// have been printed.
+pub async fn print_variables(cfg: &Config, describe: bool) -> Result<(), Errors> {
+    let (vars, errors) = dir(cfg, PathBuf::new()).await;
+    save_template_cache(cfg).await?;
+    let vars: Vec<String> = vars.into_keys().collect();
+
+    if describe {
+        print_descriptions(cfg, vars).await?;
+    } else {
+        for var in vars {
+            println!("{}", var);
+        }
     }
 
-    Ok(())
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
 }
 
-#[async_recursion]
-async fn dir(cfg: &Config, relative: PathBuf) -> Result<Vec<String>, Errors> {
-    let template_path = cfg.template_dir.join(&relative);
+async fn print_descriptions(cfg: &Config, vars: Vec<String>) -> Result<(), Errors> {
+    let mut docs = read_docs(cfg).await?;
+    let values = read_variables(cfg).await?;
 
-    info!("traversing {:?}", template_path);
+    for var in vars {
+        let doc = docs.remove(&var).unwrap_or_default();
+        let ty = doc
+            .ty
+            .as_deref()
+            .or_else(|| values.get(&var).map(|value| value.type_str()))
+            .unwrap_or("unknown");
 
-    let mut walker = read_dir(&template_path)
-        .await
-        .with_location(&template_path)?;
+        println!("{var}: {ty}");
+        if let Some(description) = doc.description {
+            println!("    {description}");
+        }
+        if let Some(example) = doc.example {
+            println!("    example: {example}");
+        }
+    }
 
-    let mut dir_tasks = vec![];
-    let mut file_tasks = vec![];
+    Ok(())
+}
 
-    while let Some(entry) = walker.next_entry().await.with_location(&template_path)? {
-        let meta = entry.metadata().await.with_location(&entry.path())?;
-        let new_relative = relative.join(entry.file_name());
+/// Iterate over the directory tree and print how the templates use each variable, and how it's
+/// defined in the variables file.
+///
+/// A variable which is defined as something else than what a template uses it as, such as a
+/// string which is used as a condition, is reported as a warning.
+pub async fn print_types(cfg: &Config) -> Result<(), Errors> {
+    let (vars, errors) = dir(cfg, PathBuf::new()).await;
+    save_template_cache(cfg).await?;
+    let values = read_variables(cfg).await?;
 
-        if meta.is_dir() {
-            dir_tasks.push(dir(cfg, new_relative));
-        } else if meta.is_file() {
-            file_tasks.push(file(cfg, new_relative));
+    for (var, usages) in vars {
+        let used_as: Vec<String> = usages.keys().map(|usage| usage.to_string()).collect();
+        let used_as = if used_as.is_empty() {
+            "unknown".to_string()
+        } else {
+            used_as.join(", ")
+        };
+
+        let Some(value) = values.get(&var) else {
+            println!("{var}: used as {used_as}, not defined");
+            continue;
+        };
+        println!("{var}: used as {used_as}, defined as {}", value.type_str());
+
+        for (usage, templates) in &usages {
+            if usage.accepts(value) {
+                continue;
+            }
+
+            for template in templates {
+                let message = format!(
+                    "{var} is defined as {} but {template:?} uses it as a {usage}",
+                    value.type_str(),
+                );
+                warning(&cfg.variables_path, message);
+            }
         }
     }
 
-    let dirs = async { join_all(dir_tasks).await.into_iter().collect::<Vec<_>>() };
-    let files = async { join_all(file_tasks).await.into_iter().collect::<Vec<_>>() };
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Collect the variables used by the templates, with how they are used.
+///
+/// Errors don't stop the traversal, they are returned together with the variables that could be
+/// collected.
+pub async fn used_variables(cfg: &Config) -> (Vec<(String, Vec<Usage>)>, Errors) {
+    let (vars, errors) = dir(cfg, PathBuf::new()).await;
+    let vars = vars
+        .into_iter()
+        .map(|(var, usages)| (var, usages.into_keys().collect()))
+        .collect();
+
+    (vars, errors)
+}
+
+/// Read the variable documentation, if there is any.
+pub async fn read_docs(cfg: &Config) -> Result<HashMap<String, VarDoc>, Error> {
+    let path = cfg.template_dir.join(VARS_DOC_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(HashMap::new());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+/// The variables used by templates, with the templates which use them in each way.
+type Variables = BTreeMap<String, BTreeMap<Usage, BTreeSet<PathBuf>>>;
+
+/// Collect the variables used in all templates under `relative`.
+///
+/// Errors don't stop the traversal, they are returned together with the variables that could be
+/// collected.
+#[async_recursion]
+async fn dir(cfg: &Config, relative: PathBuf) -> (Variables, Errors) {
+    let (dir_paths, file_paths) = match list_dir(cfg, &relative).await {
+        Ok(paths) => paths,
+        Err(error) => return (Variables::new(), error.into()),
+    };
+
+    let dirs = join_all(dir_paths.into_iter().map(|path| dir(cfg, path)));
+    let files = join_all(file_paths.into_iter().map(|path| file(cfg, path)));
     let (dirs, files) = join!(dirs, files);
 
-    let mut vars = vec![];
+    let mut vars = Variables::new();
     let mut errors = vec![];
 
     for result in files.into_iter() {
         match result {
-            Ok(mut more_vars) => vars.append(&mut more_vars),
+            Ok(more_vars) => merge(&mut vars, more_vars),
             Err(error) => errors.push(error),
         }
     }
 
     let mut errors: Errors = errors.into();
 
-    for result in dirs.into_iter() {
-        match result {
-            Ok(mut more_vars) => vars.append(&mut more_vars),
-            Err(error) => errors.join(error),
+    for (more_vars, more_errors) in dirs.into_iter() {
+        merge(&mut vars, more_vars);
+        errors.join(more_errors);
+    }
+
+    (vars, errors)
+}
+
+fn merge(vars: &mut Variables, more_vars: Variables) {
+    for (var, usages) in more_vars {
+        let entry = vars.entry(var).or_default();
+        for (usage, templates) in usages {
+            entry.entry(usage).or_default().extend(templates);
         }
     }
+}
 
-    if errors.is_empty() {
-        vars.sort_unstable();
-        vars.dedup();
-        Ok(vars)
-    } else {
-        Err(errors)
+/// List the subdirectories and files in a directory of the template tree.
+async fn list_dir(cfg: &Config, relative: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Error> {
+    let template_path = cfg.template_dir.join(relative);
+
+    info!("traversing {:?}", template_path);
+
+    let mut walker = read_dir(&template_path)
+        .await
+        .with_location(&template_path)?;
+
+    let mut dirs = vec![];
+    let mut files = vec![];
+
+    while let Some(entry) = walker.next_entry().await.with_location(&template_path)? {
+        let meta = entry.metadata().await.with_location(&entry.path())?;
+        let new_relative = relative.join(entry.file_name());
+
+        if cfg.skip(&new_relative) {
+            debug!("skipping {:?}", entry.path());
+            continue;
+        }
+
+        if meta.is_dir() {
+            dirs.push(new_relative);
+        } else if meta.is_file() {
+            files.push(new_relative);
+        }
     }
+
+    Ok((dirs, files))
 }
 
-async fn file(cfg: &Config, relative: PathBuf) -> Result<Vec<String>, Error> {
+async fn file(cfg: &Config, relative: PathBuf) -> Result<Variables, Error> {
     let template_path = cfg.template_dir.join(&relative);
 
     if template_path.extension() != Some(OsStr::new(TEMPLATE_EXTENSION)) {
-        return Ok(vec![]);
+        return Ok(Variables::new());
     }
 
     debug!("reading {:?}", template_path);
@@ -88,12 +263,18 @@ async fn file(cfg: &Config, relative: PathBuf) -> Result<Vec<String>, Error> {
         .await
         .with_location(&template_path)?;
 
-    let vars = parse_template(&file_str)
-        .with_location(&template_path)?
-        .list_variables()
-        .into_iter()
-        .map(|s| s.to_string())
+    let (_, body) = split_front_matter(&file_str).with_location(&template_path)?;
+
+    let variables = template_variables(body).with_location(&template_path)?;
+
+    let mut vars: Variables = variables
+        .iter()
+        .map(|var| (var.clone(), BTreeMap::new()))
         .collect();
+    for (var, usage) in infer_usage(body, &variables) {
+        let usages = vars.entry(var).or_default();
+        usages.entry(usage).or_default().insert(relative.clone());
+    }
 
     Ok(vars)
 }
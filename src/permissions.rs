@@ -0,0 +1,76 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::glob::GlobRules;
+use crate::Config;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs::read_to_string;
+
+/// Name of the file in the config dir which maps globs in the tree to modes.
+///
+/// ```toml
+/// [files]
+/// ".ssh/**" = "0600"
+///
+/// [dirs]
+/// ".gnupg" = "0700"
+/// ".gnupg/**" = "0700"
+/// ```
+pub const PERMISSIONS_FILE: &str = "permissions.toml";
+
+#[derive(Clone, Debug, Default)]
+pub struct PermissionRules {
+    files: GlobRules<u32>,
+    dirs: GlobRules<u32>,
+}
+
+#[derive(Deserialize)]
+struct RawRules {
+    #[serde(default)]
+    files: HashMap<String, String>,
+
+    #[serde(default)]
+    dirs: HashMap<String, String>,
+}
+
+impl PermissionRules {
+    /// The mode that a file at `relative` in the tree must have, if any.
+    pub fn file_mode(&self, relative: &Path) -> Option<u32> {
+        self.files.find(relative).copied()
+    }
+
+    /// The mode that a directory at `relative` in the tree must have, if any.
+    pub fn dir_mode(&self, relative: &Path) -> Option<u32> {
+        self.dirs.find(relative).copied()
+    }
+}
+
+/// Parse an octal mode such as `"0600"`.
+pub fn parse_mode(mode: &str) -> Result<u32, InnerError> {
+    u32::from_str_radix(mode, 8).map_err(|_| InnerError::Mode(mode.to_string()))
+}
+
+pub async fn read_permissions(cfg: &Config) -> Result<PermissionRules, Error> {
+    let path = cfg.config_dir.join(PERMISSIONS_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(PermissionRules::default());
+    };
+
+    let raw: RawRules = toml::de::from_str(&s).with_location(&path)?;
+
+    let parse = |rules: HashMap<String, String>| {
+        rules
+            .into_iter()
+            .map(|(glob, mode)| Ok((glob, parse_mode(&mode)?)))
+            .collect::<Result<GlobRules<_>, InnerError>>()
+            .with_location(&path)
+    };
+
+    Ok(PermissionRules {
+        files: parse(raw.files)?,
+        dirs: parse(raw.dirs)?,
+    })
+}
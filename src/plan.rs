@@ -0,0 +1,165 @@
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::lock::content_hash;
+use crate::managers::Manager;
+use crate::private::is_decrypted;
+use crate::state::read_sources;
+use crate::Config;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs::{canonicalize, read, read_link, symlink_metadata};
+
+/// Version of the JSON plan format.
+///
+/// Within a version, fields are only ever added. Removing, renaming or changing the meaning of a
+/// field, or adding a variant to [ActionKind], bumps the version.
+pub const PLAN_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum PlanFormat {
+    /// One line per file.
+    #[default]
+    Text,
+
+    /// A JSON document with a stable schema, see [PLAN_SCHEMA_VERSION].
+    Json,
+}
+
+/// What linking the build tree would do.
+#[derive(Serialize)]
+pub struct Plan {
+    pub schema_version: u32,
+    pub actions: Vec<PlannedAction>,
+}
+
+#[derive(Serialize)]
+pub struct PlannedAction {
+    pub action: ActionKind,
+
+    /// Where the file is linked.
+    pub path: PathBuf,
+
+    /// The file in the template tree that the file is made from, if known.
+    pub source: Option<String>,
+
+    /// Hash of the built file. Always `null` for private files.
+    pub hash: Option<String>,
+
+    /// Why the action is taken, for humans.
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    /// Nothing is at the path yet.
+    Create,
+
+    /// Something else is at the path, and will be replaced.
+    Replace,
+
+    /// The path is already linked to the build tree.
+    Keep,
+
+    /// The path is left alone.
+    Skip,
+}
+
+/// Work out what linking the tree built with `built`, usually in a scratch directory, would do to
+/// the links of `cfg`, without changing anything.
+pub async fn plan_tree(cfg: &Config, built: &Config) -> Result<Plan, Errors> {
+    let mut files = list_files(&built.build_dir).await?;
+    files.retain(|relative| !cfg.skip(relative));
+
+    let sources = read_sources(built).await?;
+
+    let mut actions = vec![];
+    for relative in files {
+        let source = sources.get(&*relative.to_string_lossy()).cloned();
+        let private = is_decrypted(&sources, &relative);
+        actions.push(plan_file(cfg, built, &relative, source, private).await?);
+    }
+
+    Ok(Plan {
+        schema_version: PLAN_SCHEMA_VERSION,
+        actions,
+    })
+}
+
+async fn plan_file(
+    cfg: &Config,
+    built: &Config,
+    relative: &Path,
+    source: Option<String>,
+    private: bool,
+) -> Result<PlannedAction, Error> {
+    let new_path = built.build_dir.join(relative);
+    let build_path = cfg.build_dir.join(relative);
+    let link_path = cfg.link_path(relative);
+
+    let content = read(&new_path).await.with_location(&new_path)?;
+    let hash = Some(content_hash(&content)).filter(|_| !private);
+
+    let (action, reason) = match symlink_metadata(&link_path).await {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            (ActionKind::Create, "nothing is there yet".into())
+        }
+        Err(e) => return Err(e.with_location(&link_path)),
+        Ok(meta) if meta.file_type().is_symlink() => {
+            let original = read_link(&link_path).await.with_location(&link_path)?;
+            let linked = match (
+                canonicalize(&link_path).await,
+                canonicalize(&build_path).await,
+            ) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            };
+
+            if let Some(manager) = Manager::detect(&original) {
+                (
+                    ActionKind::Skip,
+                    format!("managed by {manager} ({original:?})"),
+                )
+            } else if linked && read(&build_path).await.is_ok_and(|old| old == content) {
+                (ActionKind::Keep, "already linked".into())
+            } else if linked {
+                (ActionKind::Keep, "already linked, the file changes".into())
+            } else {
+                (ActionKind::Replace, format!("links to {original:?}"))
+            }
+        }
+        Ok(_) => {
+            let existing = read(&link_path).await.with_location(&link_path)?;
+            if existing == content {
+                (ActionKind::Replace, "a copy of the file is there".into())
+            } else {
+                (ActionKind::Replace, "a different file is there".into())
+            }
+        }
+    };
+
+    Ok(PlannedAction {
+        action,
+        path: link_path,
+        source,
+        hash,
+        reason,
+    })
+}
+
+pub fn print_plan(plan: &Plan, format: PlanFormat) {
+    match format {
+        PlanFormat::Json => {
+            let json = serde_json::to_string_pretty(plan).expect("serializing a plan");
+            println!("{json}");
+        }
+        PlanFormat::Text => {
+            for action in &plan.actions {
+                let kind = format!("{:?}", action.action).to_lowercase();
+                println!("{kind:>7} {} ({})", action.path.display(), action.reason);
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::error::{Error, ErrorLocation};
+use crate::process::run_bytes;
+use crate::state::Sources;
+use crate::Config;
+use std::ffi::OsStr;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Directory at the root of the template tree for files which are stored encrypted with age.
+///
+/// Files in it are decrypted into a directory of the same name in the build dir, which only the
+/// owner can access, and are linked relative to the root of the link dir, so that
+/// `private/.ssh/id_ed25519.age` ends up at `~/.ssh/id_ed25519`. Their content is never shown in
+/// diffs or exports.
+pub const PRIVATE_DIR: &str = "private";
+
+/// Extension of files encrypted with age.
+pub const ENCRYPTED_EXTENSION: &str = "age";
+
+/// Extension of files encrypted with gpg.
+pub const GPG_EXTENSION: &str = "gpg";
+
+/// Name of the file in the config dir containing the age identity used for decryption.
+const IDENTITY_FILE: &str = "age.key";
+
+/// Mode of the private directories in the build dir.
+pub const PRIVATE_DIR_MODE: u32 = 0o700;
+
+/// Mode of decrypted files in the build dir.
+pub const PRIVATE_FILE_MODE: u32 = 0o600;
+
+/// Whether the path, relative to the tree, is in the private directory.
+pub fn is_private(relative: &Path) -> bool {
+    relative.starts_with(PRIVATE_DIR)
+}
+
+/// Whether the path, relative to the tree, is an encrypted file. Encrypted files can be anywhere
+/// in the tree, and are decrypted like the files in the private directory, e.g. `.netrc.tpl.gpg`
+/// is decrypted, rendered and built into `.netrc`.
+pub fn is_encrypted(relative: &Path) -> bool {
+    [ENCRYPTED_EXTENSION, GPG_EXTENSION]
+        .iter()
+        .any(|extension| relative.extension() == Some(OsStr::new(extension)))
+}
+
+/// Whether the file at `relative` in the build tree holds decrypted content, because it's in the
+/// private directory or, according to `sources`, was built from an encrypted file. Such files are
+/// treated like the private directory, and never shown.
+pub fn is_decrypted(sources: &Sources, relative: &Path) -> bool {
+    is_private(relative)
+        || sources
+            .get(&*relative.to_string_lossy())
+            .is_some_and(|source| is_encrypted(Path::new(source)))
+}
+
+/// The path that a file in the tree is linked to, relative to the link dir.
+pub fn link_relative(relative: &Path) -> &Path {
+    relative.strip_prefix(PRIVATE_DIR).unwrap_or(relative)
+}
+
+/// How a file is encrypted, judging by its first bytes, as the extension of such files.
+///
+/// Both the armored and the binary formats of age and gpg are recognized.
+pub fn sniff_encryption(content: &[u8]) -> Option<&'static str> {
+    const AGE_HEADERS: [&[u8]; 2] = [
+        b"age-encryption.org/",
+        b"-----BEGIN AGE ENCRYPTED FILE-----",
+    ];
+    const GPG_HEADERS: [&[u8]; 1] = [b"-----BEGIN PGP MESSAGE-----"];
+
+    // the first packet of a binary gpg message holds the encrypted session key
+    const GPG_PACKET_TAGS: [u8; 6] = [0x84, 0x85, 0x8c, 0x8d, 0xc1, 0xc3];
+
+    if AGE_HEADERS.iter().any(|header| content.starts_with(header)) {
+        Some(ENCRYPTED_EXTENSION)
+    } else if GPG_HEADERS.iter().any(|header| content.starts_with(header))
+        || content.first().is_some_and(|b| GPG_PACKET_TAGS.contains(b))
+    {
+        Some(GPG_EXTENSION)
+    } else {
+        None
+    }
+}
+
+/// Decrypt a file from the template tree, with age and the identity in the config dir, or with
+/// gpg and its agent, depending on its extension.
+///
+/// The plaintext is returned as it is, it may well not be text, e.g. a keystore.
+pub async fn decrypt(cfg: &Config, path: &Path) -> Result<Vec<u8>, Error> {
+    let extension = path.extension().and_then(OsStr::to_str);
+    decrypt_as(cfg, path, extension.unwrap_or(ENCRYPTED_EXTENSION)).await
+}
+
+/// Decrypt a file with the tool that files with `extension` are decrypted with.
+pub async fn decrypt_as(cfg: &Config, path: &Path, extension: &str) -> Result<Vec<u8>, Error> {
+    let cmd = if extension == GPG_EXTENSION {
+        let mut cmd = Command::new("gpg");
+        cmd.args(["--quiet", "--batch", "--decrypt"]).arg(path);
+        cmd
+    } else {
+        let mut cmd = Command::new("age");
+        cmd.arg("--decrypt")
+            .arg("--identity")
+            .arg(cfg.config_dir.join(IDENTITY_FILE))
+            .arg(path);
+        cmd
+    };
+
+    run_bytes(cmd).await.with_location(path)
+}
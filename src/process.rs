@@ -0,0 +1,71 @@
+use std::io;
+use std::path::Path;
+use std::process::{Output, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Run a command to completion and return its stdout.
+///
+/// A non-zero exit status is reported as an error containing the stderr of the command.
+pub async fn run(mut cmd: Command) -> io::Result<String> {
+    debug!("running {cmd:?}");
+    let out = cmd.output().await?;
+    stdout(out)
+}
+
+/// Like [run], but return the stdout as it is, for commands whose output isn't necessarily text.
+pub async fn run_bytes(mut cmd: Command) -> io::Result<Vec<u8>> {
+    debug!("running {cmd:?}");
+    let out = cmd.output().await?;
+    raw_stdout(out)
+}
+
+/// Like [run], but also write `input` to the stdin of the command.
+pub async fn run_with_input(mut cmd: Command, input: &[u8]) -> io::Result<String> {
+    debug!("running {cmd:?}");
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input).await?;
+    }
+
+    let out = child.wait_with_output().await?;
+    stdout(out)
+}
+
+/// Quote a string for use as a single shell word.
+pub fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Build a command from a whitespace separated command line, replacing `%f` with `path`.
+pub fn command_line(line: &str, path: &Path) -> Command {
+    let mut words = line.split_whitespace().map(|word| {
+        if word.contains("%f") {
+            word.replace("%f", &path.to_string_lossy())
+        } else {
+            word.to_string()
+        }
+    });
+
+    let mut cmd = Command::new(words.next().unwrap_or_default());
+    cmd.args(words);
+    cmd
+}
+
+fn stdout(out: Output) -> io::Result<String> {
+    let stdout = raw_stdout(out)?;
+    Ok(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+fn raw_stdout(out: Output) -> io::Result<Vec<u8>> {
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+
+    Ok(out.stdout)
+}
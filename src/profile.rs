@@ -0,0 +1,156 @@
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::warnings::warning;
+use crate::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs::{read_dir, read_to_string, write};
+
+/// Directory at the root of the template tree containing fragments of shell environment files.
+///
+/// ```toml
+/// # .profile.d/cargo.toml
+/// order = 10
+/// path = ["~/.cargo/bin"]
+///
+/// [export]
+/// CARGO_HOME = "~/.cargo"
+/// ```
+///
+/// Fragments are ordered by `order`, then by name, and PATH entries of earlier fragments come
+/// first. Fragments are written to `.profile` unless they set `output`.
+pub const PROFILE_DIR: &str = ".profile.d";
+
+const DEFAULT_OUTPUT: &str = ".profile";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fragment {
+    #[serde(default)]
+    order: i64,
+
+    output: Option<String>,
+
+    #[serde(default)]
+    path: Vec<String>,
+
+    #[serde(default)]
+    export: BTreeMap<String, String>,
+}
+
+/// Generate the environment files from the fragments in the tree, if there are any.
+///
+/// Returns the paths of the generated files.
+pub async fn generate_profiles(cfg: &Config) -> Result<Vec<PathBuf>, Errors> {
+    let dir = cfg.template_dir.join(PROFILE_DIR);
+
+    let mut walker = match read_dir(&dir).await {
+        Ok(walker) => walker,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.with_location(&dir).into()),
+    };
+
+    let mut fragments = vec![];
+    while let Some(entry) = walker.next_entry().await.with_location(&dir)? {
+        let relative = Path::new(PROFILE_DIR).join(entry.file_name());
+        if entry.path().extension() != Some(OsStr::new("toml")) || !cfg.included(&relative) {
+            continue;
+        }
+
+        let path = entry.path();
+        let s = read_to_string(&path).await.with_location(&path)?;
+        let fragment: Fragment = toml::de::from_str(&s).with_location(&path)?;
+        fragments.push((path, fragment));
+    }
+
+    fragments.sort_by(|(a_path, a), (b_path, b)| (a.order, a_path).cmp(&(b.order, b_path)));
+
+    let mut outputs: BTreeMap<&str, Vec<&(PathBuf, Fragment)>> = BTreeMap::new();
+    for fragment in &fragments {
+        let output = fragment.1.output.as_deref().unwrap_or(DEFAULT_OUTPUT);
+        outputs.entry(output).or_default().push(fragment);
+    }
+
+    let mut paths = vec![];
+    let mut errors = vec![];
+    for (output, fragments) in outputs {
+        match write_profile(cfg, output, &fragments).await {
+            Ok(path) => paths.push(path),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(paths)
+    } else {
+        Err(errors.into())
+    }
+}
+
+async fn write_profile(
+    cfg: &Config,
+    output: &str,
+    fragments: &[&(PathBuf, Fragment)],
+) -> Result<PathBuf, Error> {
+    let mut out = format!("# generated from {PROFILE_DIR}\n");
+    let mut path_entries: Vec<(&str, &Path)> = vec![];
+
+    for (path, fragment) in fragments {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let _ = writeln!(out, "\n# {name}");
+
+        for (key, value) in &fragment.export {
+            let _ = writeln!(out, "export {key}=\"{}\"", quote(value));
+        }
+
+        for entry in &fragment.path {
+            match path_entries
+                .iter()
+                .find(|(existing, _)| *existing == entry.as_str())
+            {
+                Some((_, first)) => warning(
+                    path,
+                    format!("PATH entry {entry:?} is already added by {first:?}, ignoring it"),
+                ),
+                None => path_entries.push((entry.as_str(), path.as_path())),
+            }
+        }
+    }
+
+    if !path_entries.is_empty() {
+        out.push('\n');
+
+        // prepend in reverse, so that the first entry ends up first, and skip entries that are
+        // already there in case the file is sourced twice
+        for (entry, _) in path_entries.iter().rev() {
+            let entry = quote(entry);
+            let _ = writeln!(
+                out,
+                "case \":$PATH:\" in *\":{entry}:\"*) ;; *) PATH=\"{entry}:$PATH\" ;; esac"
+            );
+        }
+        out.push_str("export PATH\n");
+    }
+
+    let build_path = cfg.build_dir.join(output);
+    debug!("writing {:?}", build_path);
+    write(&build_path, out).await.with_location(&build_path)?;
+
+    Ok(build_path)
+}
+
+/// Escape a value for use in double quotes, expanding a leading `~` to `$HOME`.
+fn quote(value: &str) -> String {
+    let value = match value.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("$HOME{rest}"),
+        _ => value.to_string(),
+    };
+
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('`', "\\`")
+}
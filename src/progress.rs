@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Log progress at most this often, so that verbose runs over large trees stay readable.
+const LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts the files handled by a traversal, and logs how far along it is now and then instead of
+/// once per file.
+pub struct Progress {
+    what: &'static str,
+    count: AtomicUsize,
+    last_log: Mutex<Instant>,
+}
+
+impl Progress {
+    pub fn new(what: &'static str) -> Self {
+        Progress {
+            what,
+            count: AtomicUsize::new(0),
+            last_log: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Count one more file.
+    pub fn tick(&self) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let Ok(mut last_log) = self.last_log.try_lock() else {
+            return;
+        };
+
+        if last_log.elapsed() >= LOG_INTERVAL {
+            *last_log = Instant::now();
+            debug!("{} {count} files so far", self.what);
+        }
+    }
+
+    /// Log how many files were handled in total.
+    pub fn finish(self) {
+        info!("{} {} files", self.what, self.count.into_inner());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::builder::build_tree;
+use crate::diff::list_files;
+use crate::error::{ErrorLocation, Errors};
+use crate::linker::{link_tree, LinkMode};
+use crate::lock::content_hash;
+use crate::process::{run, run_with_input, sh_quote};
+use crate::state::{read_state, write_state};
+use crate::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs::{create_dir_all, read, remove_dir_all};
+use tokio::process::Command;
+
+/// Name of the directory in the state dir with the build trees and state of pushed hosts.
+const PUSH_DIR: &str = "push";
+
+/// Name of the file in the state dir of a pushed host which records what was last pushed.
+const MANIFEST_FILE: &str = "manifest.toml";
+
+/// Hash of each file in the home directory of a host as it was last pushed, by path.
+#[derive(Default, Deserialize, Serialize)]
+struct Manifest {
+    files: BTreeMap<String, String>,
+}
+
+/// How many files a push transferred and removed.
+#[derive(Clone, Copy, Debug)]
+pub struct Pushed {
+    pub changed: usize,
+    pub removed: usize,
+}
+
+/// Build the tree and copy it into the home directory of `destination`, an ssh destination such
+/// as `user@host`.
+///
+/// The tree is laid out as it would be in the home directory in a staging directory of its own,
+/// and compared to what was pushed last time. Only the files which changed since are handed to
+/// rsync, which in turn only transfers the parts of them which changed, and files which are no
+/// longer in the tree are removed from the host.
+pub async fn push(cfg: &Config, destination: &str) -> Result<Pushed, Errors> {
+    cfg.require_network("pushing")
+        .with_location(Path::new(destination))?;
+
+    let dir = cfg.state_dir.join(PUSH_DIR).join(destination);
+    let staging = dir.join("home");
+    let cfg = Config {
+        build_dir: dir.join("build"),
+        state_dir: dir.join("state"),
+        link_dir: staging.clone(),
+        link_mode: LinkMode::Copy,
+        backup_dir: None,
+        ..cfg.clone()
+    };
+
+    create_dir_all(&dir).await.with_location(&dir)?;
+
+    info!("building tree for {destination}");
+    build_tree(&cfg).await?;
+
+    // staged from scratch, so that files which are no longer in the tree aren't in it either
+    match remove_dir_all(&staging).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.with_location(&staging).into()),
+    }
+
+    info!("staging tree for {destination}");
+    link_tree(&cfg, cfg.local_fs()).await?;
+
+    let previous: Manifest = read_state(&cfg, MANIFEST_FILE).await?;
+    let mut manifest = Manifest::default();
+    let mut changed = vec![];
+
+    for relative in list_files(&staging).await? {
+        let path = staging.join(&relative);
+        let content = read(&path).await.with_location(&path)?;
+
+        let name = relative.to_string_lossy().into_owned();
+        let hash = content_hash(&content);
+        if previous.files.get(&name) != Some(&hash) {
+            changed.push(name.clone());
+        }
+        manifest.files.insert(name, hash);
+    }
+
+    let removed: Vec<&String> = previous
+        .files
+        .keys()
+        .filter(|name| !manifest.files.contains_key(*name))
+        .collect();
+
+    let pushed = Pushed {
+        changed: changed.len(),
+        removed: removed.len(),
+    };
+
+    let location = Path::new(destination);
+    if !changed.is_empty() {
+        info!("pushing {} changed files to {destination}", changed.len());
+
+        let mut cmd = Command::new("rsync");
+        cmd.args(["--archive", "--compress", "--files-from=-"])
+            .arg(format!("{}/", staging.display()))
+            .arg(format!("{destination}:"));
+
+        let list = changed.join("\n");
+        run_with_input(cmd, list.as_bytes())
+            .await
+            .with_location(location)?;
+    }
+
+    if !removed.is_empty() {
+        info!("removing {} files from {destination}", removed.len());
+
+        let mut cmd = Command::new("ssh");
+        cmd.arg(destination).args(["rm", "-f", "--"]);
+        for name in removed {
+            cmd.arg(sh_quote(name));
+        }
+        run(cmd).await.with_location(location)?;
+    }
+
+    write_state(&cfg, MANIFEST_FILE, &manifest).await?;
+    Ok(pushed)
+}
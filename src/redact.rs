@@ -0,0 +1,35 @@
+use std::sync::Mutex;
+
+/// What sensitive values are replaced with.
+const MASK: &str = "***";
+
+/// Values which must never be shown in logs, diffs or error messages, longest first.
+static SENSITIVE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Mark a value as sensitive, so that [redact] masks it.
+pub fn mark_sensitive(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+
+    let mut sensitive = SENSITIVE.lock().unwrap();
+    if !sensitive.iter().any(|v| v == value) {
+        sensitive.push(value.to_string());
+        sensitive.sort_unstable_by_key(|v| std::cmp::Reverse(v.len()));
+    }
+}
+
+/// Whether `value` was marked as sensitive.
+pub fn is_sensitive(value: &str) -> bool {
+    SENSITIVE.lock().unwrap().iter().any(|v| v == value)
+}
+
+/// Replace all sensitive values in `s` with a mask.
+pub fn redact(s: &str) -> String {
+    let sensitive = SENSITIVE.lock().unwrap();
+    let mut s = s.to_string();
+    for value in sensitive.iter() {
+        s = s.replace(value.as_str(), MASK);
+    }
+    s
+}
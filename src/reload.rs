@@ -0,0 +1,125 @@
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::glob::Glob;
+use crate::lock::hex;
+use crate::state::{read_fingerprints, read_trusted, write_fingerprints, write_trusted};
+use crate::trust::is_trusted;
+use crate::warnings::warning;
+use crate::Config;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs::{read, read_to_string};
+
+/// Name of the file in the config dir which maps globs in the tree to commands that are run after
+/// a sync changed any of the matching files.
+///
+/// ```toml
+/// ".config/sway/**" = "swaymsg reload"
+/// ".config/kitty/**" = "pkill -USR1 kitty"
+/// ".local/share/fonts/**" = "" # disable a built-in command
+/// ```
+const RELOAD_FILE: &str = "reload.toml";
+
+/// Reload commands which are run without being configured.
+const BUILTIN: &[(&str, &str)] = &[(".local/share/fonts/**", "fc-cache -f")];
+
+/// Read the reload commands, by glob, including the built-in ones which aren't disabled.
+pub async fn read_reloads(cfg: &Config) -> Result<BTreeMap<String, String>, Error> {
+    let mut commands: BTreeMap<String, String> = BUILTIN
+        .iter()
+        .map(|(glob, command)| (glob.to_string(), command.to_string()))
+        .collect();
+
+    let path = cfg.config_dir.join(RELOAD_FILE);
+    debug!("trying to read {:?}", path);
+    if let Ok(s) = read_to_string(&path).await {
+        let configured: BTreeMap<String, String> = toml::de::from_str(&s).with_location(&path)?;
+        commands.extend(configured);
+    }
+
+    commands.retain(|_, command| !command.trim().is_empty());
+    Ok(commands)
+}
+
+/// Run the reload commands whose files changed since they last ran.
+pub async fn run_reloads(cfg: &Config) -> Result<(), Errors> {
+    let commands = read_reloads(cfg).await?;
+    if commands.is_empty() {
+        return Ok(());
+    }
+
+    let path = cfg.config_dir.join(RELOAD_FILE);
+
+    let files = list_files(&cfg.build_dir).await?;
+    let mut fingerprints = read_fingerprints(cfg).await?;
+    let mut trusted = read_trusted(cfg).await?;
+    let mut errors = vec![];
+
+    for (glob, command) in &commands {
+        let pattern = Glob::new(glob);
+        let matching: Vec<&Path> = files
+            .iter()
+            .map(|relative| relative.as_path())
+            .filter(|relative| pattern.matches(relative))
+            .collect();
+
+        if matching.is_empty() {
+            continue;
+        }
+
+        let mut hasher = Sha256::new();
+        for relative in matching {
+            let path = cfg.build_dir.join(relative);
+            let content = read(&path).await.with_location(&path)?;
+
+            // lengths keep the boundaries between paths and contents unambiguous
+            let name = relative.as_os_str().as_encoded_bytes();
+            hasher.update((name.len() as u64).to_le_bytes());
+            hasher.update(name);
+            hasher.update((content.len() as u64).to_le_bytes());
+            hasher.update(&content);
+        }
+        let fingerprint = hex(&hasher.finalize());
+
+        if fingerprints.get(glob) == Some(&fingerprint) {
+            debug!("files matching {glob:?} are unchanged");
+            continue;
+        }
+
+        // the built-in commands are ours, and need no permission
+        let builtin = BUILTIN.contains(&(glob.as_str(), command.as_str()));
+        let name = format!("reload {glob:?}");
+        if !builtin {
+            match is_trusted(cfg, &mut trusted, &name, command).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    warning(&path, format!("not running {command:?}"));
+                    continue;
+                }
+                Err(e) => {
+                    errors.push(e.with_location(&path));
+                    continue;
+                }
+            }
+        }
+
+        info!("files matching {glob:?} changed, running {command:?}");
+        let hook = cfg.sandbox.command(cfg, command, &cfg.link_dir);
+        match cfg.sandbox.run(hook).await {
+            Ok(_) => {
+                fingerprints.insert(glob.clone(), fingerprint);
+            }
+            Err(e) => errors.push(e.with_location(&path)),
+        }
+    }
+
+    write_fingerprints(cfg, &fingerprints).await?;
+    write_trusted(cfg, &trusted).await?;
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::error::{Error, ErrorLocation};
+use crate::process::run;
+use crate::state::{read_state, write_state};
+use crate::Config;
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename};
+use tokio::process::Command;
+
+/// Name of the file in the state dir which tracks the version of the fetched source.
+const SOURCE_FILE: &str = "source.toml";
+
+/// The tarball the template tree was last fetched from.
+#[derive(Default, Deserialize, Serialize)]
+struct Source {
+    url: String,
+
+    /// ETag of the tarball, to only download it again when it changed.
+    etag: Option<String>,
+}
+
+/// Prefix of urls of trees in an S3 compatible bucket, e.g. `s3://bucket/dotfiles`.
+const S3_SCHEME: &str = "s3://";
+
+/// Fetch the template tree from a tarball at `url`, or from a prefix of an S3 compatible bucket,
+/// into the template dir, for machines which don't have git or can't reach a git server.
+///
+/// A tarball is only downloaded again if the server says it changed, and only the objects of a
+/// bucket which changed are downloaded. Offline, the tree fetched earlier is used as it is.
+pub async fn fetch_source(cfg: &Config, url: &str) -> Result<(), Error> {
+    let exists = cfg.template_dir.exists();
+    if cfg.offline && exists {
+        info!("offline, using the tree fetched earlier");
+        return Ok(());
+    }
+    cfg.require_network("fetching the tree")
+        .with_location(&cfg.template_dir)?;
+
+    if url.starts_with(S3_SCHEME) {
+        return fetch_bucket(cfg, url).await;
+    }
+
+    let source: Source = read_state(cfg, SOURCE_FILE).await?;
+    let etag = match (exists, source.url == url) {
+        (true, true) => source.etag,
+        _ => None,
+    };
+
+    let download_dir = cfg.template_dir.with_extension("download");
+    let tarball = download_dir.with_extension("tar");
+    let location = &cfg.template_dir;
+
+    let mut cmd = Command::new("curl");
+    cmd.args([
+        "--fail",
+        "--silent",
+        "--show-error",
+        "--location",
+        "--create-dirs",
+    ])
+    .args(["--write-out", "%{http_code}\n%header{etag}"])
+    .arg("--output")
+    .arg(&tarball);
+    if let Some(etag) = &etag {
+        cmd.arg("--header").arg(format!("If-None-Match: {etag}"));
+    }
+    cmd.arg(url);
+
+    info!("fetching {url}");
+    let out = run(cmd).await.with_location(location)?;
+    let mut lines = out.lines();
+    let status = lines.next().unwrap_or_default();
+    let new_etag = lines.next().filter(|etag| !etag.is_empty());
+
+    if status == "304" {
+        info!("{url} hasn't changed");
+        let _ = remove_file(&tarball).await;
+        return Ok(());
+    }
+
+    let extracted = extract(&tarball, &download_dir).await;
+    let _ = remove_file(&tarball).await;
+    extracted.with_location(location)?;
+
+    // tarballs of repositories usually have the tree in a single top-level directory
+    let root = single_subdirectory(&download_dir)
+        .await
+        .with_location(&download_dir)?
+        .unwrap_or_else(|| download_dir.clone());
+
+    match remove_dir_all(&cfg.template_dir).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.with_location(location)),
+    }
+    rename(&root, &cfg.template_dir)
+        .await
+        .with_location(location)?;
+    let _ = remove_dir_all(&download_dir).await;
+
+    let source = Source {
+        url: url.to_string(),
+        etag: new_etag.map(str::to_string),
+    };
+    write_state(cfg, SOURCE_FILE, &source).await
+}
+
+/// Mirror the objects under the prefix at `url` into the template dir with the aws cli, which
+/// reads the credentials, and the endpoint of buckets which aren't on AWS, from its usual
+/// configuration and environment variables such as `AWS_ENDPOINT_URL`.
+async fn fetch_bucket(cfg: &Config, url: &str) -> Result<(), Error> {
+    let location = &cfg.template_dir;
+    create_dir_all(location).await.with_location(location)?;
+
+    // the template dir is the cache, objects which didn't change aren't downloaded again
+    let mut cmd = Command::new("aws");
+    cmd.args(["s3", "sync", "--delete", "--only-show-errors"])
+        .arg(url)
+        .arg(location);
+
+    info!("syncing {url}");
+    run(cmd).await.with_location(location)?;
+
+    let source = Source {
+        url: url.to_string(),
+        etag: None,
+    };
+    write_state(cfg, SOURCE_FILE, &source).await
+}
+
+/// Unpack the tarball at `path` into a fresh directory at `dir`.
+async fn extract(path: &Path, dir: &Path) -> io::Result<()> {
+    match remove_dir_all(dir).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    create_dir_all(dir).await?;
+
+    let mut cmd = Command::new("tar");
+    cmd.arg("-C").arg(dir).arg("-xf").arg(path);
+    run(cmd).await?;
+    Ok(())
+}
+
+/// The only entry of `dir`, if it's a directory.
+async fn single_subdirectory(dir: &Path) -> io::Result<Option<PathBuf>> {
+    let mut walker = read_dir(dir).await?;
+
+    let Some(entry) = walker.next_entry().await? else {
+        return Ok(None);
+    };
+    if walker.next_entry().await?.is_some() || !entry.file_type().await?.is_dir() {
+        return Ok(None);
+    }
+
+    Ok(Some(entry.path()))
+}
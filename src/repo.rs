@@ -0,0 +1,102 @@
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::git;
+use crate::lock::FileChange;
+use crate::merge::pull;
+use crate::Config;
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum RepoAction {
+    /// Clone a git repository into the template dir.
+    Clone {
+        url: String,
+
+        /// Only fetch the latest commit.
+        #[arg(long)]
+        shallow: bool,
+    },
+
+    /// Pull the template tree, merging local edits into what was pulled.
+    Pull,
+
+    /// Commit every change to the template tree, and show which files changed.
+    Save {
+        /// The commit message, by default one listing the changed files.
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Push the commit afterwards.
+        #[arg(long)]
+        push: bool,
+    },
+}
+
+pub async fn repo(cfg: &Config, action: RepoAction) -> Result<(), Errors> {
+    match action {
+        RepoAction::Clone { url, shallow } => clone_repo(cfg, &url, shallow).await?,
+        RepoAction::Pull => pull(cfg).await?,
+        RepoAction::Save { message, push } => save(cfg, message.as_deref(), push).await?,
+    }
+
+    Ok(())
+}
+
+/// Clone the repository at `url` into the template dir, unless it already is a repository.
+pub async fn clone_repo(cfg: &Config, url: &str, shallow: bool) -> Result<(), Error> {
+    let dir = &cfg.template_dir;
+    if dir.join(".git").exists() {
+        info!("{dir:?} is already a git repository");
+        return Ok(());
+    }
+
+    cfg.require_network("cloning").with_location(dir)?;
+
+    info!("cloning {url}");
+    git::clone(url, dir, shallow).await.with_location(dir)
+}
+
+/// Stage and commit every change to the template tree, and push it if `push` is set.
+pub async fn save(cfg: &Config, message: Option<&str>, push: bool) -> Result<(), Error> {
+    let dir = &cfg.template_dir;
+
+    let staged = git::stage_all(dir).await.with_location(dir)?;
+    if staged.is_empty() {
+        println!("nothing to save");
+        return Ok(());
+    }
+
+    for (status, path) in &staged {
+        let change = match status.as_str() {
+            "A" => FileChange::Added,
+            "D" => FileChange::Removed,
+            _ => FileChange::Modified,
+        };
+        println!("{:<9} {path}", format!("{change}:"));
+    }
+
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => default_message(&staged),
+    };
+    git::commit(dir, &message).await.with_location(dir)?;
+    info!("committed {} files", staged.len());
+
+    if push {
+        cfg.require_network("pushing").with_location(dir)?;
+
+        info!("pushing {dir:?}");
+        git::push(dir).await.with_location(dir)?;
+    }
+
+    Ok(())
+}
+
+/// A commit message naming the `staged` files, or only how many there are if there are many.
+fn default_message(staged: &[(String, String)]) -> String {
+    if staged.len() > 3 {
+        return format!("Update {} files", staged.len());
+    }
+
+    let paths: Vec<&str> = staged.iter().map(|(_, path)| path.as_str()).collect();
+    format!("Update {}", paths.join(", "))
+}
@@ -0,0 +1,54 @@
+use crate::builder::TEMPLATE_EXTENSION;
+use crate::error::{Error, Errors};
+use crate::state::{read_sources, read_state, write_state};
+use crate::Config;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Name of the file in the state dir which lists the files that failed to sync last time.
+const RETRY_FILE: &str = "retry.toml";
+
+/// Paths in the template and build trees which failed to build or link.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Retry {
+    pub failed: Vec<PathBuf>,
+}
+
+pub async fn read_retry(cfg: &Config) -> Result<Retry, Error> {
+    read_state(cfg, RETRY_FILE).await
+}
+
+/// Record which files `errors` happened at, so that a later sync can retry only those. Errors
+/// which aren't about a file in the tree are left out.
+///
+/// Returns what was recorded.
+pub async fn write_retry(cfg: &Config, errors: &Errors) -> Result<Retry, Error> {
+    let sources = read_sources(cfg).await?;
+    let mut failed = vec![];
+
+    for location in errors.locations() {
+        if let Ok(relative) = location.strip_prefix(&cfg.template_dir) {
+            failed.push(relative.to_path_buf());
+
+            // the output has to be included too for the linker to pick it up
+            if relative.extension() == Some(OsStr::new(TEMPLATE_EXTENSION)) {
+                failed.push(relative.with_extension(""));
+            }
+        } else if let Ok(relative) = location.strip_prefix(&cfg.build_dir) {
+            failed.push(relative.to_path_buf());
+        } else if let Some(built) = sources
+            .keys()
+            .find(|built| cfg.link_path(Path::new(built)) == location)
+        {
+            failed.push(PathBuf::from(built));
+        }
+    }
+
+    failed.sort_unstable();
+    failed.dedup();
+
+    let retry = Retry { failed };
+    write_state(cfg, RETRY_FILE, &retry).await?;
+    Ok(retry)
+}
@@ -0,0 +1,60 @@
+use crate::backup::{backup_path, record_backup};
+use crate::error::{Error, ErrorLocation};
+use crate::mv::{find_target, unlink_target};
+use crate::state::{
+    read_journal, read_sources, unix_time, write_journal, write_sources, JournalEntry,
+};
+use crate::Config;
+use std::path::{absolute, Path};
+use tokio::fs::{copy, create_dir_all, remove_file};
+
+/// Remove the file in the tree which is linked to `target`, along with the built file and the link.
+///
+/// If `cfg.backup_dir` is set, the built file is first copied into it.
+pub async fn remove_target(cfg: &Config, target: &Path) -> Result<(), Error> {
+    let target = absolute(target).with_location(target)?;
+
+    let mut sources = read_sources(cfg).await?;
+    let (built, source) = find_target(cfg, &sources, &target)?;
+    let at = unix_time();
+
+    let backup = if let Some(backup_dir) = &cfg.backup_dir {
+        let built_path = cfg.build_dir.join(&built);
+        let backup_path = backup_path(cfg, backup_dir, &target);
+
+        if let Some(parent) = backup_path.parent() {
+            create_dir_all(parent).await.with_location(parent)?;
+        }
+
+        info!("keeping a copy of {built_path:?} in {backup_path:?}");
+        copy(&built_path, &backup_path)
+            .await
+            .with_location(&built_path)?;
+        record_backup(backup_dir, &backup_path, &target).await?;
+
+        Some(backup_path)
+    } else {
+        None
+    };
+
+    let source_path = cfg.template_dir.join(&source);
+    info!("removing {source_path:?}");
+    remove_file(&source_path)
+        .await
+        .with_location(&source_path)?;
+
+    unlink_target(cfg, &target, &built).await?;
+
+    sources.remove(&built);
+    write_sources(cfg, &sources).await?;
+
+    let mut journal = read_journal(cfg).await?;
+    journal.entries.push(JournalEntry {
+        at,
+        action: "rm".to_string(),
+        target,
+        source,
+        backup,
+    });
+    write_journal(cfg, &journal).await
+}
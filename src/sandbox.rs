@@ -0,0 +1,104 @@
+use crate::error::{Error, ErrorLocation};
+use crate::process::{command_line, run};
+use crate::Config;
+use serde::Deserialize;
+use std::env;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// Name of the file in the config dir which restricts how hooks are run.
+///
+/// ```toml
+/// # environment variables passed on to hooks, in addition to the defaults
+/// env = ["SWAYSOCK"]
+///
+/// # run hooks inside another command
+/// wrapper = "bwrap --ro-bind / / --dev /dev --unshare-net"
+///
+/// # kill hooks which run for longer than this many seconds
+/// timeout = 30
+/// ```
+const SANDBOX_FILE: &str = "sandbox.toml";
+
+/// Environment variables which hooks always get.
+const DEFAULT_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "TERM",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+    "DBUS_SESSION_BUS_ADDRESS",
+];
+
+/// Seconds a hook may run for, unless configured otherwise.
+const DEFAULT_TIMEOUT: u64 = 60;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sandbox {
+    #[serde(default)]
+    env: Vec<String>,
+    wrapper: Option<String>,
+    timeout: Option<u64>,
+}
+
+impl Sandbox {
+    /// Build a hook from a command line like [command_line] does, with only the allowed
+    /// environment variables, running in the template dir.
+    pub fn command(&self, cfg: &Config, line: &str, path: &Path) -> Command {
+        let line = match &self.wrapper {
+            Some(wrapper) => format!("{wrapper} {line}"),
+            None => line.to_string(),
+        };
+
+        let mut cmd = command_line(&line, path);
+        cmd.env_clear()
+            .current_dir(&cfg.template_dir)
+            .kill_on_drop(true);
+
+        let allowed = DEFAULT_ENV
+            .iter()
+            .copied()
+            .chain(self.env.iter().map(String::as_str));
+
+        for name in allowed {
+            if let Some(value) = env::var_os(name) {
+                cmd.env(name, value);
+            }
+        }
+
+        cmd
+    }
+
+    /// Run a hook built by [Sandbox::command] to completion, killing it if it runs for too long.
+    pub async fn run(&self, cmd: Command) -> io::Result<String> {
+        let seconds = self.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+        match timeout(Duration::from_secs(seconds), run(cmd)).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("killed after {seconds} seconds"),
+            )),
+        }
+    }
+}
+
+pub async fn read_sandbox(cfg: &Config) -> Result<Sandbox, Error> {
+    let path = cfg.config_dir.join(SANDBOX_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(Sandbox::default());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
@@ -0,0 +1,225 @@
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::private::{is_encrypted, is_private};
+use crate::process::{run, run_bytes, sh_quote};
+use crate::Config;
+use futures::future::join_all;
+use std::env::current_exe;
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::{read, read_to_string, rename, set_permissions, write};
+use tokio::process::Command;
+
+/// Prefixes of well known access token formats, and the minimum length of such a token.
+const TOKEN_PREFIXES: &[(&str, usize)] = &[
+    ("ghp_", 40),
+    ("gho_", 40),
+    ("ghu_", 40),
+    ("ghs_", 40),
+    ("ghr_", 40),
+    ("github_pat_", 60),
+    ("glpat-", 26),
+    ("xoxb-", 30),
+    ("xoxp-", 30),
+    ("AKIA", 20),
+    ("AIza", 39),
+    ("sk_live_", 30),
+];
+
+/// Words at least this long are checked for entropy.
+const MIN_ENTROPY_LENGTH: usize = 20;
+
+/// Shannon entropy, in bits per character, above which a word is assumed to be a secret.
+const MAX_ENTROPY: f64 = 4.0;
+
+/// Line in the pre-commit hooks we install, to tell them apart from the user's own.
+const HOOK_MARKER: &str = "# installed by dotfiles-manager scan-secrets --install-hook";
+
+/// Name an existing pre-commit hook is moved to, to run it from ours.
+const PREVIOUS_HOOK: &str = "pre-commit.previous";
+
+/// Scan the template tree, or the changes staged in its git repository, for things that look like
+/// secrets.
+pub async fn scan_secrets(cfg: &Config, staged: bool) -> Result<(), Errors> {
+    let mut files = if staged {
+        staged_files(cfg).await?
+    } else {
+        let mut files = list_files(&cfg.template_dir).await?;
+        files.retain(|relative| !cfg.skip(relative) && !relative.starts_with(".git"));
+        files
+    };
+
+    // these are committed encrypted, and ciphertext always looks like a secret
+    files.retain(|relative| !is_private(relative) && !is_encrypted(relative));
+
+    let scans = join_all(
+        files
+            .iter()
+            .map(|relative| scan_file(cfg, relative, staged)),
+    )
+    .await;
+
+    let mut errors = vec![];
+    let mut found = 0;
+
+    for (relative, result) in files.iter().zip(scans) {
+        match result {
+            Ok(findings) => {
+                for (line, kind) in findings {
+                    found += 1;
+                    println!("{}:{line}: possible {kind}", relative.display());
+                }
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if found > 0 {
+        errors.push(InnerError::SecretsFound(found).with_location(&cfg.template_dir));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+/// Install a git pre-commit hook in the template tree which scans staged changes.
+///
+/// A hook the user already had is kept, and run after the scan.
+pub async fn install_hook(cfg: &Config) -> Result<(), Error> {
+    let hooks = cfg.template_dir.join(".git/hooks");
+    let path = hooks.join("pre-commit");
+    let previous = hooks.join(PREVIOUS_HOOK);
+
+    match read_to_string(&path).await {
+        Ok(hook) if hook.contains(HOOK_MARKER) => {}
+        Ok(_) if previous.exists() => {
+            return Err(InnerError::HookExists(previous).with_location(&path));
+        }
+        Ok(_) => {
+            info!("moving the existing hook to {previous:?}");
+            rename(&path, &previous).await.with_location(&path)?;
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.with_location(&path)),
+    }
+
+    // the hook runs this very binary, whatever it's called and wherever it's installed
+    let exe = current_exe().with_location(&path)?;
+    let mut hook = format!(
+        "#!/bin/sh\n{HOOK_MARKER}\n{} --template-dir \"$(git rev-parse --show-toplevel)\" \
+         scan-secrets --staged || exit 1\n",
+        sh_quote(&exe.to_string_lossy()),
+    );
+    if previous.exists() {
+        hook.push_str(&format!(
+            "exec \"$(dirname \"$0\")/{PREVIOUS_HOOK}\" \"$@\"\n"
+        ));
+    }
+
+    write(&path, hook).await.with_location(&path)?;
+
+    set_permissions(&path, Permissions::from_mode(0o755))
+        .await
+        .with_location(&path)?;
+
+    info!("installed {:?}", path);
+    Ok(())
+}
+
+async fn staged_files(cfg: &Config) -> Result<Vec<PathBuf>, Error> {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(&cfg.template_dir);
+    cmd.args(["diff", "--cached", "--name-only", "--diff-filter=ACM", "-z"]);
+
+    let out = run(cmd).await.with_location(&cfg.template_dir)?;
+    Ok(out
+        .split('\0')
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+async fn scan_file(
+    cfg: &Config,
+    relative: &Path,
+    staged: bool,
+) -> Result<Vec<(usize, &'static str)>, Error> {
+    let path = cfg.template_dir.join(relative);
+
+    let content = if staged {
+        let mut cmd = Command::new("git");
+        cmd.arg("-C").arg(&cfg.template_dir);
+        cmd.arg("show").arg(format!(":{}", relative.display()));
+        run_bytes(cmd).await.with_location(&path)?
+    } else {
+        read(&path).await.with_location(&path)?
+    };
+
+    // binary files are not scanned
+    let Ok(content) = String::from_utf8(content) else {
+        return Ok(vec![]);
+    };
+
+    debug!("scanning {:?}", path);
+
+    Ok(content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| scan_line(line).map(|kind| (i + 1, kind)))
+        .collect())
+}
+
+fn scan_line(line: &str) -> Option<&'static str> {
+    if line.contains("-----BEGIN") && line.contains("PRIVATE KEY") {
+        return Some("private key");
+    }
+
+    let words = line
+        .split(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '/' | '=')));
+
+    for word in words {
+        let is_token = TOKEN_PREFIXES
+            .iter()
+            .any(|(prefix, len)| word.starts_with(prefix) && word.len() >= *len);
+
+        if is_token {
+            return Some("access token");
+        }
+
+        let has_digits = word.chars().any(|c| c.is_ascii_digit());
+        let has_letters = word.chars().any(|c| c.is_ascii_alphabetic());
+
+        if word.len() >= MIN_ENTROPY_LENGTH
+            && has_digits
+            && has_letters
+            && entropy(word) > MAX_ENTROPY
+        {
+            return Some("high-entropy string");
+        }
+    }
+
+    None
+}
+
+/// Shannon entropy of a string in bits per character.
+fn entropy(s: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in s.bytes() {
+        counts[b as usize] += 1;
+    }
+
+    let len = s.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
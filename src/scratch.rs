@@ -0,0 +1,75 @@
+use crate::builder::build_tree;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::inventory::Host;
+use crate::lock::copy_lock;
+use crate::private::PRIVATE_DIR_MODE;
+use crate::Config;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use tokio::fs::{remove_dir_all, DirBuilder};
+
+/// Name of the directory in the state dir where trees are built without being synced, so that
+/// clean only has to remove what is in it.
+pub const SCRATCH_DIR: &str = "scratch";
+
+/// Make a new directory only this run and user can build scratch trees in. Remove it with
+/// [remove_scratch] once done with them.
+pub async fn new_scratch(cfg: &Config) -> Result<PathBuf, Error> {
+    let parent = cfg.state_dir.join(SCRATCH_DIR);
+    DirBuilder::new()
+        .recursive(true)
+        .mode(PRIVATE_DIR_MODE)
+        .create(&parent)
+        .await
+        .with_location(&parent)?;
+
+    // a run which died without removing its directory may have had the same pid
+    for n in 0.. {
+        let dir = parent.join(format!("{}-{n}", process::id()));
+        match DirBuilder::new().mode(PRIVATE_DIR_MODE).create(&dir).await {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.with_location(&dir)),
+        }
+    }
+
+    unreachable!("ran out of scratch directory names")
+}
+
+/// Render the tree for `host` into the scratch directory `scratch`, returning the config used.
+pub async fn build_scratch(
+    cfg: &Config,
+    scratch: &Path,
+    host: Option<Host>,
+) -> Result<Config, Errors> {
+    let name = host
+        .as_ref()
+        .map(|host| host.name.as_str())
+        .unwrap_or("local");
+    let dir = scratch.join(name);
+    let scratch = Config {
+        build_dir: dir.join("build"),
+        state_dir: dir.join("state"),
+        host,
+        ..cfg.clone()
+    };
+
+    // the scratch state starts out empty, but a frozen build uses the lock of the last sync
+    if cfg.frozen {
+        copy_lock(cfg, &scratch).await?;
+    }
+
+    build_tree(&scratch).await?;
+
+    Ok(scratch)
+}
+
+/// Remove the scratch directory `scratch` and the trees built in it.
+pub async fn remove_scratch(scratch: &Path) -> Result<(), Error> {
+    match remove_dir_all(scratch).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.with_location(scratch)),
+    }
+}
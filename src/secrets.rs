@@ -0,0 +1,201 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::process::run;
+use crate::redact::mark_sensitive;
+use crate::Config;
+use blueprint::{Env, Value};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// Name of the file in the config dir which maps variables to secrets in a password manager.
+///
+/// ```toml
+/// # pass (the default), op or bw
+/// backend = "op"
+///
+/// [secrets]
+/// smtp_password = "op://Private/smtp/password"
+/// ```
+///
+/// Secrets are fetched when building a template which uses them, and are never written to the
+/// variables file, the lock file or the log. op and bw talk to a server, so with `--offline`
+/// templates which use their secrets fail to build.
+const SECRETS_FILE: &str = "secrets.toml";
+
+/// A password manager which secrets can be fetched from.
+pub trait SecretsBackend: Send + Sync {
+    /// Fetch the secret at `reference`, in whatever form the password manager names secrets.
+    fn fetch<'a>(
+        &'a self,
+        cfg: &'a Config,
+        reference: &'a str,
+    ) -> BoxFuture<'a, Result<String, InnerError>>;
+}
+
+/// The standard unix password manager, where the secret is the first line of an entry.
+pub struct Pass;
+
+/// The 1Password CLI, with secret references such as `op://vault/item/field`.
+pub struct OnePassword;
+
+/// The Bitwarden CLI, which needs to be unlocked with `BW_SESSION` set. The secret is the password
+/// of an item.
+pub struct Bitwarden;
+
+impl SecretsBackend for Pass {
+    fn fetch<'a>(
+        &'a self,
+        _cfg: &'a Config,
+        reference: &'a str,
+    ) -> BoxFuture<'a, Result<String, InnerError>> {
+        async move {
+            let mut cmd = Command::new("pass");
+            cmd.arg("show").arg(reference);
+
+            let out = run(cmd).await?;
+            Ok(out.lines().next().unwrap_or_default().to_string())
+        }
+        .boxed()
+    }
+}
+
+impl SecretsBackend for OnePassword {
+    fn fetch<'a>(
+        &'a self,
+        cfg: &'a Config,
+        reference: &'a str,
+    ) -> BoxFuture<'a, Result<String, InnerError>> {
+        async move {
+            cfg.require_network("fetching secrets")?;
+
+            let mut cmd = Command::new("op");
+            cmd.args(["read", "--no-newline"]).arg(reference);
+            Ok(run(cmd).await?)
+        }
+        .boxed()
+    }
+}
+
+impl SecretsBackend for Bitwarden {
+    fn fetch<'a>(
+        &'a self,
+        cfg: &'a Config,
+        reference: &'a str,
+    ) -> BoxFuture<'a, Result<String, InnerError>> {
+        async move {
+            cfg.require_network("fetching secrets")?;
+
+            let mut cmd = Command::new("bw");
+            cmd.args(["get", "password"]).arg(reference);
+
+            let out = run(cmd).await?;
+            Ok(out.trim_end_matches('\n').to_string())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    #[default]
+    Pass,
+    Op,
+    Bw,
+}
+
+impl Backend {
+    fn get(self) -> &'static dyn SecretsBackend {
+        match self {
+            Backend::Pass => &Pass,
+            Backend::Op => &OnePassword,
+            Backend::Bw => &Bitwarden,
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SecretsFile {
+    #[serde(default)]
+    backend: Backend,
+
+    /// Reference of each secret, by variable name.
+    #[serde(default)]
+    secrets: BTreeMap<String, String>,
+}
+
+/// The secrets templates may use, which are fetched the first time a template uses them.
+pub struct Secrets {
+    backend: &'static dyn SecretsBackend,
+
+    /// Reference of each secret, by variable name.
+    refs: BTreeMap<String, String>,
+
+    /// Secrets fetched so far, by variable name. Fetching holds the lock, so that the user is
+    /// asked to unlock the password manager at most once at a time.
+    fetched: Mutex<HashMap<String, String>>,
+}
+
+impl Secrets {
+    /// Whether any of `variables` is a secret.
+    pub fn any(&self, variables: &[String]) -> bool {
+        variables.iter().any(|v| self.refs.contains_key(v))
+    }
+
+    /// Add the secrets among `variables` to `env`, fetching those that haven't been yet.
+    pub async fn resolve(
+        &self,
+        cfg: &Config,
+        variables: &[String],
+        env: &mut Env,
+    ) -> Result<(), Error> {
+        let mut fetched = self.fetched.lock().await;
+
+        for name in variables {
+            let Some(reference) = self.refs.get(name) else {
+                continue;
+            };
+
+            if !fetched.contains_key(name) {
+                debug!("fetching secret {reference:?}");
+                let value = self
+                    .backend
+                    .fetch(cfg, reference)
+                    .await
+                    .with_location(Path::new(reference))?;
+
+                mark_sensitive(&value);
+                fetched.insert(name.clone(), value);
+            }
+
+            env.insert(name.clone(), Value::Str(fetched[name].clone()));
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn read_secrets(cfg: &Config) -> Result<Secrets, Error> {
+    let path = cfg.config_dir.join(SECRETS_FILE);
+
+    debug!("trying to read {:?}", path);
+    let file = match read_to_string(&path).await {
+        Ok(s) => toml::de::from_str(&s).with_location(&path)?,
+        Err(_) => {
+            debug!("failed to read {:?}", path);
+            SecretsFile::default()
+        }
+    };
+
+    Ok(Secrets {
+        backend: file.backend.get(),
+        refs: file.secrets,
+        fetched: Mutex::new(HashMap::new()),
+    })
+}
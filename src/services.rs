@@ -0,0 +1,187 @@
+use crate::block::{has_block, replace_block};
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::process::{run, run_with_input};
+use crate::state::{read_units, write_units, Units};
+use crate::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::ErrorKind;
+use std::path::Path;
+use tokio::fs::{create_dir_all, read_to_string, remove_file, write};
+use tokio::join;
+use tokio::process::Command;
+
+/// File at the root of the template tree declaring cron entries and systemd user units.
+///
+/// ```toml
+/// [cron]
+/// backup = "0 3 * * * restic backup --quiet ~"
+///
+/// [units."backup.service".Unit]
+/// Description = "Back up the home directory"
+///
+/// [units."backup.service".Service]
+/// Type = "oneshot"
+/// ExecStart = "restic backup --quiet %h"
+/// ```
+///
+/// Cron entries are kept in a managed block of the crontab, units are written to
+/// `~/.config/systemd/user`. Units which are removed from the file are removed from there too.
+pub const SERVICES_FILE: &str = ".services.toml";
+
+/// Where units are installed, relative to the link dir.
+const UNIT_DIR: &str = ".config/systemd/user";
+
+/// Name of the managed block in the crontab.
+const CRON_BLOCK: &str = "cron";
+
+/// Sections of a unit file, by name, and their keys.
+type Unit = BTreeMap<String, BTreeMap<String, toml::Value>>;
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Services {
+    #[serde(default)]
+    cron: BTreeMap<String, String>,
+
+    #[serde(default)]
+    units: BTreeMap<String, Unit>,
+}
+
+/// Install the cron entries and units declared in the tree, and remove the ones that no longer
+/// are.
+pub async fn install_services(cfg: &Config) -> Result<(), Errors> {
+    let path = cfg.template_dir.join(SERVICES_FILE);
+
+    debug!("trying to read {:?}", path);
+    let services: Services = match read_to_string(&path).await {
+        Ok(s) => toml::de::from_str(&s).with_location(&path)?,
+        Err(_) => Services::default(),
+    };
+
+    let (cron, units) = join!(
+        install_cron(&path, &services),
+        install_units(cfg, &path, &services)
+    );
+
+    let errors: Vec<Error> = [cron, units].into_iter().filter_map(|r| r.err()).collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+async fn install_cron(path: &Path, services: &Services) -> Result<(), Error> {
+    let mut list = Command::new("crontab");
+    list.arg("-l");
+
+    // crontab fails with "no crontab for <user>" when there is none yet, any other failure must
+    // not be mistaken for an empty crontab, or installing would wipe the user's entries
+    let current = match run(list).await {
+        Ok(current) => current,
+        Err(e) if e.to_string().starts_with("no crontab for") => String::new(),
+        Err(e) => return Err(e.with_location(path)),
+    };
+
+    // don't touch the crontab of machines which never had any entries
+    if services.cron.is_empty() && !has_block(&current, CRON_BLOCK) {
+        return Ok(());
+    }
+
+    let mut block = String::new();
+    for (name, entry) in &services.cron {
+        let _ = writeln!(block, "# {name}\n{entry}");
+    }
+
+    let updated = replace_block(&current, CRON_BLOCK, &block);
+    if updated == current {
+        debug!("crontab is up to date");
+        return Ok(());
+    }
+
+    info!("installing {} cron entries", services.cron.len());
+    let mut install = Command::new("crontab");
+    install.arg("-");
+    run_with_input(install, updated.as_bytes())
+        .await
+        .with_location(path)?;
+
+    Ok(())
+}
+
+async fn install_units(cfg: &Config, path: &Path, services: &Services) -> Result<(), Error> {
+    let dir = cfg.link_dir.join(UNIT_DIR);
+    let previous = read_units(cfg).await?;
+    let mut changed = false;
+
+    if !services.units.is_empty() {
+        create_dir_all(&dir).await.with_location(&dir)?;
+    }
+
+    for (name, unit) in &services.units {
+        let unit_path = dir.join(name);
+        let content = render_unit(unit).with_location(path)?;
+
+        if read_to_string(&unit_path).await.ok().as_ref() != Some(&content) {
+            info!("installing {:?}", unit_path);
+            write(&unit_path, content).await.with_location(&unit_path)?;
+            changed = true;
+        }
+    }
+
+    for name in &previous.installed {
+        if services.units.contains_key(name) {
+            continue;
+        }
+
+        let unit_path = dir.join(name);
+        match remove_file(&unit_path).await {
+            Ok(()) => info!("removed {:?}", unit_path),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.with_location(&unit_path)),
+        }
+        changed = true;
+    }
+
+    if changed {
+        let mut reload = Command::new("systemctl");
+        reload.args(["--user", "daemon-reload"]);
+        run(reload).await.with_location(&dir)?;
+    }
+
+    let units = Units {
+        installed: services.units.keys().cloned().collect(),
+    };
+    write_units(cfg, &units).await
+}
+
+fn render_unit(unit: &Unit) -> Result<String, InnerError> {
+    let mut out = String::new();
+
+    for (section, keys) in unit {
+        let _ = writeln!(out, "[{section}]");
+
+        for (key, value) in keys {
+            let values = match value {
+                toml::Value::Array(items) => items.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+
+            for value in values {
+                let value = match value {
+                    toml::Value::String(s) => s.clone(),
+                    toml::Value::Integer(i) => i.to_string(),
+                    toml::Value::Boolean(b) => b.to_string(),
+                    _ => return Err(InnerError::Type),
+                };
+                let _ = writeln!(out, "{key}={value}");
+            }
+        }
+
+        out.push('\n');
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,58 @@
+use crate::error::{Error, ErrorLocation};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read_to_string, write};
+
+/// Name of the file in the config dir with the settings of the tool itself, as written by
+/// `setup`.
+///
+/// ```toml
+/// template_dir = "/home/user/src/dotfiles"
+/// shared_dir = "/usr/share/site-dotfiles"
+/// source = "https://example.com/dotfiles.tar.gz"
+/// keep_generations = 5
+/// keep_backups_days = 30
+/// ```
+const SETTINGS_FILE: &str = "settings.toml";
+
+/// Settings which would otherwise have to be passed on every run. Arguments and environment
+/// variables take precedence.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Where the template tree is, if not in the config dir.
+    pub template_dir: Option<PathBuf>,
+
+    /// The tree shared by the users of the machine, if any.
+    pub shared_dir: Option<PathBuf>,
+
+    /// Url of a tarball or a bucket to fetch the tree from, if not a git repository.
+    pub source: Option<String>,
+
+    /// How many of the build trees of past syncs to keep, see [crate::clean::gc].
+    pub keep_generations: Option<usize>,
+
+    /// How many days to keep backups for, forever if unset.
+    pub keep_backups_days: Option<u64>,
+}
+
+pub async fn read_settings(config_dir: &Path) -> Result<Settings, Error> {
+    let path = config_dir.join(SETTINGS_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(Settings::default());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+pub async fn write_settings(config_dir: &Path, settings: &Settings) -> Result<(), Error> {
+    let path = config_dir.join(SETTINGS_FILE);
+
+    create_dir_all(config_dir).await.with_location(config_dir)?;
+
+    let s = toml::to_string(settings).with_location(&path)?;
+    write(&path, s).await.with_location(&path)
+}
@@ -0,0 +1,226 @@
+use crate::builder::{build_env, parse_flag, read_variables, VariablesFormat, FACTS};
+use crate::error::{ErrorLocation, Errors, InnerError};
+use crate::git;
+use crate::ignore::read_ignores;
+use crate::peeker::{read_docs, used_variables};
+use crate::private::sniff_encryption;
+use crate::settings::{read_settings, write_settings, Settings};
+use crate::usage::Usage;
+use crate::Config;
+use blueprint::Value;
+use std::env;
+use std::io::{self, stdin, IsTerminal};
+use std::path::PathBuf;
+use tokio::fs::{create_dir_all, read, write};
+use tokio::io::{stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Walk the user through setting up the tool: find or create the template tree, show the
+/// detected facts, and ask for the variables the templates use which aren't set yet.
+///
+/// Returns the configuration with the chosen template tree, for the initial dry run.
+pub async fn setup(cfg: Config) -> Result<Config, Errors> {
+    if !stdin().is_terminal() {
+        return Err(InnerError::NotATerminal
+            .with_location(&cfg.config_dir)
+            .into());
+    }
+
+    let cfg = setup_template_dir(cfg).await?;
+
+    println!();
+    println!("detected facts:");
+    let env = build_env(&cfg).await?;
+    for (key, value) in env.iter().filter(|(key, _)| FACTS.contains(&key.as_str())) {
+        match value {
+            Value::Str(s) => println!("  {key}: {s}"),
+            Value::Bool(b) => println!("  {key}: {b}"),
+            other => println!("  {key}: {other:?}"),
+        }
+    }
+
+    println!();
+    setup_variables(&cfg).await?;
+
+    Ok(cfg)
+}
+
+/// Ask where the template tree is, cloning or creating it if needed, and remember the answer in
+/// the settings file.
+async fn setup_template_dir(cfg: Config) -> Result<Config, Errors> {
+    let question = format!(
+        "Where is your dotfiles repository? A path or a git url [{}]: ",
+        cfg.template_dir.display(),
+    );
+    let answer = ask(&question).await.with_location(&cfg.config_dir)?;
+
+    let template_dir = if answer.is_empty() {
+        cfg.template_dir.clone()
+    } else if is_url(&answer) {
+        if cfg.template_dir.join(".git").exists() {
+            println!("{:?} is already a git repository", cfg.template_dir);
+        } else {
+            cfg.require_network("cloning")
+                .with_location(&cfg.template_dir)?;
+
+            info!("cloning {answer}");
+            git::clone(&answer, &cfg.template_dir, false)
+                .await
+                .with_location(&cfg.template_dir)?;
+        }
+        cfg.template_dir.clone()
+    } else {
+        match answer.strip_prefix("~/") {
+            Some(rest) => PathBuf::from(env::var("HOME").expect("$HOME")).join(rest),
+            None => PathBuf::from(answer),
+        }
+    };
+
+    let is_empty = match std::fs::read_dir(&template_dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+    if is_empty {
+        let question = format!("{template_dir:?} is empty, create a new repository there? [Y/n] ");
+        let answer = ask(&question).await.with_location(&template_dir)?;
+        if matches!(answer.as_str(), "" | "y" | "Y" | "yes") {
+            create_dir_all(&template_dir)
+                .await
+                .with_location(&template_dir)?;
+            git::init(&template_dir)
+                .await
+                .with_location(&template_dir)?;
+        }
+    }
+
+    if template_dir != cfg.template_dir {
+        let template_dir = template_dir
+            .canonicalize()
+            .unwrap_or_else(|_| template_dir.clone());
+        let settings = Settings {
+            template_dir: Some(template_dir),
+            shared_dir: cfg.shared_dir.clone(),
+            source: None,
+            ..read_settings(&cfg.config_dir).await?
+        };
+        write_settings(&cfg.config_dir, &settings).await?;
+    }
+
+    Ok(Config {
+        ignore: read_ignores(&template_dir).await?,
+        template_dir,
+        ..cfg
+    })
+}
+
+/// Ask for the variables which the templates use but which aren't set, and add the answers to
+/// the variables file. Unanswered variables are left unset.
+async fn setup_variables(cfg: &Config) -> Result<(), Errors> {
+    let (vars, errors) = used_variables(cfg).await;
+    errors.log();
+
+    let docs = read_docs(cfg).await?;
+    let values = read_variables(cfg).await?;
+
+    let mut answers = toml::Table::new();
+    for (var, usages) in vars {
+        let is_flag = cfg.flags.iter().any(|flag| parse_flag(flag).0 == var);
+        if FACTS.contains(&var.as_str()) || is_flag || values.contains_key(&var) {
+            continue;
+        }
+
+        if let Some(doc) = docs.get(&var) {
+            if let Some(description) = &doc.description {
+                println!("{var}: {description}");
+            }
+            if let Some(example) = &doc.example {
+                println!("    example: {example}");
+            }
+        }
+
+        let location = &cfg.variables_path;
+        if usages == [Usage::Bool] {
+            let answer = ask(&format!("{var}? [y/N] "))
+                .await
+                .with_location(location)?;
+            let value = matches!(answer.as_str(), "y" | "Y" | "yes");
+            answers.insert(var, toml::Value::Boolean(value));
+        } else {
+            let answer = ask(&format!("{var} (leave empty to skip): "))
+                .await
+                .with_location(location)?;
+            if !answer.is_empty() {
+                answers.insert(var, toml::Value::String(answer));
+            }
+        }
+    }
+
+    if answers.is_empty() {
+        return Ok(());
+    }
+
+    let format = VariablesFormat::of(&cfg.variables_path);
+    let new = format
+        .to_string(&answers)
+        .with_location(&cfg.variables_path)?;
+
+    // the user has to add the answers to an encrypted file themselves
+    let existing = read(&cfg.variables_path).await.unwrap_or_default();
+    if sniff_encryption(&existing).is_some() {
+        println!("{:?} is encrypted, add these to it:", cfg.variables_path);
+        println!("{new}");
+        return Ok(());
+    }
+
+    if let Some(parent) = cfg.variables_path.parent() {
+        create_dir_all(parent).await.with_location(parent)?;
+    }
+
+    let content = match format {
+        _ if existing.is_empty() => new,
+
+        // the answers go before the existing content, as they would end up in its last table
+        // if they came after it
+        VariablesFormat::Toml => format!("{new}\n{}", String::from_utf8_lossy(&existing)),
+
+        // neither can simply be appended to, so the whole file is written again
+        VariablesFormat::Json | VariablesFormat::Yaml => {
+            let existing = String::from_utf8_lossy(&existing);
+            let mut all: toml::Table = format
+                .parse(&existing)
+                .with_location(&cfg.variables_path)?
+                .into_iter()
+                .collect();
+            all.extend(answers.clone());
+            format.to_string(&all).with_location(&cfg.variables_path)?
+        }
+    };
+    write(&cfg.variables_path, content)
+        .await
+        .with_location(&cfg.variables_path)?;
+
+    println!(
+        "wrote {} variables to {:?}",
+        answers.len(),
+        cfg.variables_path
+    );
+    Ok(())
+}
+
+/// Whether the location of a repository is a url rather than a path.
+fn is_url(location: &str) -> bool {
+    location.contains("://") || location.starts_with("git@")
+}
+
+/// Ask the user a question, and return the trimmed answer.
+pub async fn ask(question: &str) -> io::Result<String> {
+    let mut out = stdout();
+    out.write_all(question.as_bytes()).await?;
+    out.flush().await?;
+
+    let mut answer = String::new();
+    BufReader::new(tokio::io::stdin())
+        .read_line(&mut answer)
+        .await?;
+
+    Ok(answer.trim().to_string())
+}
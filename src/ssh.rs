@@ -0,0 +1,121 @@
+use crate::builder::read_variables;
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::inventory::read_inventory;
+use crate::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::{set_permissions, write, DirBuilder};
+
+/// Table in the variables file which the SSH config is generated from.
+///
+/// ```toml
+/// [ssh.hosts.laptop]
+/// hostname = "laptop.lan"
+/// user = "vidde"
+/// port = 2222
+/// local_forward = ["8080 localhost:80"]
+/// ```
+///
+/// Options are written as ssh_config keywords, so `local_forward` becomes `LocalForward`, and
+/// arrays repeat the keyword. Hosts in the inventory which aren't listed get a block with just
+/// their hostname.
+const SSH_TABLE: &str = "ssh";
+
+/// Where the generated config is placed in the tree.
+const SSH_CONFIG: &str = ".ssh/config";
+
+#[derive(Deserialize)]
+struct SshVariables {
+    #[serde(default)]
+    hosts: BTreeMap<String, BTreeMap<String, toml::Value>>,
+}
+
+/// Generate `~/.ssh/config` in the build tree, if the variables file has an `[ssh]` table.
+///
+/// Returns the path of the generated file.
+pub async fn generate_ssh_config(cfg: &Config) -> Result<Option<PathBuf>, Error> {
+    let Some(table) = read_variables(cfg).await?.remove(SSH_TABLE) else {
+        return Ok(None);
+    };
+
+    let relative = Path::new(SSH_CONFIG);
+    if cfg.skip(relative) {
+        return Ok(None);
+    }
+
+    let ssh: SshVariables = table.try_into().with_location(&cfg.variables_path)?;
+    let mut hosts = ssh.hosts;
+
+    if cfg.inventory_path.exists() {
+        for (name, host) in read_inventory(cfg).await? {
+            hosts.entry(name).or_insert_with(|| {
+                BTreeMap::from([("hostname".to_string(), toml::Value::String(host.hostname()))])
+            });
+        }
+    }
+
+    let mut out = String::from("# generated from the [ssh] table of the variables file\n\n");
+    for (name, options) in &hosts {
+        let _ = writeln!(out, "Host {name}");
+
+        for (key, value) in options {
+            let values = match value {
+                toml::Value::Array(items) => items.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+
+            for value in values {
+                let value = to_option(value).with_location(&cfg.variables_path)?;
+                let _ = writeln!(out, "    {} {value}", keyword(key));
+            }
+        }
+
+        out.push('\n');
+    }
+
+    let path = cfg.build_dir.join(relative);
+    let dir = cfg.build_dir.join(".ssh");
+
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .await
+        .with_location(&dir)?;
+
+    debug!("writing {:?}", path);
+    write(&path, out).await.with_location(&path)?;
+
+    set_permissions(&path, Permissions::from_mode(0o600))
+        .await
+        .with_location(&path)?;
+
+    Ok(Some(path))
+}
+
+/// Turn a snake_case key into an ssh_config keyword.
+fn keyword(key: &str) -> String {
+    key.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn to_option(value: &toml::Value) -> Result<String, InnerError> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Boolean(true) => Ok("yes".into()),
+        toml::Value::Boolean(false) => Ok("no".into()),
+        _ => Err(InnerError::Type),
+    }
+}
@@ -0,0 +1,182 @@
+use crate::error::{Error, ErrorLocation};
+use crate::lock::FileChange;
+use crate::Config;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs::{create_dir_all, read_to_string, write};
+
+/// Name of the file in the state dir which tracks the outputs of multi-output templates.
+const OUTPUTS_FILE: &str = "outputs.toml";
+
+/// Name of the file in the state dir which tracks the files that reload commands depend on.
+const FINGERPRINTS_FILE: &str = "fingerprints.toml";
+
+/// Name of the file in the state dir which tracks the systemd units installed from the tree.
+const UNITS_FILE: &str = "units.toml";
+
+/// Name of the file in the state dir which tracks where each file in the build tree came from.
+const SOURCES_FILE: &str = "sources.toml";
+
+/// Name of the file in the state dir which tracks files linked somewhere else than usual.
+const LINK_TARGETS_FILE: &str = "link_targets.toml";
+
+/// Name of the file in the state dir which tracks the hooks the user has allowed to run.
+const TRUSTED_FILE: &str = "trusted.toml";
+
+/// Name of the file in the state dir which records the changes commands made to the tree.
+pub const JOURNAL_FILE: &str = "journal.toml";
+
+/// Files in the build tree produced by each multi-output template, by template path.
+pub type Outputs = BTreeMap<String, Vec<PathBuf>>;
+
+/// Hash of the files matched by each reload glob, when its command last ran.
+pub type Fingerprints = BTreeMap<String, String>;
+
+/// What each file in the build tree was made from, by path.
+pub type Sources = BTreeMap<String, String>;
+
+/// Where each file in the build tree which sets a target in its front matter is linked, by path.
+pub type LinkTargets = BTreeMap<String, PathBuf>;
+
+/// Hash of each hook the user has allowed to run, by name.
+pub type Trusted = BTreeMap<String, String>;
+
+/// Names of the systemd user units installed from the tree.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Units {
+    pub installed: Vec<String>,
+}
+
+pub async fn read_outputs(cfg: &Config) -> Result<Outputs, Error> {
+    read_state(cfg, OUTPUTS_FILE).await
+}
+
+pub async fn write_outputs(cfg: &Config, outputs: &Outputs) -> Result<(), Error> {
+    write_state(cfg, OUTPUTS_FILE, outputs).await
+}
+
+pub async fn read_fingerprints(cfg: &Config) -> Result<Fingerprints, Error> {
+    read_state(cfg, FINGERPRINTS_FILE).await
+}
+
+pub async fn write_fingerprints(cfg: &Config, fingerprints: &Fingerprints) -> Result<(), Error> {
+    write_state(cfg, FINGERPRINTS_FILE, fingerprints).await
+}
+
+pub async fn read_sources(cfg: &Config) -> Result<Sources, Error> {
+    read_state(cfg, SOURCES_FILE).await
+}
+
+pub async fn write_sources(cfg: &Config, sources: &Sources) -> Result<(), Error> {
+    write_state(cfg, SOURCES_FILE, sources).await
+}
+
+pub async fn read_link_targets(cfg: &Config) -> Result<LinkTargets, Error> {
+    read_state(cfg, LINK_TARGETS_FILE).await
+}
+
+pub async fn write_link_targets(cfg: &Config, targets: &LinkTargets) -> Result<(), Error> {
+    write_state(cfg, LINK_TARGETS_FILE, targets).await
+}
+
+pub async fn read_trusted(cfg: &Config) -> Result<Trusted, Error> {
+    read_state(cfg, TRUSTED_FILE).await
+}
+
+pub async fn write_trusted(cfg: &Config, trusted: &Trusted) -> Result<(), Error> {
+    write_state(cfg, TRUSTED_FILE, trusted).await
+}
+
+/// Changes made to the tree by commands, oldest first.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Journal {
+    pub entries: Vec<JournalEntry>,
+
+    /// The successful syncs, oldest first.
+    #[serde(default)]
+    pub syncs: Vec<SyncEntry>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct JournalEntry {
+    /// When the change was made, in seconds since the epoch.
+    pub at: u64,
+
+    /// What was done, e.g. `rm`.
+    pub action: String,
+
+    /// The path in the link dir which was affected.
+    pub target: PathBuf,
+
+    /// The path in the template tree which was affected.
+    pub source: String,
+
+    /// Where a copy of the built file was kept, if anywhere.
+    pub backup: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SyncEntry {
+    /// When the sync finished, in seconds since the epoch, which also identifies it.
+    pub at: u64,
+
+    /// The commit of the template tree, if it is a git repository.
+    pub commit: Option<String>,
+
+    /// The files in the template tree which changed since the previous sync, by path.
+    #[serde(default)]
+    pub files: BTreeMap<String, FileChange>,
+}
+
+pub async fn read_journal(cfg: &Config) -> Result<Journal, Error> {
+    read_state(cfg, JOURNAL_FILE).await
+}
+
+pub async fn write_journal(cfg: &Config, journal: &Journal) -> Result<(), Error> {
+    write_state(cfg, JOURNAL_FILE, journal).await
+}
+
+pub async fn read_units(cfg: &Config) -> Result<Units, Error> {
+    read_state(cfg, UNITS_FILE).await
+}
+
+pub async fn write_units(cfg: &Config, units: &Units) -> Result<(), Error> {
+    write_state(cfg, UNITS_FILE, units).await
+}
+
+/// The current time, in seconds since the epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub async fn read_state<T: DeserializeOwned + Default>(
+    cfg: &Config,
+    name: &str,
+) -> Result<T, Error> {
+    let path = cfg.state_dir.join(name);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(T::default());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+pub async fn write_state<T: Serialize>(cfg: &Config, name: &str, state: &T) -> Result<(), Error> {
+    let path = cfg.state_dir.join(name);
+
+    create_dir_all(&cfg.state_dir)
+        .await
+        .with_location(&cfg.state_dir)?;
+
+    let s = toml::to_string(state).with_location(&path)?;
+    write(&path, s).await.with_location(&path)
+}
@@ -0,0 +1,70 @@
+use crate::builder::{read_variables, TEMPLATE_EXTENSION};
+use crate::diff::list_files;
+use crate::error::{ErrorLocation, Errors};
+use crate::lint::read_template;
+use crate::tplcache::save_template_cache;
+use crate::Config;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use tokio::fs::metadata;
+
+/// How many of the most used variables to show.
+const TOP_VARIABLES: usize = 10;
+
+/// Print a summary of the template tree and the last build.
+pub async fn print_stats(cfg: &Config) -> Result<(), Errors> {
+    let mut files = list_files(&cfg.template_dir).await?;
+    files.retain(|relative| !cfg.skip(relative) && !relative.starts_with(".git"));
+
+    let is_template =
+        |relative: &Path| relative.extension() == Some(OsStr::new(TEMPLATE_EXTENSION));
+    let templates: Vec<_> = files
+        .iter()
+        .filter(|relative| is_template(relative))
+        .collect();
+
+    println!("templates: {}", templates.len());
+    println!("plain files: {}", files.len() - templates.len());
+    println!("variables: {}", read_variables(cfg).await?.len());
+
+    let mut per_dir: BTreeMap<String, usize> = BTreeMap::new();
+    for relative in &files {
+        let dir = match relative.parent().and_then(|parent| parent.iter().next()) {
+            Some(dir) => dir.to_string_lossy().into_owned(),
+            None => ".".to_string(),
+        };
+        *per_dir.entry(dir).or_default() += 1;
+    }
+
+    println!("files per directory:");
+    for (dir, count) in &per_dir {
+        println!("  {dir}: {count}");
+    }
+
+    let mut size = 0;
+    for relative in list_files(&cfg.build_dir).await? {
+        let path = cfg.build_dir.join(relative);
+        size += metadata(&path).await.with_location(&path)?.len();
+    }
+    println!("rendered size: {size} bytes");
+
+    let mut uses: BTreeMap<String, usize> = BTreeMap::new();
+    for relative in templates {
+        for variable in read_template(cfg, relative).await?.variables {
+            *uses.entry(variable).or_default() += 1;
+        }
+    }
+
+    let mut uses: Vec<_> = uses.into_iter().collect();
+    uses.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then(a_name.cmp(b_name)));
+
+    println!("most used variables:");
+    for (variable, count) in uses.into_iter().take(TOP_VARIABLES) {
+        println!("  {variable}: {count}");
+    }
+
+    save_template_cache(cfg).await?;
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use crate::process::{run, run_with_input};
+use futures::future::{ready, BoxFuture};
+use futures::{FutureExt, TryFutureExt};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::DirBuilder;
+use tokio::process::Command;
+
+/// A place that the linker deploys the built tree into.
+///
+/// The linker owns the traversal and the conflict handling, a target only has to know how to
+/// perform the individual filesystem operations.
+pub trait Target: Send + Sync {
+    /// Create a single directory, with `mode` if set. An existing directory may either be reported
+    /// as [io::ErrorKind::AlreadyExists] or as success.
+    fn create_dir<'a>(&'a self, path: &'a Path, mode: Option<u32>)
+        -> BoxFuture<'a, io::Result<()>>;
+
+    /// Check whether there is a symlink at `path`.
+    fn is_symlink<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>>;
+
+    /// Get the target of the symlink at `path`, or `None` if it isn't a symlink.
+    fn read_link<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<PathBuf>>>;
+
+    /// Remove a file. Should fail with [io::ErrorKind::NotFound] if there is nothing to remove.
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Create a symlink at `link` pointing to `original`.
+    fn symlink<'a>(&'a self, original: &'a Path, link: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Copy the local file at `from` to `to`.
+    fn copy_file<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Replace whatever is at `link` with a symlink pointing to `original`.
+    fn replace_with_symlink<'a>(
+        &'a self,
+        original: &'a Path,
+        link: &'a Path,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            match self.remove_file(link).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            self.symlink(original, link).await
+        }
+        .boxed()
+    }
+
+    /// Replace whatever is at `to` with a copy of the local file at `from`.
+    fn replace_with_copy<'a>(
+        &'a self,
+        from: &'a Path,
+        to: &'a Path,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            match self.remove_file(to).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            self.copy_file(from, to).await
+        }
+        .boxed()
+    }
+
+    /// Move the regular file at `path` to the local path `to`, creating its parent directories.
+    /// Returns `false` without doing anything if there is no regular file at `path`.
+    fn back_up<'a>(&'a self, path: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<bool>>;
+
+    /// Get the extended POSIX ACL entries of a file, if it exists and has any.
+    fn get_acl<'a>(&'a self, _path: &'a Path) -> BoxFuture<'a, io::Result<Option<String>>> {
+        ready(Ok(None)).boxed()
+    }
+
+    /// Set ACL entries previously returned by [Target::get_acl].
+    fn set_acl<'a>(&'a self, _path: &'a Path, _acl: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        ready(Ok(())).boxed()
+    }
+
+    /// Restore the default SELinux security context of a file.
+    fn restore_context<'a>(&'a self, _path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        ready(Ok(())).boxed()
+    }
+}
+
+/// The local filesystem.
+pub struct LocalFs;
+
+impl Target for LocalFs {
+    fn create_dir<'a>(
+        &'a self,
+        path: &'a Path,
+        mode: Option<u32>,
+    ) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let mut builder = DirBuilder::new();
+            if let Some(mode) = mode {
+                builder.mode(mode);
+            }
+            builder.create(path).await
+        }
+        .boxed()
+    }
+
+    fn is_symlink<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        async move {
+            match tokio::fs::symlink_metadata(path).await {
+                Ok(meta) => Ok(meta.file_type().is_symlink()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e),
+            }
+        }
+        .boxed()
+    }
+
+    fn read_link<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<PathBuf>>> {
+        async move {
+            match tokio::fs::read_link(path).await {
+                Ok(original) => Ok(Some(original)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+        .boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        tokio::fs::remove_file(path).boxed()
+    }
+
+    fn symlink<'a>(&'a self, original: &'a Path, link: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        tokio::fs::symlink(original, link).boxed()
+    }
+
+    fn copy_file<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        tokio::fs::copy(from, to).map_ok(|_| ()).boxed()
+    }
+
+    fn back_up<'a>(&'a self, path: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        async move {
+            match tokio::fs::symlink_metadata(path).await {
+                Ok(meta) if meta.is_file() => {}
+                Ok(_) => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e),
+            }
+
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            // the backup dir may be on another file system
+            if tokio::fs::rename(path, to).await.is_err() {
+                tokio::fs::copy(path, to).await?;
+                tokio::fs::remove_file(path).await?;
+            }
+
+            Ok(true)
+        }
+        .boxed()
+    }
+
+    fn get_acl<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Option<String>>> {
+        async move {
+            match tokio::fs::symlink_metadata(path).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            }
+
+            let mut cmd = Command::new("getfacl");
+            cmd.args(["--absolute-names", "--omit-header", "--skip-base"])
+                .arg(path);
+            let acl = run(cmd).await?;
+
+            Ok(Some(acl).filter(|acl| !acl.trim().is_empty()))
+        }
+        .boxed()
+    }
+
+    fn set_acl<'a>(&'a self, path: &'a Path, acl: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let mut cmd = Command::new("setfacl");
+            cmd.arg("--set-file=-").arg(path);
+            run_with_input(cmd, acl.as_bytes()).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn restore_context<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        async move {
+            let mut cmd = Command::new("restorecon");
+            cmd.arg("-F").arg(path);
+            run(cmd).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
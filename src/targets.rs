@@ -0,0 +1,111 @@
+use crate::backup::new_backup_dir;
+use crate::builder::build_tree;
+use crate::container::{Container, Runtime};
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::linker::{link_tree, LinkMode};
+use crate::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::read_to_string;
+
+/// Name of the file in the config dir which lists more places to sync to.
+///
+/// ```toml
+/// [etc]
+/// template_dir = "/home/vidde/dotfiles/etc"
+/// link_dir = "/etc"
+///
+/// [devbox]
+/// docker = "devbox"
+/// ```
+const TARGETS_FILE: &str = "targets.toml";
+
+/// Name of the directory in the state dir where each target keeps its build and state.
+const TARGETS_DIR: &str = "targets";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetSpec {
+    /// Tree to build, instead of the default one.
+    template_dir: Option<PathBuf>,
+
+    /// Directory to link into, instead of the default one.
+    link_dir: Option<PathBuf>,
+
+    /// Copy into the home directory of this docker container.
+    docker: Option<String>,
+
+    /// Copy into the home directory of this podman container.
+    podman: Option<String>,
+}
+
+pub async fn read_targets(cfg: &Config) -> Result<BTreeMap<String, TargetSpec>, Error> {
+    let path = cfg.config_dir.join(TARGETS_FILE);
+
+    debug!("trying to read {:?}", path);
+    let Ok(s) = read_to_string(&path).await else {
+        debug!("failed to read {:?}", path);
+        return Ok(BTreeMap::new());
+    };
+
+    toml::de::from_str(&s).with_location(&path)
+}
+
+/// Build and link the tree for the target `name`, with a build tree and state of its own.
+pub async fn sync_target(cfg: &Config, name: &str, spec: &TargetSpec) -> Result<(), Errors> {
+    let dir = cfg.state_dir.join(TARGETS_DIR).join(name);
+    let cfg = Config {
+        template_dir: spec
+            .template_dir
+            .clone()
+            .unwrap_or_else(|| cfg.template_dir.clone()),
+        link_dir: spec
+            .link_dir
+            .clone()
+            .unwrap_or_else(|| cfg.link_dir.clone()),
+        build_dir: dir.join("build"),
+        state_dir: dir.join("state"),
+        ..cfg.clone()
+    };
+    let cfg = Config {
+        backup_dir: match &cfg.backup_dir {
+            Some(_) => Some(new_backup_dir(&cfg).await?),
+            None => None,
+        },
+        ..cfg
+    };
+
+    let container = match (&spec.docker, &spec.podman) {
+        (Some(name), _) => Some(Container::new(Runtime::Docker, name.clone())),
+        (_, Some(name)) => Some(Container::new(Runtime::Podman, name.clone())),
+        (None, None) => None,
+    };
+
+    match container {
+        Some(container) => {
+            let home = container
+                .home()
+                .await
+                .with_location(Path::new(container.name()))?;
+            let cfg = Config {
+                link_dir: home,
+                link_mode: LinkMode::Copy,
+                ..cfg
+            };
+
+            info!("building tree for {name}");
+            build_tree(&cfg).await?;
+
+            info!("copying tree into {name}");
+            link_tree(&cfg, &container).await
+        }
+        None => {
+            info!("building tree for {name}");
+            build_tree(&cfg).await?;
+
+            info!("linking tree for {name}");
+            link_tree(&cfg, cfg.local_fs()).await
+        }
+    }
+}
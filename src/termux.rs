@@ -0,0 +1,7 @@
+use std::env;
+
+/// Detect whether we are running in Termux on Android.
+pub fn detect_termux() -> bool {
+    env::var_os("TERMUX_VERSION").is_some()
+        || env::var("PREFIX").is_ok_and(|prefix| prefix.contains("com.termux"))
+}
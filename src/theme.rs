@@ -0,0 +1,154 @@
+use crate::builder::read_variables;
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::lock::content_hash;
+use crate::process::{command_line, run};
+use crate::state::{read_fingerprints, write_fingerprints};
+use crate::Config;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Table in the variables file describing the desktop theme, which is applied after a sync
+/// whenever it changes.
+///
+/// ```toml
+/// [theme]
+/// wallpaper = "~/.local/share/wallpapers/forest.jpg"
+/// wallpaper_command = "feh --bg-fill %f" # defaults to swaybg on wayland and feh otherwise
+/// gtk = "Adwaita-dark"
+/// icons = "Papirus-Dark"
+/// kvantum = "KvArcDark"
+///
+/// [theme.gsettings."org.gnome.desktop.interface"]
+/// color-scheme = "prefer-dark"
+/// ```
+const THEME_TABLE: &str = "theme";
+
+/// Key of the theme in the fingerprints state file.
+const FINGERPRINT_KEY: &str = "[theme]";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Theme {
+    wallpaper: Option<String>,
+    wallpaper_command: Option<String>,
+    gtk: Option<String>,
+    icons: Option<String>,
+    kvantum: Option<String>,
+
+    #[serde(default)]
+    gsettings: BTreeMap<String, BTreeMap<String, toml::Value>>,
+}
+
+/// Apply the theme from the variables file, if there is one and it changed since the last sync.
+pub async fn apply_theme(cfg: &Config) -> Result<(), Errors> {
+    let Some(table) = read_variables(cfg).await?.remove(THEME_TABLE) else {
+        return Ok(());
+    };
+
+    let fingerprint = content_hash(table.to_string().as_bytes());
+
+    let mut fingerprints = read_fingerprints(cfg).await?;
+    if fingerprints.get(FINGERPRINT_KEY) == Some(&fingerprint) {
+        debug!("theme is unchanged");
+        return Ok(());
+    }
+
+    let location = &cfg.variables_path;
+    let theme: Theme = table.try_into().with_location(location)?;
+
+    let mut settings: Vec<(&str, &str, String)> = vec![];
+    if let Some(gtk) = &theme.gtk {
+        settings.push(("org.gnome.desktop.interface", "gtk-theme", gtk.clone()));
+    }
+    if let Some(icons) = &theme.icons {
+        settings.push(("org.gnome.desktop.interface", "icon-theme", icons.clone()));
+    }
+    for (schema, keys) in &theme.gsettings {
+        for (key, value) in keys {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                _ => return Err(InnerError::Type.with_location(location).into()),
+            };
+            settings.push((schema.as_str(), key.as_str(), value));
+        }
+    }
+
+    let mut errors = vec![];
+
+    for (schema, key, value) in settings {
+        let mut cmd = Command::new("gsettings");
+        cmd.args(["set", schema, key, &value]);
+
+        info!("setting {schema} {key} to {value:?}");
+        if let Err(e) = run(cmd).await {
+            errors.push(e.with_location(location));
+        }
+    }
+
+    if let Some(kvantum) = &theme.kvantum {
+        let mut cmd = Command::new("kvantummanager");
+        cmd.arg("--set").arg(kvantum);
+
+        info!("setting kvantum theme to {kvantum:?}");
+        if let Err(e) = run(cmd).await {
+            errors.push(e.with_location(location));
+        }
+    }
+
+    if let Some(wallpaper) = &theme.wallpaper {
+        let path = expand_home(wallpaper);
+        if let Err(e) = set_wallpaper(&path, theme.wallpaper_command.as_deref()).await {
+            errors.push(e);
+        }
+    }
+
+    if errors.is_empty() {
+        fingerprints.insert(FINGERPRINT_KEY.to_string(), fingerprint);
+        write_fingerprints(cfg, &fingerprints).await?;
+        Ok(())
+    } else {
+        Err(errors.into())
+    }
+}
+
+async fn set_wallpaper(path: &Path, command: Option<&str>) -> Result<(), Error> {
+    info!("setting wallpaper to {:?}", path);
+
+    if let Some(command) = command {
+        run(command_line(command, path)).await.with_location(path)?;
+        return Ok(());
+    }
+
+    if env::var_os("WAYLAND_DISPLAY").is_none() {
+        let mut cmd = Command::new("feh");
+        cmd.arg("--bg-fill").arg(path);
+        run(cmd).await.with_location(path)?;
+        return Ok(());
+    }
+
+    // swaybg keeps running to draw the wallpaper, so replace the running one
+    let mut kill = Command::new("pkill");
+    kill.args(["-x", "swaybg"]);
+    let _ = kill.status().await;
+
+    Command::new("swaybg")
+        .args(["--mode", "fill", "--image"])
+        .arg(path)
+        .spawn()
+        .with_location(path)?;
+
+    Ok(())
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
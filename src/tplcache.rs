@@ -0,0 +1,73 @@
+use crate::error::Error;
+use crate::lock::content_hash;
+use crate::state::{read_state, write_state};
+use crate::Config;
+use blueprint::{parse_template, Template};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+/// Name of the file in the state dir which caches what was learned from parsing templates.
+const TEMPLATE_CACHE_FILE: &str = "templates.toml";
+
+/// The variables used by each template, by hash of its content.
+static CACHE: Mutex<BTreeMap<String, Vec<String>>> = Mutex::new(BTreeMap::new());
+
+/// Hashes of the templates looked up by this run, the rest are dropped when saving.
+static USED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// The templates parsed by this run, by hash of their content.
+static PARSED: Mutex<BTreeMap<String, Arc<Template<'static>>>> = Mutex::new(BTreeMap::new());
+
+/// List the variables used by the template `body`, parsing it only if it hasn't been seen before.
+pub fn template_variables(body: &str) -> Result<Vec<String>, blueprint::Error> {
+    let hash = content_hash(body.as_bytes());
+    USED.lock().unwrap().insert(hash.clone());
+
+    if let Some(variables) = CACHE.lock().unwrap().get(&hash) {
+        return Ok(variables.clone());
+    }
+
+    let variables: Vec<String> = parse(hash.clone(), body)?
+        .list_variables()
+        .into_iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    CACHE.lock().unwrap().insert(hash, variables.clone());
+    Ok(variables)
+}
+
+/// The template `body`, parsing it only if this run hasn't already.
+pub fn parsed_template(body: &str) -> Result<Arc<Template<'static>>, blueprint::Error> {
+    parse(content_hash(body.as_bytes()), body)
+}
+
+fn parse(hash: String, body: &str) -> Result<Arc<Template<'static>>, blueprint::Error> {
+    if let Some(template) = PARSED.lock().unwrap().get(&hash) {
+        return Ok(Arc::clone(template));
+    }
+
+    // a template borrows from its content, which is leaked to keep the template, once per
+    // distinct content
+    let body: &'static str = Box::leak(body.into());
+    let template = Arc::new(parse_template(body)?);
+
+    PARSED.lock().unwrap().insert(hash, Arc::clone(&template));
+    Ok(template)
+}
+
+/// Load the templates parsed by earlier runs.
+pub async fn load_template_cache(cfg: &Config) -> Result<(), Error> {
+    let cached: BTreeMap<String, Vec<String>> = read_state(cfg, TEMPLATE_CACHE_FILE).await?;
+    CACHE.lock().unwrap().extend(cached);
+    Ok(())
+}
+
+/// Save the templates parsed so far for later runs.
+pub async fn save_template_cache(cfg: &Config) -> Result<(), Error> {
+    let used = USED.lock().unwrap().clone();
+    let mut cache = CACHE.lock().unwrap().clone();
+    cache.retain(|hash, _| used.contains(hash));
+
+    write_state(cfg, TEMPLATE_CACHE_FILE, &cache).await
+}
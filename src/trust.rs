@@ -0,0 +1,45 @@
+use crate::error::InnerError;
+use crate::lock::content_hash;
+use crate::state::Trusted;
+use crate::Config;
+use std::io::{stdin, IsTerminal};
+use tokio::io::{stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Check whether the hook called `name` may run `command`.
+///
+/// Hooks which are new or have changed since the user last allowed them are allowed by
+/// `--trust-all`, or else by asking the user. Allowed hooks are added to `trusted`.
+pub async fn is_trusted(
+    cfg: &Config,
+    trusted: &mut Trusted,
+    name: &str,
+    command: &str,
+) -> Result<bool, InnerError> {
+    let hash = content_hash(command.as_bytes());
+    if trusted.get(name) == Some(&hash) {
+        return Ok(true);
+    }
+
+    if !cfg.trust_all {
+        if !stdin().is_terminal() {
+            return Err(InnerError::Untrusted(command.to_string()));
+        }
+
+        let question = format!("{name} wants to run {command:?}, allow it? [y/N] ");
+        let mut out = stdout();
+        out.write_all(question.as_bytes()).await?;
+        out.flush().await?;
+
+        let mut answer = String::new();
+        BufReader::new(tokio::io::stdin())
+            .read_line(&mut answer)
+            .await?;
+
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(false);
+        }
+    }
+
+    trusted.insert(name.to_string(), hash);
+    Ok(true)
+}
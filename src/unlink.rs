@@ -0,0 +1,74 @@
+use crate::diff::list_files;
+use crate::error::{Error, ErrorLocation, Errors};
+use crate::Config;
+use std::path::Path;
+use tokio::fs::{canonicalize, read_link, remove_dir, remove_file};
+
+/// Remove the links in the link dir which point into the build tree, and the directories which
+/// are left empty.
+///
+/// Returns the number of removed links.
+pub async fn unlink_tree(cfg: &Config) -> Result<usize, Errors> {
+    let mut files = list_files(&cfg.build_dir).await?;
+    files.retain(|relative| !cfg.skip(relative));
+
+    let build_dir = canonicalize(&cfg.build_dir)
+        .await
+        .with_location(&cfg.build_dir)?;
+
+    let mut removed = 0;
+    let mut errors = vec![];
+
+    for relative in files {
+        let link_path = cfg.link_path(&relative);
+        match unlink(cfg, &build_dir, &link_path).await {
+            Ok(true) => removed += 1,
+            Ok(false) => {}
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(removed)
+    } else {
+        Err(errors.into())
+    }
+}
+
+/// Remove the link at `link_path` if it points into `build_dir`, and then its parent directories
+/// while they are empty.
+async fn unlink(cfg: &Config, build_dir: &Path, link_path: &Path) -> Result<bool, Error> {
+    // anything else at the path isn't ours to remove
+    if read_link(link_path).await.is_err() {
+        return Ok(false);
+    }
+
+    let points_into_build = canonicalize(link_path)
+        .await
+        .is_ok_and(|original| original.starts_with(build_dir));
+
+    if !points_into_build {
+        trace!("{link_path:?} doesn't point into the build tree, leaving it");
+        return Ok(false);
+    }
+
+    debug!("removing link {link_path:?}");
+    remove_file(link_path).await.with_location(link_path)?;
+
+    let mut dir = link_path.parent();
+    while let Some(path) = dir {
+        if path == cfg.link_dir || !path.starts_with(&cfg.link_dir) {
+            break;
+        }
+
+        // fails when the directory isn't empty
+        if remove_dir(path).await.is_err() {
+            break;
+        }
+
+        debug!("removed empty directory {path:?}");
+        dir = path.parent();
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// How a template uses a variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Usage {
+    /// As a condition, e.g. `{% if work %}`.
+    Bool,
+
+    /// Interpolated into the output, e.g. `{{ font_size }}`, or compared to a string.
+    String,
+
+    /// Iterated over, e.g. `{% for host in hosts %}`.
+    List,
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Usage::Bool => write!(f, "boolean"),
+            Usage::String => write!(f, "string"),
+            Usage::List => write!(f, "list"),
+        }
+    }
+}
+
+impl Usage {
+    /// Whether a value from the variables file fits this usage.
+    pub fn accepts(&self, value: &toml::Value) -> bool {
+        match self {
+            Usage::Bool => value.is_bool(),
+            Usage::String => !value.is_array() && !value.is_table(),
+            Usage::List => value.is_array(),
+        }
+    }
+}
+
+/// Infer how the template `body` uses each of `variables`, from the tags it's made of.
+///
+/// This only looks at the text of the tags, so names which aren't in `variables`, such as loop
+/// variables, are ignored.
+pub fn infer_usage(body: &str, variables: &[String]) -> Vec<(String, Usage)> {
+    let mut usages = BTreeSet::new();
+    let mut record = |name: &str, usage| {
+        if variables.iter().any(|var| var == name) {
+            usages.insert((name.to_string(), usage));
+        }
+    };
+
+    let mut rest = body;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start..];
+
+        let (close, is_block) = if rest.starts_with("{{") {
+            ("}}", false)
+        } else if rest.starts_with("{%") {
+            ("%}", true)
+        } else {
+            rest = &rest[1..];
+            continue;
+        };
+
+        let Some(end) = rest.find(close) else {
+            break;
+        };
+        let tag = rest[2..end].trim_matches(['-', ' ', '\t', '\n']);
+        rest = &rest[end + 2..];
+
+        let words = tokens(tag);
+        if !is_block {
+            if let Some(name) = words.first() {
+                record(name, Usage::String);
+            }
+            continue;
+        }
+
+        match words.as_slice() {
+            ["if" | "elif", condition @ ..] => {
+                for (i, word) in condition.iter().enumerate() {
+                    let compared = matches!(condition.get(i + 1), Some(&"==" | &"!="))
+                        || (i > 0 && matches!(condition[i - 1], "==" | "!="));
+                    let usage = if compared { Usage::String } else { Usage::Bool };
+                    record(word, usage);
+                }
+            }
+            ["for", .., "in", list] => record(list, Usage::List),
+            _ => {}
+        }
+    }
+
+    usages.into_iter().collect()
+}
+
+/// Split a tag into names and operators, dropping string literals and keywords.
+fn tokens(tag: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut rest = tag;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || "()!".contains(c));
+        let Some(c) = rest.chars().next() else {
+            break;
+        };
+
+        let len = if c == '"' || c == '\'' {
+            let len = rest[1..].find(c).map(|i| i + 2).unwrap_or(rest.len());
+            rest = &rest[len..];
+            continue;
+        } else if c.is_alphanumeric() || c == '_' {
+            rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len())
+        } else {
+            rest.find(|c: char| c.is_alphanumeric() || c.is_whitespace() || "_()'\"".contains(c))
+                .unwrap_or(rest.len())
+        };
+
+        let token = &rest[..len];
+        rest = &rest[len..];
+        if !matches!(token, "not" | "and" | "or" | "true" | "false") {
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
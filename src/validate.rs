@@ -0,0 +1,52 @@
+use crate::error::{Error, ErrorLocation, InnerError};
+use crate::glob::{read_command_rules, GlobRules};
+use crate::process::command_line;
+use crate::Config;
+use std::path::Path;
+
+/// Name of the file in the config dir which maps globs in the tree to validation commands.
+///
+/// ```toml
+/// ".config/sway/config" = "sway -C -c %f"
+/// ".config/tmux/tmux.conf" = "tmux -f %f list-keys"
+/// "**/*.json" = "jq . %f"
+/// ```
+///
+/// `%f` is replaced with the path of the built file.
+pub const VALIDATORS_FILE: &str = "validators.toml";
+
+#[derive(Clone, Debug, Default)]
+pub struct Validators {
+    rules: GlobRules<String>,
+}
+
+impl Validators {
+    /// Run the validator matching the file at `relative` in the tree, if any, against the built
+    /// file at `build_path`.
+    pub async fn validate(&self, relative: &Path, build_path: &Path) -> Result<(), Error> {
+        let Some(command) = self.rules.find(relative) else {
+            return Ok(());
+        };
+
+        debug!("validating {:?} with {:?}", build_path, command);
+        let out = command_line(command, build_path)
+            .output()
+            .await
+            .with_location(build_path)?;
+
+        if !out.status.success() {
+            let mut output = String::from_utf8_lossy(&out.stdout).into_owned();
+            output.push_str(&String::from_utf8_lossy(&out.stderr));
+            return Err(InnerError::Validation(output.trim().to_string()).with_location(build_path));
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn read_validators(cfg: &Config) -> Result<Validators, Error> {
+    let path = cfg.config_dir.join(VALIDATORS_FILE);
+    Ok(Validators {
+        rules: read_command_rules(&path).await?,
+    })
+}
@@ -0,0 +1,75 @@
+use crate::error::{Error, ErrorLocation, Errors, InnerError};
+use crate::Config;
+use async_recursion::async_recursion;
+use futures::future::join_all;
+use std::fs::Metadata;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::fs::read_dir;
+
+/// Check that the files in the build tree have the modes required by the permission rules.
+pub async fn verify_tree(cfg: &Config) -> Result<(), Errors> {
+    dir(cfg, PathBuf::new()).await
+}
+
+#[async_recursion]
+async fn dir(cfg: &Config, relative: PathBuf) -> Result<(), Errors> {
+    let build_path = cfg.build_dir.join(&relative);
+
+    info!("traversing {:?}", build_path);
+
+    let meta = tokio::fs::metadata(&build_path)
+        .await
+        .with_location(&build_path)?;
+    check_mode(&build_path, &meta, cfg.permissions.dir_mode(&relative))?;
+
+    let mut walker = read_dir(&build_path).await.with_location(&build_path)?;
+
+    let mut dir_tasks = vec![];
+    let mut errors = vec![];
+
+    while let Some(entry) = walker.next_entry().await.with_location(&build_path)? {
+        let meta = entry.metadata().await.with_location(&entry.path())?;
+        let new_relative = relative.join(entry.file_name());
+
+        if cfg.skip(&new_relative) {
+            continue;
+        }
+
+        if meta.is_dir() {
+            dir_tasks.push(dir(cfg, new_relative));
+        } else if meta.is_file() {
+            let expected = cfg.permissions.file_mode(&new_relative);
+            if let Err(error) = check_mode(&entry.path(), &meta, expected) {
+                errors.push(error);
+            }
+        }
+    }
+
+    let mut errors: Errors = errors.into();
+
+    for error in join_all(dir_tasks)
+        .await
+        .into_iter()
+        .filter_map(|r| r.err())
+    {
+        errors.join(error);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn check_mode(path: &Path, meta: &Metadata, expected: Option<u32>) -> Result<(), Error> {
+    let actual = meta.permissions().mode() & 0o7777;
+
+    match expected {
+        Some(expected) if expected != actual => {
+            Err(InnerError::WrongMode { actual, expected }.with_location(path))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,67 @@
+use crate::error::{ErrorLocation, Errors, InnerError};
+use crate::redact::redact;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Warnings recorded so far by this run.
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+/// Things that didn't stop the run, but that the user should know about, such as fallbacks that
+/// were taken or files that were skipped.
+#[derive(Default)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+}
+
+struct Warning {
+    location: PathBuf,
+    message: String,
+}
+
+/// Record a warning about `location`, to be reported at the end of the run.
+pub fn warning(location: &Path, message: impl Into<String>) {
+    let message = message.into();
+    debug!("warning at {location:?}: {message}");
+
+    WARNINGS.lock().unwrap().push(Warning {
+        location: location.to_owned(),
+        message,
+    });
+}
+
+/// Take the warnings recorded so far.
+pub fn take_warnings() -> Warnings {
+    Warnings {
+        warnings: std::mem::take(&mut *WARNINGS.lock().unwrap()),
+    }
+}
+
+impl Warnings {
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    pub fn log(mut self) {
+        if self.warnings.is_empty() {
+            return;
+        }
+
+        // they are recorded in whatever order the files were visited
+        self.warnings
+            .sort_by(|a, b| (&a.location, &a.message).cmp(&(&b.location, &b.message)));
+
+        warn!("{} warnings:", self.warnings.len());
+        for warning in &self.warnings {
+            warn!("  at {:?}: {}", warning.location, redact(&warning.message));
+        }
+    }
+
+    /// Turn the warnings into errors, for `--deny-warnings`.
+    pub fn into_errors(self) -> Errors {
+        self.warnings
+            .into_iter()
+            .map(|warning| InnerError::Warning(warning.message).with_location(&warning.location))
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
@@ -0,0 +1,37 @@
+use crate::error::InnerError;
+use std::path::Path;
+
+/// Names which refer to devices on Windows, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest path most Windows programs can open, unless long paths are enabled.
+const MAX_PATH: usize = 260;
+
+/// Check that `path` can be created on Windows.
+pub fn check_windows_path(path: &Path) -> Result<(), InnerError> {
+    for component in path.iter() {
+        let name = component.to_string_lossy();
+        let stem = name.split('.').next().unwrap_or_default();
+
+        if RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        {
+            return Err(InnerError::ReservedName(name.into_owned()));
+        }
+
+        if name.ends_with('.') || name.ends_with(' ') {
+            return Err(InnerError::ReservedName(name.into_owned()));
+        }
+    }
+
+    let length = path.as_os_str().len();
+    if length >= MAX_PATH {
+        return Err(InnerError::PathTooLong(length));
+    }
+
+    Ok(())
+}
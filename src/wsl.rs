@@ -0,0 +1,57 @@
+use crate::process::run;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs::read_to_string;
+use tokio::process::Command;
+
+/// Logical directory at the root of the tree which is linked into the Windows home directory when
+/// running under WSL, and left out everywhere else.
+pub const WINDOWS_HOME_DIR: &str = "WINHOME";
+
+/// The Windows side of a WSL machine.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Wsl {
+    /// Name of the Windows user.
+    pub user: String,
+
+    /// Home directory of the Windows user, as seen from WSL, e.g. `/mnt/c/Users/vidde`.
+    pub home: PathBuf,
+}
+
+/// Detect whether we are running under WSL, and find the Windows user if so.
+pub async fn detect_wsl() -> Option<Wsl> {
+    let version = read_to_string("/proc/version").await.ok()?;
+    if !version.to_lowercase().contains("microsoft") {
+        return None;
+    }
+
+    debug!("running under WSL");
+
+    let user = windows_env("USERNAME").await?;
+    let profile = windows_env("USERPROFILE").await?;
+
+    let mut cmd = Command::new("wslpath");
+    cmd.arg("-u").arg(&profile);
+    let home = match run(cmd).await {
+        Ok(home) => PathBuf::from(home.trim()),
+        Err(_) => Path::new("/mnt/c/Users").join(&user),
+    };
+
+    Some(Wsl { user, home })
+}
+
+/// Read an environment variable on the Windows side.
+async fn windows_env(name: &str) -> Option<String> {
+    let mut cmd = Command::new("cmd.exe");
+    cmd.args(["/C", &format!("echo %{name}%")]);
+
+    let value = run(cmd).await.ok()?;
+    let value = value.trim();
+
+    // unset variables are echoed as is
+    if value.is_empty() || value == format!("%{name}%") {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}